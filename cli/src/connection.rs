@@ -0,0 +1,141 @@
+use std::{fs::File, io::BufReader, path::{Path, PathBuf}, sync::Arc};
+use clap::Args;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::{
+    ClientConfig, RootCertStore,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer}
+};
+use client::Client;
+use crate::CliError;
+
+/// Any stream the client can be connected on: TCP, TLS or a Unix domain socket.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// A client connected on any stream.
+pub type Connected = Client<Box<dyn Connection>>;
+
+/// How to reach and authenticate to the server.
+#[derive(Args, Debug)]
+pub struct ConnectionArgs {
+    /// Address of the server: `host:port`, or `unix:<path>` for a Unix domain socket
+    #[arg(short, long, env = "NFTP_SERVER", default_value = "127.0.0.1:3000")]
+    pub server: String,
+
+    /// User to log in with; the password is read from NFTP_PASSWORD
+    #[arg(short, long, env = "NFTP_USER")]
+    pub user: Option<String>,
+
+    /// Pre-shared API token to log in with, used when no user is given
+    #[arg(long, env = "NFTP_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// Connect through TLS, trusting the certificates of this PEM file
+    #[arg(long, env = "NFTP_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+
+    /// Name the server certificate is verified for (default: the host of the address)
+    #[arg(long, requires = "tls_ca")]
+    pub tls_name: Option<String>,
+
+    /// Client certificate (PEM) for servers that authenticate the clients through TLS
+    #[arg(long, requires_all = ["tls_ca", "tls_key"])]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM) of the client certificate
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>
+}
+
+
+/// Connect to the server and log in with the credentials of the arguments.
+/// Without credentials, the session is not authenticated: only local peers
+/// trusted by their credentials on a Unix socket can use it.
+pub async fn connect(args: &ConnectionArgs) -> Result<Connected, CliError> {
+    let stream: Box<dyn Connection> = match (args.server.strip_prefix("unix:"), &args.tls_ca) {
+        (Some(path), _) => Box::new(Client::connect_unix(path).await?.into_inner()),
+        (None, None) => Box::new(Client::connect(&args.server).await?.into_inner()),
+        (None, Some(tls_ca)) => {
+            let server_name = match &args.tls_name {
+                Some(tls_name) => tls_name.clone(),
+                None => host(&args.server).to_string()
+            };
+            let tls_config = tls_config(tls_ca, args.tls_cert.as_deref().zip(args.tls_key.as_deref()))?;
+            Box::new(Client::connect_tls(&args.server, &server_name, tls_config).await?.into_inner())
+        }
+    };
+    let mut client = Client::new(stream);
+
+    match (&args.user, &args.token) {
+        (Some(user), _) => {
+            let password = std::env::var("NFTP_PASSWORD")
+                .map_err(|_| CliError::Local("the password of the user must be in NFTP_PASSWORD".to_string()))?;
+            client.login(user, password.as_bytes()).await?;
+        },
+        (None, Some(token)) => client.login_with_token(token.as_bytes()).await?,
+        (None, None) => ()
+    };
+    Ok(client)
+}
+
+
+/// Return the host of a `host:port` address, without the brackets of IPv6.
+fn host(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+
+/// Build the TLS configuration trusting the CA file, with the optional client certificate and key.
+fn tls_config(tls_ca: &Path, client_auth: Option<(&Path, &Path)>) -> Result<Arc<ClientConfig>, CliError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(tls_ca)? {
+        roots.add(cert).map_err(|e| CliError::Local(format!("{}: {}", tls_ca.display(), e)))?;
+    }
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| CliError::Local(e.to_string()))?
+        .with_root_certificates(roots);
+    let tls_config = match client_auth {
+        Some((cert, key)) => builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(|e| CliError::Local(e.to_string()))?,
+        None => builder.with_no_client_auth()
+    };
+    Ok(Arc::new(tls_config))
+}
+
+
+/// Read all the certificates of a PEM file.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, CliError> {
+    let file = File::open(path).map_err(|e| CliError::Local(format!("{}: {}", path.display(), e)))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| CliError::Local(format!("{}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(CliError::Local(format!("{}: no certificates found", path.display())));
+    }
+    Ok(certs)
+}
+
+
+/// Read the first private key of a PEM file.
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, CliError> {
+    let file = File::open(path).map_err(|e| CliError::Local(format!("{}: {}", path.display(), e)))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| CliError::Local(format!("{}: {}", path.display(), e)))?
+        .ok_or(CliError::Local(format!("{}: no private key found", path.display())))
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn host_should_strip_the_port() {
+        assert_eq!(host("example.com:3000"), "example.com");
+        assert_eq!(host("[::1]:3443"), "::1");
+        assert_eq!(host("localhost"), "localhost");
+    }
+}
@@ -0,0 +1,422 @@
+mod connection;
+mod output;
+mod shell;
+mod sync;
+
+use std::{fmt, path::{Path, PathBuf}, process::ExitCode};
+use clap::{Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::{json, Value};
+use client::protocol::{response::{RC_ERROR, RC_QUOTA_EXCEEDED}, search::{Pattern, Query}, watch::Event};
+use connection::{ConnectionArgs, Connected};
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0   success
+  1   local error: files, arguments, TLS configuration
+  2   invalid command line
+  3   connection error
+  4   invalid response from the server
+  10  GENERIC ERROR (response code 100)
+  11  AUTHENTICATION FAILED (101)
+  12  LOCKED OUT (102)
+  13  PERMISSION DENIED (103)
+  14  SERVER BUSY (104)
+  15  TIMEOUT (105)
+  16  QUOTA EXCEEDED (106)
+  19  unknown response code";
+
+/// Command-line client of the nFTP protocol.
+#[derive(Parser, Debug)]
+#[command(name = "nftp", version, after_help = EXIT_CODES)]
+struct Cli {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    /// Print the result, or the error, as a JSON object on stdout
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Don't show the progress bars of the transfers
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the user space as a tree, or only the directory at the path
+    Ls { path: Option<String> },
+    /// Download a file (default local path: its name in the current directory)
+    Get {
+        remote: String,
+        local: Option<PathBuf>,
+        /// If the local file exists, receive only the blocks that changed
+        #[arg(long)]
+        delta: bool
+    },
+    /// Upload a file, replacing the remote one (default remote path: its name in the root)
+    Put {
+        local: PathBuf,
+        remote: Option<String>,
+        /// If the remote file exists, send only the blocks that changed
+        #[arg(long)]
+        delta: bool
+    },
+    /// Remove a file or an empty directory
+    Rm { path: String },
+    /// Create a directory
+    Mkdir { path: String },
+    /// Move or rename a file or a directory
+    Mv { source: String, destination: String },
+    /// Show the kind, the dimension and the last modification of a path
+    Stat { path: String },
+    /// Find the entries under a directory whose name matches a glob (`*.pdf`), or whose
+    /// relative path does if it contains a `/` (`docs/**/*.pdf`)
+    Search {
+        pattern: String,
+        /// Directory to search in
+        #[arg(default_value = "/")]
+        path: String,
+        /// The pattern is a regular expression, searched in the whole path
+        #[arg(long)]
+        regex: bool,
+        /// Only files at least this big, in bytes or with a K, M, G suffix
+        #[arg(long, value_parser = parse_size)]
+        min_size: Option<u64>,
+        /// Only files at most this big, in bytes or with a K, M, G suffix
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Only entries modified after this time: RFC 3339 or seconds since the Unix epoch
+        #[arg(long, value_parser = parse_time)]
+        modified_after: Option<u64>,
+        /// Only entries modified before this time: RFC 3339 or seconds since the Unix epoch
+        #[arg(long, value_parser = parse_time)]
+        modified_before: Option<u64>
+    },
+    /// Show the space used by paths on the server: dimension in bytes, number of files
+    /// and path, one per line
+    Du {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Also show the space used by each immediate child
+        #[arg(long)]
+        children: bool
+    },
+    /// Show the total and available space on the server and the remaining quotas
+    Df,
+    /// Print the changes under a path as they happen, one per line, until interrupted
+    Watch { path: String },
+    /// Mirror a directory: `sync remote:/dir ./local` downloads, `sync ./local remote:/dir` uploads
+    Sync(sync::SyncArgs),
+    /// Open an interactive shell on one connection, type `help` for its commands
+    Shell
+}
+
+
+/// Errors of the command line client.
+#[derive(Debug)]
+pub enum CliError {
+    /// The connection or the server failed the request.
+    Client(client::Error),
+    /// A problem found before talking to the server.
+    Local(String)
+}
+impl CliError {
+    /// Return the exit code of the error, see `EXIT_CODES`.
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Local(_) | CliError::Client(client::Error::Encode(_)) => 1,
+            CliError::Client(client::Error::Io(_)) => 3,
+            CliError::Client(client::Error::InvalidResponse(_)) => 4,
+            CliError::Client(e) => match e.response_code() {
+                Some(response_code @ RC_ERROR..=RC_QUOTA_EXCEEDED) => 10 + (response_code - RC_ERROR),
+                _ => 19
+            }
+        }
+    }
+}
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Client(e) => write!(f, "{}", e),
+            CliError::Local(e) => write!(f, "{}", e)
+        }
+    }
+}
+impl From<client::Error> for CliError {
+    fn from(e: client::Error) -> Self {
+        CliError::Client(e)
+    }
+}
+
+
+/// Result of a command: the text for humans and the JSON object for scripts.
+struct Output {
+    text: String,
+    json: Value
+}
+
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli).await {
+        Ok(output) => {
+            if cli.json && !output.json.is_null() {
+                println!("{}", output.json);
+            } else {
+                print!("{}", output.text);
+            }
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            if cli.json {
+                let response_code = match &e {
+                    CliError::Client(e) => e.response_code(),
+                    CliError::Local(_) => None
+                };
+                println!("{}", json!({ "error": e.to_string(), "response_code": response_code }));
+            } else {
+                eprintln!("nftp: {}", e);
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+
+async fn run(cli: &Cli) -> Result<Output, CliError> {
+    if let Command::Shell = &cli.command {
+        shell::run(&cli.connection, cli.quiet).await?;
+        return Ok(Output { text: String::new(), json: Value::Null });
+    }
+    if let Command::Put { local, .. } = &cli.command {
+        if !local.is_file() {
+            return Err(CliError::Local(format!("{}: not a file", local.display())));
+        }
+    }
+    let mut client = connection::connect(&cli.connection).await?;
+
+    match &cli.command {
+        Command::Ls { path } => {
+            let root = client.list().await?;
+            let entry = match path {
+                Some(path) => root.find(path).ok_or(CliError::Local(format!("{}: no such path", path)))?,
+                None => &root
+            };
+            Ok(Output { text: output::render_tree(entry), json: output::tree_json(entry) })
+        },
+        Command::Get { remote, local, delta } => {
+            let (local, bytes) = download(&mut client, remote, local.as_deref(), cli.quiet || cli.json, *delta).await?;
+            Ok(Output {
+                text: String::new(),
+                json: json!({ "remote": remote, "local": local, "bytes": bytes })
+            })
+        },
+        Command::Put { local, remote, delta } => {
+            let remote = match remote {
+                Some(remote) => remote.clone(),
+                None => file_name(&local.to_string_lossy())?.to_string()
+            };
+            let bytes = upload(&mut client, local, &remote, cli.quiet || cli.json, *delta).await?;
+            Ok(Output {
+                text: String::new(),
+                json: json!({ "local": local, "remote": remote, "bytes": bytes })
+            })
+        },
+        Command::Rm { path } => {
+            client.rm(path).await?;
+            Ok(Output { text: String::new(), json: json!({ "removed": path }) })
+        },
+        Command::Mkdir { path } => {
+            client.mkdir(path).await?;
+            Ok(Output { text: String::new(), json: json!({ "created": path }) })
+        },
+        Command::Mv { source, destination } => {
+            client.mv(source, destination).await?;
+            Ok(Output { text: String::new(), json: json!({ "source": source, "destination": destination }) })
+        },
+        Command::Stat { path } => {
+            let stat = client.stat(path).await?;
+            Ok(Output { text: output::render_stat(path, &stat), json: output::stat_json(path, &stat) })
+        },
+        Command::Search { pattern, path, regex, min_size, max_size, modified_after, modified_before } => {
+            let pattern = if *regex { Pattern::Regex(pattern.clone()) } else { Pattern::Glob(pattern.clone()) };
+            let query = Query {
+                pattern,
+                min_size: *min_size,
+                max_size: *max_size,
+                modified_after: *modified_after,
+                modified_before: *modified_before
+            };
+            let matches = client.search(path, &query).await?.collect().await?;
+            let text = matches.iter()
+                .map(|found| format!("{}{}\n", found.path, if found.stat.is_dir { "/" } else { "" }))
+                .collect();
+            let json = matches.iter().map(|found| output::stat_json(&found.path, &found.stat)).collect::<Vec<_>>();
+            Ok(Output { text, json: json!({ "matches": json }) })
+        },
+        Command::Du { paths, children } => {
+            let usages = client.du(&paths.iter().map(String::as_str).collect::<Vec<_>>(), *children).await?;
+            Ok(Output {
+                text: paths.iter().zip(&usages).map(|(path, usage)| output::render_usage(path, usage)).collect(),
+                json: json!({ "usages": paths.iter().zip(&usages).map(|(path, usage)| output::usage_json(path, usage)).collect::<Vec<_>>() })
+            })
+        },
+        Command::Df => {
+            let space = client.space().await?;
+            Ok(Output { text: output::render_space(&space), json: output::space_json(&space) })
+        },
+        Command::Watch { path } => {
+            let mut watch = client.watch(path).await?;
+            loop {
+                let event = watch.next().await?;
+                if cli.json {
+                    println!("{}", output::event_json(&event));
+                } else {
+                    print!("{}", output::render_event(&event));
+                }
+                if event == Event::End {
+                    return Ok(Output { text: String::new(), json: Value::Null });
+                }
+            }
+        },
+        Command::Sync(sync_args) => sync::run(&mut client, sync_args, cli.quiet || cli.json).await,
+        Command::Shell => unreachable!("the shell opens its own connection")
+    }
+}
+
+
+/// Download a remote file with a progress bar, by default into the current directory
+/// with the same name. With `delta`, an existing local file is updated receiving only
+/// the blocks that changed. Return the local path and the bytes received.
+async fn download(client: &mut Connected, remote: &str, local: Option<&Path>, quiet: bool, delta: bool) -> Result<(PathBuf, u64), CliError> {
+    let local = match local {
+        Some(local) => local.to_path_buf(),
+        None => PathBuf::from(file_name(remote)?)
+    };
+    if delta && local.is_file() {
+        let bytes = client.get_delta(remote, &local).await?;
+        return Ok((local, bytes));
+    }
+    let progress = progress_bar(quiet, remote);
+    let bytes = client.get_to_file_with_progress(remote, &local, |done, total| {
+        progress.set_length(total);
+        progress.set_position(done);
+    }).await;
+    progress.finish_and_clear();
+    Ok((local, bytes?))
+}
+
+
+/// Upload a local file with a progress bar, returning the bytes sent. With `delta`,
+/// an existing remote file is updated sending only the blocks that changed.
+async fn upload(client: &mut Connected, local: &Path, remote: &str, quiet: bool, delta: bool) -> Result<u64, CliError> {
+    if delta {
+        match client.stat(remote).await {
+            Ok(stat) if !stat.is_dir => return Ok(client.put_delta(remote, local).await?),
+            // a missing file, or not a file: the full upload fails or creates it
+            Err(e) if e.response_code().is_none() => return Err(e.into()),
+            _ => ()
+        };
+    }
+    let progress = progress_bar(quiet, remote);
+    let bytes = client.put_from_file_with_progress(remote, local, |done, total| {
+        progress.set_length(total);
+        progress.set_position(done);
+    }).await;
+    progress.finish_and_clear();
+    Ok(bytes?)
+}
+
+
+/// Return the last component of a path, the default name of a transferred file.
+fn file_name(path: &str) -> Result<&str, CliError> {
+    Path::new(path).file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(CliError::Local(format!("{}: not a file path", path)))
+}
+
+
+/// Parse a dimension in bytes, with an optional K, M or G suffix (powers of 1024).
+fn parse_size(arg: &str) -> Result<u64, String> {
+    let (number, shift) = match arg.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&arg[..i], 10),
+        Some((i, 'm' | 'M')) => (&arg[..i], 20),
+        Some((i, 'g' | 'G')) => (&arg[..i], 30),
+        _ => (arg, 0)
+    };
+    number.parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or(format!("`{}` is not a dimension", arg))
+}
+
+
+/// Parse a time, RFC 3339 or seconds since the Unix epoch, into seconds since the Unix epoch.
+fn parse_time(arg: &str) -> Result<u64, String> {
+    if let Ok(seconds) = arg.parse::<u64>() {
+        return Ok(seconds);
+    }
+    time::OffsetDateTime::parse(arg, &time::format_description::well_known::Rfc3339).ok()
+        .and_then(|time| u64::try_from(time.unix_timestamp()).ok())
+        .ok_or(format!("`{}` is not an RFC 3339 time or seconds since the Unix epoch", arg))
+}
+
+
+/// Return the progress bar of a transfer on stderr, unless `quiet`
+/// (indicatif hides it also when stderr is not a terminal).
+fn progress_bar(quiet: bool, name: &str) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} {bytes_per_sec} {eta}")
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+    ProgressBar::new(0).with_style(style).with_message(name.to_string())
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use clap::CommandFactory;
+    use client::protocol::response::*;
+    use super::*;
+
+    #[test]
+    fn cli_should_be_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn exit_codes_should_follow_the_response_codes() {
+        let exit_code = |response_code| CliError::Client(client::Error::from_response_code(response_code)).exit_code();
+
+        assert_eq!(exit_code(RC_ERROR), 10);
+        assert_eq!(exit_code(RC_PERMISSION_DENIED), 13);
+        assert_eq!(exit_code(RC_TIMEOUT), 15);
+        assert_eq!(exit_code(RC_QUOTA_EXCEEDED), 16);
+        assert_eq!(exit_code(200), 19);
+        assert_eq!(CliError::Client(client::Error::Io(std::io::ErrorKind::ConnectionRefused.into())).exit_code(), 3);
+        assert_eq!(CliError::Local("no such path".to_string()).exit_code(), 1);
+    }
+
+    #[test]
+    fn search_filters_should_parse_sizes_and_times() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("2g"), Ok(2 << 30));
+        assert!(parse_size("M").is_err());
+        assert_eq!(parse_time("1700000000"), Ok(1_700_000_000));
+        assert_eq!(parse_time("2023-11-14T22:13:20Z"), Ok(1_700_000_000));
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn default_names_should_be_the_last_component() {
+        assert_eq!(file_name("docs/a.txt").unwrap(), "a.txt");
+        assert!(file_name("/").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use serde_json::{json, Value};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use client::protocol::{du::PathUsage, space::Space, stat::Stat, tree::Entry, watch::Event};
+
+/// Render a directory as an indented tree, one entry per line, directories
+/// with a trailing `/`.
+///
+/// # Examples
+/// ```text
+/// docs/
+/// ├── a.txt
+/// └── old/
+///     └── b.txt
+/// ```
+pub fn render_tree(root: &Entry) -> String {
+    let mut out = format!("{}\n", display_name(root));
+    if let Entry::Dir { entries, .. } = root {
+        render_entries(entries, "", &mut out);
+    }
+    out
+}
+
+fn render_entries(entries: &[Entry], prefix: &str, out: &mut String) {
+    for (i, entry) in entries.iter().enumerate() {
+        let last = i + 1 == entries.len();
+        out.push_str(prefix);
+        out.push_str(if last { "└── " } else { "├── " });
+        out.push_str(&display_name(entry));
+        out.push('\n');
+        if let Entry::Dir { entries, .. } = entry {
+            render_entries(entries, &format!("{}{}", prefix, if last { "    " } else { "│   " }), out);
+        }
+    }
+}
+
+fn display_name(entry: &Entry) -> String {
+    match entry {
+        Entry::Dir { name, .. } if !name.ends_with('/') => format!("{}/", name),
+        entry => entry.name().to_string()
+    }
+}
+
+
+/// Return the tree as JSON: every entry has `name` and `type` (`file` or `dir`),
+/// directories also their `entries`.
+pub fn tree_json(entry: &Entry) -> Value {
+    match entry {
+        Entry::File(name) => json!({ "name": name, "type": "file" }),
+        Entry::Dir { name, entries } => json!({
+            "name": name,
+            "type": "dir",
+            "entries": entries.iter().map(tree_json).collect::<Vec<_>>()
+        })
+    }
+}
+
+
+/// Return the metadata of the path as JSON, with the modification time in seconds
+/// since the Unix epoch.
+pub fn stat_json(path: &str, stat: &Stat) -> Value {
+    json!({
+        "path": path,
+        "type": if stat.is_dir { "dir" } else { "file" },
+        "size": stat.size,
+        "modified": stat.modified
+    })
+}
+
+
+/// Render the metadata of the path, one field per line.
+pub fn render_stat(path: &str, stat: &Stat) -> String {
+    let modified = i64::try_from(stat.modified).ok()
+        .and_then(|modified| OffsetDateTime::from_unix_timestamp(modified).ok())
+        .and_then(|modified| modified.format(&Rfc3339).ok())
+        .unwrap_or_else(|| stat.modified.to_string());
+    format!(
+        "path: {}\ntype: {}\nsize: {}\nmodified: {}\n",
+        path,
+        if stat.is_dir { "directory" } else { "file" },
+        stat.size,
+        modified
+    )
+}
+
+
+/// Render the usage of the path like `du`: dimension in bytes, number of files and
+/// path, tab separated, then the same for each child.
+pub fn render_usage(path: &str, usage: &PathUsage) -> String {
+    let mut out = String::new();
+    for (name, child) in &usage.children {
+        out.push_str(&format!("{}\t{}\t{}/{}\n", child.size, child.files, path.trim_end_matches('/'), name));
+    }
+    out.push_str(&format!("{}\t{}\t{}\n", usage.total.size, usage.total.files, path));
+    out
+}
+
+/// Return the usage of the path as JSON: `path`, `size`, `files` and, if requested,
+/// the `children` with their `name`, `size` and `files`.
+pub fn usage_json(path: &str, usage: &PathUsage) -> Value {
+    let mut json = json!({ "path": path, "size": usage.total.size, "files": usage.total.files });
+    if !usage.children.is_empty() {
+        json["children"] = usage.children.iter()
+            .map(|(name, child)| json!({ "name": name, "size": child.size, "files": child.files }))
+            .collect();
+    }
+    json
+}
+
+
+/// Render the capacity of the server, one field per line; the quotas only if configured.
+pub fn render_space(space: &Space) -> String {
+    let mut out = format!("total: {}\navailable: {}\n", space.total, space.available);
+    if let Some(remaining) = space.remaining_bytes {
+        out.push_str(&format!("remaining bytes: {}\n", remaining));
+    }
+    if let Some(remaining) = space.remaining_files {
+        out.push_str(&format!("remaining files: {}\n", remaining));
+    }
+    out
+}
+
+/// Return the capacity of the server as JSON, with `null` for a quota not configured.
+pub fn space_json(space: &Space) -> Value {
+    json!({
+        "total": space.total,
+        "available": space.available,
+        "remaining_bytes": space.remaining_bytes,
+        "remaining_files": space.remaining_files
+    })
+}
+
+
+/// Return the name of the event and its paths.
+fn event_parts(event: &Event) -> (&'static str, Vec<&str>) {
+    match event {
+        Event::Create(path) => ("create", vec![path]),
+        Event::Modify(path) => ("modify", vec![path]),
+        Event::Delete(path) => ("delete", vec![path]),
+        Event::Rename { from, to } => ("rename", vec![from, to]),
+        Event::Overflow => ("overflow", vec![]),
+        Event::End => ("end", vec![])
+    }
+}
+
+/// Render an event of a watch on one line: its name and its paths, `->` between
+/// the two paths of a rename.
+pub fn render_event(event: &Event) -> String {
+    let (name, paths) = event_parts(event);
+    match paths.as_slice() {
+        [] => format!("{}\n", name),
+        paths => format!("{} {}\n", name, paths.join(" -> "))
+    }
+}
+
+/// Return an event of a watch as JSON: `event` and `path`, or `from` and `to` for a rename.
+pub fn event_json(event: &Event) -> Value {
+    match event {
+        Event::Rename { from, to } => json!({ "event": "rename", "from": from, "to": to }),
+        event => {
+            let (name, paths) = event_parts(event);
+            match paths.first() {
+                Some(path) => json!({ "event": name, "path": path }),
+                None => json!({ "event": name })
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use client::protocol::tree::parse_tree;
+    use super::*;
+
+    #[test]
+    fn render_tree_should_draw_the_branches() {
+        let root = parse_tree("/{docs{a.txt,old{b.txt,}}c.txt,}").unwrap();
+
+        assert_eq!(render_tree(&root), "\
+/
+├── docs/
+│   ├── a.txt
+│   └── old/
+│       └── b.txt
+└── c.txt
+");
+    }
+
+    #[test]
+    fn tree_json_should_nest_the_entries() {
+        let root = parse_tree("root{a.txt,docs{}}").unwrap();
+
+        assert_eq!(tree_json(&root), json!({
+            "name": "root",
+            "type": "dir",
+            "entries": [{ "name": "a.txt", "type": "file" }, { "name": "docs", "type": "dir", "entries": [] }]
+        }));
+    }
+
+    #[test]
+    fn render_stat_should_format_the_modification_time() {
+        let stat = Stat { is_dir: false, size: 5, modified: 1_700_000_000 };
+
+        assert_eq!(render_stat("a.txt", &stat), "path: a.txt\ntype: file\nsize: 5\nmodified: 2023-11-14T22:13:20Z\n");
+        assert_eq!(stat_json("a.txt", &stat)["type"], "file");
+    }
+
+    #[test]
+    fn render_usage_should_show_the_children_before_the_total() {
+        use client::protocol::du::Usage;
+        let usage = PathUsage {
+            total: Usage { size: 9, files: 2 },
+            children: vec![("a.txt".to_string(), Usage { size: 5, files: 1 }), ("old".to_string(), Usage { size: 4, files: 1 })]
+        };
+
+        assert_eq!(render_usage("docs/", &usage), "5\t1\tdocs/a.txt\n4\t1\tdocs/old\n9\t2\tdocs/\n");
+        assert_eq!(usage_json("docs", &PathUsage { children: vec![], ..usage }), json!({ "path": "docs", "size": 9, "files": 2 }));
+    }
+
+    #[test]
+    fn render_space_should_show_only_the_configured_quotas() {
+        let space = Space { total: 1000, available: 400, remaining_bytes: Some(100), remaining_files: None };
+
+        assert_eq!(render_space(&space), "total: 1000\navailable: 400\nremaining bytes: 100\n");
+        assert_eq!(space_json(&space)["remaining_files"], Value::Null);
+    }
+
+    #[test]
+    fn events_should_show_their_paths() {
+        let rename = Event::Rename { from: "/a.txt".to_string(), to: "/docs/a.txt".to_string() };
+
+        assert_eq!(render_event(&rename), "rename /a.txt -> /docs/a.txt\n");
+        assert_eq!(render_event(&Event::Overflow), "overflow\n");
+        assert_eq!(event_json(&rename), json!({ "event": "rename", "from": "/a.txt", "to": "/docs/a.txt" }));
+        assert_eq!(event_json(&Event::Delete("/b".to_string())), json!({ "event": "delete", "path": "/b" }));
+    }
+}
@@ -0,0 +1,313 @@
+//! Interactive shell: the commands run on one connection, relative to a remote
+//! working directory, with tab completion of the remote paths from the tree of
+//! the last LIST.
+
+use std::path::{Path, PathBuf};
+use rustyline::{
+    Context, Editor, Helper,
+    completion::{Completer, FilenameCompleter, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator
+};
+use client::protocol::tree::Entry;
+use crate::{connection::{self, ConnectionArgs, Connected}, output, download, upload, CliError};
+
+const HELP: &str = "\
+cd [PATH]                  change the remote directory (default: the root)
+ls [PATH]                  show the remote directory as a tree
+pwd                        print the remote directory
+get REMOTE [LOCAL]         download a file
+put LOCAL [REMOTE]         upload a file
+rm PATH                    remove a file or an empty directory
+mkdir PATH                 create a directory
+mv SOURCE DESTINATION      move or rename
+stat PATH                  show the metadata of a path
+help                       show this help
+exit                       close the connection
+";
+
+/// Names of the commands, for the completion.
+const COMMANDS: [&str; 11] = ["cd", "ls", "pwd", "get", "put", "rm", "mkdir", "mv", "stat", "help", "exit"];
+
+
+/// Run the shell until `exit` or end of input.
+/// If the connection is lost (e.g. closed by the server after the idle timeout),
+/// the next command opens a new one.
+pub async fn run(args: &ConnectionArgs, quiet: bool) -> Result<(), CliError> {
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()
+        .map_err(|e| CliError::Local(e.to_string()))?;
+    editor.set_helper(Some(ShellHelper::default()));
+    let mut shell = Shell { args, quiet, client: Some(connection::connect(args).await?) };
+
+    loop {
+        let prompt = format!("nftp:{}> ", state(&mut editor).cwd);
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(CliError::Local(e.to_string()))
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        let words = split_words(&line);
+        if words.first().is_some_and(|command| command == "exit" || command == "quit") {
+            break;
+        }
+        if let Err(e) = shell.execute(&words, state(&mut editor)).await {
+            eprintln!("nftp: {}", e);
+        }
+    }
+    Ok(())
+}
+
+
+/// Return the state of the shell, kept in the helper to complete the paths.
+fn state(editor: &mut Editor<ShellHelper, DefaultHistory>) -> &mut ShellHelper {
+    editor.helper_mut().expect("the helper is set at the start")
+}
+
+
+struct Shell<'a> {
+    args: &'a ConnectionArgs,
+    quiet: bool,
+    /// `None` after the connection has been lost
+    client: Option<Connected>
+}
+impl Shell<'_> {
+    /// Execute a command line, reconnecting first if needed.
+    async fn execute(&mut self, words: &[String], state: &mut ShellHelper) -> Result<(), CliError> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => self.client.insert(connection::connect(self.args).await?)
+        };
+        let result = execute(client, words, state, self.quiet).await;
+        if let Err(CliError::Client(e)) = &result {
+            if matches!(e, client::Error::Io(_) | client::Error::InvalidResponse(_) | client::Error::Timeout) {
+                self.client = None;
+            }
+        }
+        result
+    }
+}
+
+
+async fn execute(client: &mut Connected, words: &[String], state: &mut ShellHelper, quiet: bool) -> Result<(), CliError> {
+    let Some((command, args)) = words.split_first() else { return Ok(()) };
+    let arg = |i: usize| args.get(i).map(|arg| resolve(&state.cwd, arg));
+    let required = |i: usize| arg(i).ok_or(CliError::Local(format!("{}: missing argument, see `help`", command)));
+
+    match (command.as_str(), args.len()) {
+        ("help", 0) => print!("{}", HELP),
+        ("pwd", 0) => println!("{}", state.cwd),
+        ("cd", 0..=1) => {
+            let path = arg(0).unwrap_or("/".to_string());
+            if state.tree.is_none() {
+                state.tree = Some(client.list().await?);
+            }
+            match state.tree.as_ref().and_then(|tree| tree.find(&path)) {
+                Some(entry) if entry.is_dir() => state.cwd = path,
+                _ => return Err(CliError::Local(format!("{}: no such directory", path)))
+            };
+        },
+        ("ls", 0..=1) => {
+            let path = arg(0).unwrap_or(state.cwd.clone());
+            let tree = state.tree.insert(client.list().await?);
+            let entry = tree.find(&path).ok_or(CliError::Local(format!("{}: no such path", path)))?;
+            print!("{}", output::render_tree(entry));
+        },
+        ("get", 1..=2) => {
+            download(client, &required(0)?, args.get(1).map(Path::new), quiet, false).await?;
+        },
+        ("put", 1..=2) => {
+            let local = PathBuf::from(&args[0]);
+            if !local.is_file() {
+                return Err(CliError::Local(format!("{}: not a file", local.display())));
+            }
+            let remote = match args.get(1) {
+                Some(remote) => resolve(&state.cwd, remote),
+                None => resolve(&state.cwd, crate::file_name(&args[0])?)
+            };
+            let result = upload(client, &local, &remote, quiet, false).await;
+            state.tree = None;
+            result?;
+        },
+        ("rm", 1) => {
+            client.rm(&required(0)?).await?;
+            state.tree = None;
+        },
+        ("mkdir", 1) => {
+            client.mkdir(&required(0)?).await?;
+            state.tree = None;
+        },
+        ("mv", 2) => {
+            client.mv(&required(0)?, &required(1)?).await?;
+            state.tree = None;
+        },
+        ("stat", 1) => {
+            let path = required(0)?;
+            print!("{}", output::render_stat(&path, &client.stat(&path).await?));
+        },
+        (command, _) if COMMANDS.contains(&command) => {
+            return Err(CliError::Local(format!("{}: wrong number of arguments, see `help`", command)));
+        },
+        (command, _) => return Err(CliError::Local(format!("{}: unknown command, see `help`", command)))
+    };
+    Ok(())
+}
+
+
+/// Return the absolute remote path of `path`, relative to `cwd` unless it starts
+/// with `/`, without `.` and `..` components.
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let start = if path.starts_with('/') { "" } else { cwd };
+    for component in start.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => (),
+            ".." => { components.pop(); },
+            component => components.push(component)
+        };
+    }
+    format!("/{}", components.join("/"))
+}
+
+
+/// Split a command line into words, on whitespace outside double quotes.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut in_word = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            },
+            c if c.is_whitespace() && !quoted => if in_word {
+                words.push(std::mem::take(&mut word));
+                in_word = false;
+            },
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+
+/// State of the shell used by the completion: the remote directory and the tree
+/// of the last LIST, dropped when a command changes it.
+pub struct ShellHelper {
+    cwd: String,
+    tree: Option<Entry>,
+    files: FilenameCompleter
+}
+impl Default for ShellHelper {
+    fn default() -> Self {
+        ShellHelper { cwd: "/".to_string(), tree: None, files: FilenameCompleter::new() }
+    }
+}
+impl ShellHelper {
+    /// Return the candidates to complete the remote path `word`: the entries of
+    /// its directory that start with its last component.
+    fn complete_remote(&self, word: &str) -> Vec<Pair> {
+        let (directory, prefix) = match word.rfind('/') {
+            Some(i) => word.split_at(i + 1),
+            None => ("", word)
+        };
+        let Some(Entry::Dir { entries, .. }) = self.tree.as_ref().and_then(|tree| tree.find(&resolve(&self.cwd, directory))) else {
+            return Vec::new();
+        };
+        entries.iter()
+            .filter(|entry| entry.name().starts_with(prefix))
+            .map(|entry| {
+                let name = if entry.is_dir() { format!("{}/", entry.name()) } else { entry.name().to_string() };
+                Pair { replacement: format!("{}{}", directory, name), display: name }
+            })
+            .collect()
+    }
+}
+
+/// Which arguments of the commands are remote paths; the others are local paths.
+fn is_remote_argument(command: &str, index: usize) -> bool {
+    matches!((command, index), ("cd" | "ls" | "get" | "rm" | "mkdir" | "stat" | "mv", 0) | ("mv" | "put", 1))
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let previous: Vec<&str> = line[..start].split_whitespace().collect();
+        match previous.split_first() {
+            None => Ok((start, COMMANDS.iter()
+                .filter(|command| command.starts_with(word))
+                .map(|command| Pair { display: command.to_string(), replacement: format!("{} ", command) })
+                .collect())),
+            Some((command, args)) if is_remote_argument(command, args.len()) => Ok((start, self.complete_remote(word))),
+            Some((&("get" | "put"), _)) => self.files.complete(line, pos, ctx),
+            Some(_) => Ok((start, Vec::new()))
+        }
+    }
+}
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+impl Highlighter for ShellHelper {}
+impl Validator for ShellHelper {}
+impl Helper for ShellHelper {}
+
+
+#[cfg(test)]
+pub mod test {
+    use client::protocol::tree::parse_tree;
+    use super::*;
+
+    #[test]
+    fn resolve_should_follow_the_working_directory() {
+        assert_eq!(resolve("/", "docs"), "/docs");
+        assert_eq!(resolve("/docs", "old/../a.txt"), "/docs/a.txt");
+        assert_eq!(resolve("/docs", "/music/./b.mp3"), "/music/b.mp3");
+        assert_eq!(resolve("/docs", "../../.."), "/");
+    }
+
+    #[test]
+    fn split_words_should_keep_quoted_spaces() {
+        assert_eq!(split_words("  put \"my file.txt\"  docs/ "), vec!["put", "my file.txt", "docs/"]);
+        assert_eq!(split_words("mkdir \"\""), vec!["mkdir", ""]);
+        assert!(split_words("   ").is_empty());
+    }
+
+    #[test]
+    fn complete_remote_should_return_the_matching_entries() {
+        let helper = ShellHelper {
+            cwd: "/docs".to_string(),
+            tree: parse_tree("/{docs{a.txt,archive{b.txt,}c.txt,}}"),
+            ..Default::default()
+        };
+        let replacements = |word| helper.complete_remote(word).into_iter().map(|pair| pair.replacement).collect::<Vec<_>>();
+
+        assert_eq!(replacements("a"), vec!["a.txt", "archive/"]);
+        assert_eq!(replacements("archive/"), vec!["archive/b.txt"]);
+        assert_eq!(replacements("/do"), vec!["/docs/"]);
+        assert!(replacements("missing/").is_empty());
+    }
+
+    #[test]
+    fn only_remote_arguments_should_be_completed_remotely() {
+        assert!(is_remote_argument("get", 0));
+        assert!(!is_remote_argument("get", 1));
+        assert!(!is_remote_argument("put", 0));
+        assert!(is_remote_argument("put", 1));
+        assert!(is_remote_argument("mv", 1));
+    }
+}
@@ -0,0 +1,408 @@
+//! Mirror of a directory tree between the server and the local filesystem, in
+//! either direction: only the files that changed are transferred.
+
+use std::{collections::BTreeMap, fs, io::Read, path::{Component, Path, PathBuf}, time::{Duration, UNIX_EPOCH}};
+use clap::Args;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use client::protocol::tree::Entry;
+use crate::{connection::Connected, download, upload, CliError, Output};
+
+/// Prefix of the remote locations in the arguments
+const REMOTE_PREFIX: &str = "remote:";
+
+#[derive(Args, Debug)]
+pub struct SyncArgs {
+    /// Source directory: `remote:/dir` on the server, or a local path
+    source: String,
+
+    /// Destination directory: `remote:/dir` on the server, or a local path
+    destination: String,
+
+    /// Compare the files with the same dimension by SHA-256 instead of modification time
+    #[arg(long)]
+    checksum: bool,
+
+    /// Delete the files and directories of the destination that are not in the source
+    #[arg(long)]
+    delete: bool,
+
+    /// Transfer only the blocks that changed of the files that exist on both sides
+    #[arg(long)]
+    delta: bool,
+
+    /// Only show what would be done
+    #[arg(short = 'n', long)]
+    dry_run: bool
+}
+
+
+/// A directory on one side of the sync.
+#[derive(Debug, PartialEq)]
+enum Location {
+    Remote(String),
+    Local(PathBuf)
+}
+impl Location {
+    fn parse(arg: &str) -> Self {
+        match arg.strip_prefix(REMOTE_PREFIX) {
+            Some(path) => Location::Remote(path.to_string()),
+            None => Location::Local(PathBuf::from(arg))
+        }
+    }
+}
+
+
+/// A file or a directory of a listing.
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    is_dir: bool,
+    size: u64,
+    /// Last modification, in seconds since the Unix epoch
+    modified: u64,
+    checksum: Option<[u8; 32]>
+}
+
+/// The whole tree under a directory, by path relative to it. Parents always
+/// come before their contents.
+type Listing = BTreeMap<String, Item>;
+
+
+/// What the sync does on the destination, on a path relative to its directory.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Mkdir(String),
+    /// Transfer a file, of the given dimension
+    Copy(String, u64),
+    /// Remove a file or, if `true`, a directory
+    Delete(String, bool)
+}
+
+
+/// Sync the destination with the source, one of which must be remote.
+pub async fn run(client: &mut Connected, args: &SyncArgs, quiet: bool) -> Result<Output, CliError> {
+    let (source, destination) = (Location::parse(&args.source), Location::parse(&args.destination));
+    let (mut source_listing, mut destination_listing) = match (&source, &destination) {
+        (Location::Remote(remote), Location::Local(local)) => (remote_listing(client, remote).await?, local_listing(local)?),
+        (Location::Local(local), Location::Remote(remote)) => (local_listing(local)?, remote_listing(client, remote).await?),
+        _ => return Err(CliError::Local(format!("one of source and destination must start with `{}`", REMOTE_PREFIX)))
+    };
+
+    if args.checksum {
+        for (path, item) in source_listing.iter_mut() {
+            let Some(other) = destination_listing.get_mut(path) else { continue };
+            if !item.is_dir && !other.is_dir && item.size == other.size {
+                item.checksum = Some(checksum(client, &source, path).await?);
+                other.checksum = Some(checksum(client, &destination, path).await?);
+            }
+        }
+    }
+
+    // only a local copy gets the modification time of the source
+    let exact_modified = matches!(destination, Location::Local(_));
+    let actions = plan(&source_listing, &destination_listing, args.delete, exact_modified)?;
+
+    let mut text = String::new();
+    let mut done = Vec::with_capacity(actions.len());
+    if !args.dry_run {
+        if let Location::Local(local) = &destination {
+            fs::create_dir_all(local).map_err(|e| CliError::Local(format!("{}: {}", local.display(), e)))?;
+        }
+    }
+    for action in &actions {
+        if !args.dry_run {
+            apply(client, action, &source, &destination, &source_listing, quiet, args.delta).await?;
+        }
+        let (name, path, bytes) = match action {
+            Action::Mkdir(path) => ("mkdir", path, None),
+            Action::Copy(path, bytes) => ("copy", path, Some(*bytes)),
+            Action::Delete(path, _) => ("delete", path, None)
+        };
+        text.push_str(&format!("{} {}\n", name, path));
+        done.push(json!({ "action": name, "path": path, "bytes": bytes }));
+    }
+    if args.dry_run {
+        text.push_str(&format!("dry run: {} actions not applied\n", actions.len()));
+    }
+    Ok(Output { text, json: json!({ "dry_run": args.dry_run, "actions": done }) })
+}
+
+
+/// Return the actions that make the destination equal to the source: first the
+/// deletions (contents before their directory), then the creations and the copies
+/// (directories before their contents).
+///
+/// A file is copied if it's missing, has a different dimension or checksum or, without
+/// checksums, a different modification time (`exact_modified`) or an older one.
+fn plan(source: &Listing, destination: &Listing, delete: bool, exact_modified: bool) -> Result<Vec<Action>, CliError> {
+    let mut actions = Vec::new();
+    for (path, item) in destination.iter().rev() {
+        let kept = source.get(path).is_some_and(|source_item| source_item.is_dir == item.is_dir);
+        if kept {
+            continue;
+        }
+        if !delete {
+            if source.contains_key(path) {
+                return Err(CliError::Local(format!("{}: a file on one side and a directory on the other, use --delete", path)));
+            }
+            continue;
+        }
+        actions.push(Action::Delete(path.clone(), item.is_dir));
+    }
+
+    for (path, item) in source {
+        let existing = destination.get(path).filter(|destination_item| destination_item.is_dir == item.is_dir);
+        let changed = match existing {
+            None => true,
+            Some(_) if item.is_dir => false,
+            Some(other) if item.size != other.size => true,
+            Some(other) => match (item.checksum, other.checksum) {
+                (Some(checksum), Some(other_checksum)) => checksum != other_checksum,
+                _ if exact_modified => item.modified != other.modified,
+                _ => item.modified > other.modified
+            }
+        };
+        if changed {
+            actions.push(if item.is_dir { Action::Mkdir(path.clone()) } else { Action::Copy(path.clone(), item.size) });
+        }
+    }
+    Ok(actions)
+}
+
+
+async fn apply(client: &mut Connected, action: &Action, source: &Location, destination: &Location, source_listing: &Listing, quiet: bool, delta: bool) -> Result<(), CliError> {
+    let local_error = |path: &Path, e: std::io::Error| CliError::Local(format!("{}: {}", path.display(), e));
+    match (action, destination) {
+        (Action::Mkdir(path), Location::Remote(remote)) => client.mkdir(&remote_path(remote, path)).await?,
+        (Action::Mkdir(path), Location::Local(local)) => {
+            let local = local.join(path);
+            fs::create_dir(&local).map_err(|e| local_error(&local, e))?;
+        },
+        (Action::Delete(path, _), Location::Remote(remote)) => client.rm(&remote_path(remote, path)).await?,
+        (Action::Delete(path, is_dir), Location::Local(local)) => {
+            let local = local.join(path);
+            let removed = if *is_dir { fs::remove_dir(&local) } else { fs::remove_file(&local) };
+            removed.map_err(|e| local_error(&local, e))?;
+        },
+        (Action::Copy(path, _), Location::Remote(remote)) => {
+            let Location::Local(local) = source else { unreachable!("one side is local") };
+            upload(client, &local.join(path), &remote_path(remote, path), quiet, delta).await?;
+        },
+        (Action::Copy(path, _), Location::Local(local)) => {
+            let Location::Remote(remote) = source else { unreachable!("one side is remote") };
+            let local = local.join(path);
+            download(client, &remote_path(remote, path), Some(&local), quiet, delta).await?;
+            let modified = UNIX_EPOCH + Duration::from_secs(source_listing[path].modified);
+            fs::File::options().write(true).open(&local)
+                .and_then(|file| file.set_modified(modified))
+                .map_err(|e| local_error(&local, e))?;
+        }
+    };
+    Ok(())
+}
+
+
+/// Return the listing of a remote directory, with the metadata of every file.
+async fn remote_listing(client: &mut Connected, remote: &str) -> Result<Listing, CliError> {
+    let tree = client.list().await?;
+    let Some(Entry::Dir { entries, .. }) = tree.find(remote) else {
+        return Err(CliError::Local(format!("{}{}: no such directory", REMOTE_PREFIX, remote)));
+    };
+    let mut paths = Vec::new();
+    collect_paths(entries, "", &mut paths)?;
+
+    let mut listing = Listing::new();
+    for (path, is_dir) in paths {
+        let item = if is_dir {
+            Item { is_dir, size: 0, modified: 0, checksum: None }
+        } else {
+            let stat = client.stat(&remote_path(remote, &path)).await?;
+            Item { is_dir, size: stat.size, modified: stat.modified, checksum: None }
+        };
+        listing.insert(path, item);
+    }
+    Ok(listing)
+}
+
+/// Collect the paths of the entries, refusing the names that aren't a single normal
+/// component (`..`, empty, with a `/`): they would lead outside the local directory.
+fn collect_paths(entries: &[Entry], prefix: &str, paths: &mut Vec<(String, bool)>) -> Result<(), CliError> {
+    for entry in entries {
+        let mut components = Path::new(entry.name()).components();
+        let plain = matches!((components.next(), components.next()), (Some(Component::Normal(name)), None) if name == entry.name());
+        if !plain {
+            return Err(CliError::Local(format!("{}{}: invalid name in the listing of the server", prefix, entry.name())));
+        }
+        let path = format!("{}{}", prefix, entry.name());
+        paths.push((path.clone(), entry.is_dir()));
+        if let Entry::Dir { entries, .. } = entry {
+            collect_paths(entries, &format!("{}/", path), paths)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Return the listing of a local directory; a missing directory is empty.
+/// Symbolic links and the other special files are skipped, so the walk never
+/// leaves the directory.
+fn local_listing(local: &Path) -> Result<Listing, CliError> {
+    let mut listing = Listing::new();
+    if local.exists() {
+        walk(local, "", &mut listing).map_err(|e| CliError::Local(format!("{}: {}", local.display(), e)))?;
+    }
+    Ok(listing)
+}
+
+fn walk(dir: &Path, prefix: &str, listing: &mut Listing) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = fs::symlink_metadata(entry.path())?;
+        if !metadata.is_dir() && !metadata.is_file() {
+            continue;
+        }
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+        let path = format!("{}{}", prefix, name);
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_secs());
+        listing.insert(path.clone(), Item {
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified,
+            checksum: None
+        });
+        if metadata.is_dir() {
+            walk(&entry.path(), &format!("{}/", path), listing)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Return the SHA-256 of a file on either side.
+async fn checksum(client: &mut Connected, location: &Location, path: &str) -> Result<[u8; 32], CliError> {
+    match location {
+        Location::Remote(remote) => Ok(client.checksum(&remote_path(remote, path)).await?),
+        Location::Local(local) => {
+            let local = local.join(path);
+            sha256(&local).map_err(|e| CliError::Local(format!("{}: {}", local.display(), e)))
+        }
+    }
+}
+
+fn sha256(path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut chunk)? {
+            0 => return Ok(hasher.finalize().into()),
+            n => hasher.update(&chunk[..n])
+        }
+    }
+}
+
+
+fn remote_path(remote: &str, path: &str) -> String {
+    format!("{}/{}", remote.trim_end_matches('/'), path)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn file(size: u64, modified: u64) -> Item {
+        Item { is_dir: false, size, modified, checksum: None }
+    }
+
+    fn dir() -> Item {
+        Item { is_dir: true, size: 0, modified: 0, checksum: None }
+    }
+
+    fn listing(items: &[(&str, Item)]) -> Listing {
+        items.iter().map(|(path, item)| (path.to_string(), item.clone())).collect()
+    }
+
+    #[test]
+    fn parse_should_recognize_remote_locations() {
+        assert_eq!(Location::parse("remote:/docs"), Location::Remote("/docs".to_string()));
+        assert_eq!(Location::parse("./docs"), Location::Local(PathBuf::from("./docs")));
+    }
+
+    #[test]
+    fn plan_should_copy_only_what_changed() {
+        let source = listing(&[("a.txt", file(5, 100)), ("docs", dir()), ("docs/b.txt", file(3, 100)), ("docs/c.txt", file(3, 200))]);
+        let destination = listing(&[("a.txt", file(5, 100)), ("docs", dir()), ("docs/b.txt", file(4, 100)), ("docs/c.txt", file(3, 100))]);
+
+        assert_eq!(plan(&source, &destination, false, true).unwrap(), vec![
+            Action::Copy("docs/b.txt".to_string(), 3),
+            Action::Copy("docs/c.txt".to_string(), 3)
+        ]);
+        assert_eq!(plan(&source, &listing(&[]), false, true).unwrap().first(), Some(&Action::Copy("a.txt".to_string(), 5)));
+        assert_eq!(plan(&source, &listing(&[]), false, true).unwrap().get(1), Some(&Action::Mkdir("docs".to_string())));
+    }
+
+    #[test]
+    fn plan_on_remote_should_copy_only_newer_files() {
+        let source = listing(&[("a.txt", file(5, 100)), ("b.txt", file(5, 300))]);
+        let destination = listing(&[("a.txt", file(5, 200)), ("b.txt", file(5, 200))]);
+
+        assert_eq!(plan(&source, &destination, false, false).unwrap(), vec![Action::Copy("b.txt".to_string(), 5)]);
+    }
+
+    #[test]
+    fn plan_with_checksums_should_ignore_the_modification_time() {
+        let mut source = listing(&[("a.txt", file(5, 100)), ("b.txt", file(5, 100))]);
+        let mut destination = listing(&[("a.txt", file(5, 200)), ("b.txt", file(5, 100))]);
+        source.get_mut("a.txt").unwrap().checksum = Some([1; 32]);
+        destination.get_mut("a.txt").unwrap().checksum = Some([1; 32]);
+        source.get_mut("b.txt").unwrap().checksum = Some([1; 32]);
+        destination.get_mut("b.txt").unwrap().checksum = Some([2; 32]);
+
+        assert_eq!(plan(&source, &destination, false, true).unwrap(), vec![Action::Copy("b.txt".to_string(), 5)]);
+    }
+
+    #[test]
+    fn plan_with_delete_should_remove_the_contents_first() {
+        let source = listing(&[("a.txt", file(5, 100)), ("old", file(1, 100))]);
+        let destination = listing(&[("a.txt", file(5, 100)), ("old", dir()), ("old/x.txt", file(1, 100)), ("z.txt", file(1, 100))]);
+
+        assert!(plan(&source, &destination, false, true).is_err());
+        assert_eq!(plan(&source, &destination, true, true).unwrap(), vec![
+            Action::Delete("z.txt".to_string(), false),
+            Action::Delete("old/x.txt".to_string(), false),
+            Action::Delete("old".to_string(), true),
+            Action::Copy("old".to_string(), 1)
+        ]);
+    }
+
+    #[test]
+    fn collect_paths_should_refuse_names_leading_outside() {
+        let tree = |name: &str| vec![Entry::Dir { name: "docs".to_string(), entries: vec![Entry::File(name.to_string())] }];
+        let mut paths = Vec::new();
+
+        collect_paths(&tree("a.txt"), "", &mut paths).unwrap();
+        assert_eq!(paths, vec![("docs".to_string(), true), ("docs/a.txt".to_string(), false)]);
+        for name in ["..", ".", "", "a/b", "/etc", "a/"] {
+            assert!(collect_paths(&tree(name), "", &mut Vec::new()).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn local_listing_should_walk_the_tree() {
+        let root = std::env::temp_dir().join(format!("nftp-sync-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.txt"), b"hello").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("docs/loop")).unwrap();
+
+        let listing = local_listing(&root).unwrap();
+        assert_eq!(listing.keys().collect::<Vec<_>>(), vec!["docs", "docs/a.txt"]);
+        assert!(listing["docs"].is_dir);
+        assert_eq!(listing["docs/a.txt"].size, 5);
+        assert!(local_listing(&root.join("missing")).unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use std::{fmt, io};
+use protocol::{request::EncodeError, response::*};
+
+/// Errors of the client: the transport, the protocol or a response code of the server.
+#[derive(Debug)]
+pub enum Error {
+    /// The connection failed.
+    Io(io::Error),
+    /// The request doesn't fit in the limits of the protocol.
+    Encode(EncodeError),
+    /// The server answered with something that isn't a valid response.
+    InvalidResponse(String),
+    /// GENERIC ERROR: e.g. a bad request, or a path that doesn't exist.
+    Generic,
+    /// AUTHENTICATION FAILED: wrong credentials, or an istruction sent before LOGIN.
+    AuthFailed,
+    /// LOCKED OUT: too many failed logins from the same address.
+    LockedOut,
+    /// PERMISSION DENIED: the user has not the right to access the path.
+    PermissionDenied,
+    /// SERVER BUSY: too many sessions; the connection is closed.
+    ServerBusy,
+    /// TIMEOUT: the client was too slow; the connection is closed.
+    Timeout,
+    /// QUOTA EXCEEDED: the upload doesn't fit in a quota of the user or of a directory.
+    QuotaExceeded,
+    /// A response code this client doesn't know.
+    Unknown(u8)
+}
+impl Error {
+    /// Return the error of a response code other than OK.
+    pub fn from_response_code(response_code: u8) -> Self {
+        match response_code {
+            RC_ERROR => Error::Generic,
+            RC_AUTH_FAILED => Error::AuthFailed,
+            RC_LOCKED_OUT => Error::LockedOut,
+            RC_PERMISSION_DENIED => Error::PermissionDenied,
+            RC_SERVER_BUSY => Error::ServerBusy,
+            RC_TIMEOUT => Error::Timeout,
+            RC_QUOTA_EXCEEDED => Error::QuotaExceeded,
+            response_code => Error::Unknown(response_code)
+        }
+    }
+
+    /// Return the response code of the error, if it comes from the server.
+    pub fn response_code(&self) -> Option<u8> {
+        match self {
+            Error::Io(_) | Error::Encode(_) | Error::InvalidResponse(_) => None,
+            Error::Generic => Some(RC_ERROR),
+            Error::AuthFailed => Some(RC_AUTH_FAILED),
+            Error::LockedOut => Some(RC_LOCKED_OUT),
+            Error::PermissionDenied => Some(RC_PERMISSION_DENIED),
+            Error::ServerBusy => Some(RC_SERVER_BUSY),
+            Error::Timeout => Some(RC_TIMEOUT),
+            Error::QuotaExceeded => Some(RC_QUOTA_EXCEEDED),
+            Error::Unknown(response_code) => Some(*response_code)
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "connection error: {}", e),
+            Error::Encode(e) => write!(f, "invalid request: {}", e),
+            Error::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+            Error::Generic => write!(f, "the server failed the request"),
+            Error::AuthFailed => write!(f, "authentication failed"),
+            Error::LockedOut => write!(f, "locked out after too many failed logins"),
+            Error::PermissionDenied => write!(f, "permission denied"),
+            Error::ServerBusy => write!(f, "the server is busy"),
+            Error::Timeout => write!(f, "the server closed the connection for a timeout"),
+            Error::QuotaExceeded => write!(f, "quota exceeded"),
+            Error::Unknown(response_code) => write!(f, "unknown response code {}", response_code)
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Encode(e) => Some(e),
+            _ => None
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+impl From<EncodeError> for Error {
+    fn from(e: EncodeError) -> Self {
+        Error::Encode(e)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn every_error_response_code_should_have_its_error() {
+        for response_code in [RC_ERROR, RC_AUTH_FAILED, RC_LOCKED_OUT, RC_PERMISSION_DENIED, RC_SERVER_BUSY, RC_TIMEOUT, RC_QUOTA_EXCEEDED, 200] {
+            assert_eq!(Error::from_response_code(response_code).response_code(), Some(response_code));
+        }
+        assert!(matches!(Error::from_response_code(RC_PERMISSION_DENIED), Error::PermissionDenied));
+    }
+}
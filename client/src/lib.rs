@@ -0,0 +1,654 @@
+//! Async client of the nFTP protocol.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), client::Error> {
+//! let mut client = client::Client::connect("127.0.0.1:3000").await?;
+//! client.login("alice", b"secret").await?;
+//! let tree = client.list().await?;
+//! let file = client.get("docs/a.txt").await?;
+//! client.put("docs/b.txt", b"hello").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod error;
+mod search;
+mod watch;
+
+use std::path::{Path, PathBuf};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs, UnixStream}
+};
+use protocol::{
+    istruction::{GET, LIST, PUT, LOGIN, RM, MKDIR, MV, STAT, CHECKSUM, GET_DELTA, SIGNATURES, PUT_DELTA, WATCH, SEARCH, DU, SPACE},
+    delta::{self, Delta, DeltaOp, Signatures},
+    du::{self, PathUsage},
+    request::Request,
+    search::Query,
+    space::Space,
+    response::{response_code, HEADER_LEN, RC_OK},
+    stat::Stat,
+    tree::{parse_tree, Entry}
+};
+
+pub use error::Error;
+pub use search::Search;
+pub use watch::Watch;
+pub use protocol;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Dimension of the chunks in which the transfers are read from and written to files
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A connection to an nFTP server, on any stream.
+///
+/// The requests are served in order on the same connection. If a transfer fails
+/// midway, the rest of its payload is still on the stream and the connection
+/// can't be used anymore.
+pub struct Client<S> {
+    stream: S
+}
+
+impl Client<TcpStream> {
+    /// Connect to a server through TCP.
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> Result<Self> {
+        Ok(Client::new(TcpStream::connect(address).await?))
+    }
+}
+
+impl Client<UnixStream> {
+    /// Connect to a server through a Unix domain socket.
+    pub async fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Client::new(UnixStream::connect(path).await?))
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Client<tokio_rustls::client::TlsStream<TcpStream>> {
+    /// Connect to a server through TLS, verifying its certificate for `server_name`.
+    pub async fn connect_tls<A: ToSocketAddrs>(
+        address: A,
+        server_name: &str,
+        tls_config: std::sync::Arc<tokio_rustls::rustls::ClientConfig>
+    ) -> Result<Self>
+    {
+        let server_name = tokio_rustls::rustls::pki_types::ServerName::try_from(server_name.to_string())
+            .map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))?;
+        let stream = TcpStream::connect(address).await?;
+        let stream = tokio_rustls::TlsConnector::from(tls_config).connect(server_name, stream).await?;
+        Ok(Client::new(stream))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+    /// Use an already connected stream.
+    pub fn new(stream: S) -> Self {
+        Client { stream }
+    }
+
+    /// Return the stream of the connection.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Log in with username and password.
+    pub async fn login(&mut self, username: &str, password: &[u8]) -> Result<()> {
+        self.send(Request::new(LOGIN).path(username).payload(password)).await?;
+        self.read_response(false).await?;
+        Ok(())
+    }
+
+    /// Log in with a pre-shared API token.
+    pub async fn login_with_token(&mut self, token: &[u8]) -> Result<()> {
+        self.send(Request::new(LOGIN).payload(token)).await?;
+        self.read_response(false).await?;
+        Ok(())
+    }
+
+    /// Return the tree of the user space.
+    pub async fn list(&mut self) -> Result<Entry> {
+        self.send(Request::new(LIST)).await?;
+        let payload = self.read_payload().await?;
+        let serialized = String::from_utf8(payload)
+            .map_err(|_| Error::InvalidResponse("the tree is not UTF-8".to_string()))?;
+        parse_tree(&serialized).ok_or(Error::InvalidResponse("malformed tree".to_string()))
+    }
+
+    /// Download a file in memory.
+    pub async fn get(&mut self, path: &str) -> Result<Vec<u8>> {
+        self.send(Request::new(GET).path(path)).await?;
+        self.read_payload().await
+    }
+
+    /// Download a file into a local file, one chunk at a time, returning its dimension.
+    pub async fn get_to_file<P: AsRef<Path>>(&mut self, path: &str, local_path: P) -> Result<u64> {
+        self.get_to_file_with_progress(path, local_path, |_, _| ()).await
+    }
+
+    /// Same as `get_to_file`, calling `progress` with the bytes received so far and
+    /// the dimension of the file, after the response header and after each chunk.
+    /// The file is received in a hidden file next to the local one, which replaces it
+    /// only when the whole payload arrived: a failed download leaves it untouched.
+    pub async fn get_to_file_with_progress<P, F>(&mut self, path: &str, local_path: P, mut progress: F) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        F: FnMut(u64, u64)
+    {
+        self.send(Request::new(GET).path(path)).await?;
+        let payload_dim = self.read_response(true).await?.unwrap_or(0);
+        progress(0, payload_dim);
+
+        let local_path = local_path.as_ref();
+        let part_path = temp_path(local_path, "nftp-part");
+        let mut file = tokio::fs::File::create(&part_path).await?;
+        let received = self.read_payload_to_file(payload_dim, &mut file, progress).await;
+        drop(file);
+        let received = match received {
+            Ok(()) => tokio::fs::rename(&part_path, local_path).await.map_err(Error::from),
+            Err(e) => Err(e)
+        };
+        if received.is_err() {
+            let _ = tokio::fs::remove_file(&part_path).await;
+        }
+        received?;
+        Ok(payload_dim)
+    }
+
+    /// Update a local file with the changes of the remote one, receiving only the data
+    /// that changed: the signatures of the blocks of the local file are sent, and the
+    /// new file is rebuilt from the delta in the response and replaces the local one
+    /// when its SHA-256 matches. Return the dimension of the delta.
+    pub async fn get_delta<P: AsRef<Path>>(&mut self, path: &str, local_path: P) -> Result<u64> {
+        let local_path = local_path.as_ref().to_path_buf();
+        let signatures_path = local_path.clone();
+        let signatures = blocking(move || {
+            let file = std::fs::File::open(signatures_path)?;
+            let block_size = delta::block_size_for(file.metadata()?.len());
+            Signatures::compute(std::io::BufReader::new(file), block_size)
+        }).await?.encode();
+
+        let request = Request::new(GET_DELTA).path(path).encode_with_payload_dim(signatures.len() as u64)?;
+        let sent = async {
+            self.stream.write_all(&request).await?;
+            self.stream.write_all(&signatures).await
+        }.await;
+        let payload_dim = self.finish_request(sent, true).await?.unwrap_or(0);
+
+        let delta_path = temp_path(&local_path, "nftp-delta");
+        let mut delta_file = tokio::fs::File::create(&delta_path).await?;
+        let received = self.read_payload_to_file(payload_dim, &mut delta_file, |_, _| ()).await;
+        drop(delta_file);
+        let rebuilt = match received {
+            Ok(()) => {
+                let (delta_path, part_path) = (delta_path.clone(), temp_path(&local_path, "nftp-part"));
+                blocking(move || {
+                    let delta = std::io::BufReader::new(std::fs::File::open(&delta_path)?);
+                    let old_copy = std::fs::File::open(&local_path)?;
+                    let mut new_file = std::io::BufWriter::new(std::fs::File::create(&part_path)?);
+                    let rebuilt = delta::apply_delta(delta, old_copy, &mut new_file)
+                        .and_then(|_| new_file.into_inner().map_err(|e| e.into_error()))
+                        .and_then(|_| std::fs::rename(&part_path, &local_path));
+                    if rebuilt.is_err() {
+                        let _ = std::fs::remove_file(&part_path);
+                    }
+                    rebuilt
+                }).await
+            },
+            Err(e) => Err(e)
+        };
+        let _ = tokio::fs::remove_file(&delta_path).await;
+        rebuilt?;
+        Ok(payload_dim)
+    }
+
+    /// Upload a file from memory; an existing file is replaced.
+    pub async fn put(&mut self, path: &str, file: &[u8]) -> Result<()> {
+        let request = Request::new(PUT).path(path).encode_with_payload_dim(file.len() as u64)?;
+        let sent = async {
+            self.stream.write_all(&request).await?;
+            self.stream.write_all(file).await
+        }.await;
+        self.finish_request(sent, false).await?;
+        Ok(())
+    }
+
+    /// Upload a local file, one chunk at a time, returning its dimension.
+    pub async fn put_from_file<P: AsRef<Path>>(&mut self, path: &str, local_path: P) -> Result<u64> {
+        self.put_from_file_with_progress(path, local_path, |_, _| ()).await
+    }
+
+    /// Same as `put_from_file`, calling `progress` with the bytes sent so far and the
+    /// dimension of the file, before the upload and after each chunk.
+    pub async fn put_from_file_with_progress<P, F>(&mut self, path: &str, local_path: P, mut progress: F) -> Result<u64>
+    where
+        P: AsRef<Path>,
+        F: FnMut(u64, u64)
+    {
+        let mut file = tokio::fs::File::open(local_path).await?;
+        let payload_dim = file.metadata().await?.len();
+        let request = Request::new(PUT).path(path).encode_with_payload_dim(payload_dim)?;
+        progress(0, payload_dim);
+
+        let sent = async {
+            self.stream.write_all(&request).await?;
+            let mut chunk = vec![0u8; CHUNK_SIZE];
+            let mut remaining = payload_dim;
+            while remaining > 0 {
+                let to_read = chunk.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                let n = file.read(&mut chunk[..to_read]).await?;
+                if n == 0 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                self.stream.write_all(&chunk[..n]).await?;
+                remaining -= n as u64;
+                progress(payload_dim - remaining, payload_dim);
+            }
+            Ok(())
+        }.await;
+        self.finish_request(sent, false).await?;
+        Ok(payload_dim)
+    }
+
+    /// Update a remote file with the changes of the local one, sending only the data
+    /// that changed: the delta of the local file against the signatures of the remote
+    /// one. The server replaces its file when the SHA-256 of the rebuilt one matches.
+    /// Return the dimension of the delta.
+    pub async fn put_delta<P: AsRef<Path>>(&mut self, path: &str, local_path: P) -> Result<u64> {
+        self.send(Request::new(SIGNATURES).path(path)).await?;
+        let payload = self.read_payload().await?;
+        let signatures = Signatures::decode(&payload)
+            .ok_or(Error::InvalidResponse("malformed signatures".to_string()))?;
+
+        let local_path = local_path.as_ref().to_path_buf();
+        let delta_path = local_path.clone();
+        let delta = blocking(move || {
+            let file = std::io::BufReader::new(std::fs::File::open(delta_path)?);
+            delta::compute_delta(file, &signatures)
+        }).await?;
+
+        let mut file = tokio::fs::File::open(&local_path).await?;
+        let payload_dim = delta.encoded_len();
+        let request = Request::new(PUT_DELTA).path(path).encode_with_payload_dim(payload_dim)?;
+        let sent = async {
+            self.stream.write_all(&request).await?;
+            self.write_delta(&delta, &mut file).await
+        }.await;
+        self.finish_request(sent, false).await?;
+        Ok(payload_dim)
+    }
+
+    /// Remove a file or an empty directory.
+    pub async fn rm(&mut self, path: &str) -> Result<()> {
+        self.send(Request::new(RM).path(path)).await?;
+        self.read_response(false).await?;
+        Ok(())
+    }
+
+    /// Create a directory; its parent must exist.
+    pub async fn mkdir(&mut self, path: &str) -> Result<()> {
+        self.send(Request::new(MKDIR).path(path)).await?;
+        self.read_response(false).await?;
+        Ok(())
+    }
+
+    /// Move or rename a path; the destination must not exist.
+    pub async fn mv(&mut self, source: &str, destination: &str) -> Result<()> {
+        self.send(Request::new(MV).path(source).path(destination)).await?;
+        self.read_response(false).await?;
+        Ok(())
+    }
+
+    /// Return the metadata of a path.
+    pub async fn stat(&mut self, path: &str) -> Result<Stat> {
+        self.send(Request::new(STAT).path(path)).await?;
+        let payload = self.read_payload().await?;
+        Stat::decode(&payload).ok_or(Error::InvalidResponse("malformed stat".to_string()))
+    }
+
+    /// Return the SHA-256 of a file.
+    pub async fn checksum(&mut self, path: &str) -> Result<[u8; 32]> {
+        self.send(Request::new(CHECKSUM).path(path)).await?;
+        let payload = self.read_payload().await?;
+        payload.try_into().map_err(|_| Error::InvalidResponse("malformed checksum".to_string()))
+    }
+
+    /// Return the total dimension and number of files of each path, in the same order,
+    /// and with `children` also the usage of each immediate child.
+    pub async fn du(&mut self, paths: &[&str], children: bool) -> Result<Vec<PathUsage>> {
+        let mut request = Request::new(DU).payload([if children { du::CHILDREN } else { du::TOTALS }]);
+        for path in paths {
+            request = request.path(*path);
+        }
+        self.send(request).await?;
+        let payload = self.read_payload().await?;
+        du::decode_usages(&payload)
+            .filter(|usages| usages.len() == paths.len())
+            .ok_or(Error::InvalidResponse("malformed disk usage".to_string()))
+    }
+
+    /// Return the capacity of the server: the total and available space of its
+    /// filesystem and the remaining quotas of the user, see `Space::fits`.
+    pub async fn space(&mut self) -> Result<Space> {
+        self.send(Request::new(SPACE)).await?;
+        let payload = self.read_payload().await?;
+        Space::decode(&payload).ok_or(Error::InvalidResponse("malformed space".to_string()))
+    }
+
+    /// Search the entries under a directory matching the query: the returned `Search`
+    /// receives the matches as the server finds them.
+    pub async fn search(&mut self, path: &str, query: &Query) -> Result<Search<'_, S>> {
+        self.send(Request::new(SEARCH).path(path).payload(query.encode())).await?;
+        self.read_response(false).await?;
+        Ok(Search { client: self, ended: false })
+    }
+
+    /// Subscribe to the changes under a path: the returned `Watch` receives an event
+    /// for each one, until `Watch::unwatch`.
+    pub async fn watch(&mut self, path: &str) -> Result<Watch<'_, S>> {
+        self.send(Request::new(WATCH).path(path)).await?;
+        self.read_response(false).await?;
+        Ok(Watch { client: self, ended: false })
+    }
+
+    /// Read the response of a request with a payload. If sending the payload failed midway,
+    /// the server may have refused it and closed the connection: its response explains
+    /// the failure better.
+    async fn finish_request(&mut self, sent: std::io::Result<()>, with_payload: bool) -> Result<Option<u64>> {
+        match sent {
+            Ok(()) => self.read_response(with_payload).await,
+            Err(e) => match self.read_response(false).await {
+                Err(error) if error.response_code().is_some() => Err(error),
+                _ => Err(Error::Io(e))
+            }
+        }
+    }
+
+    /// Write an encoded delta, reading its data from the new file.
+    async fn write_delta(&mut self, delta: &Delta, file: &mut tokio::fs::File) -> std::io::Result<()> {
+        self.stream.write_all(&delta.encode_header()).await?;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        for op in &delta.ops {
+            self.stream.write_all(&op.encode_header()).await?;
+            let DeltaOp::Data { offset, len } = *op else { continue };
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            let mut remaining = len;
+            while remaining > 0 {
+                let to_read = chunk.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                let n = file.read(&mut chunk[..to_read]).await?;
+                if n == 0 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                self.stream.write_all(&chunk[..n]).await?;
+                remaining -= n as u64;
+            }
+        }
+        self.stream.write_all(&delta.encode_trailer()).await
+    }
+
+    /// Read a payload into a file, one chunk at a time, calling `progress` after each chunk.
+    async fn read_payload_to_file<F: FnMut(u64, u64)>(&mut self, payload_dim: u64, file: &mut tokio::fs::File, mut progress: F) -> Result<()> {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let mut remaining = payload_dim;
+        while remaining > 0 {
+            let to_read = chunk.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+            let n = self.stream.read(&mut chunk[..to_read]).await?;
+            if n == 0 {
+                return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            file.write_all(&chunk[..n]).await?;
+            remaining -= n as u64;
+            progress(payload_dim - remaining, payload_dim);
+        }
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn send(&mut self, request: Request) -> Result<()> {
+        self.stream.write_all(&request.encode()?).await?;
+        Ok(())
+    }
+
+    /// Read a response header, returning the dimension of the payload if `with_payload`.
+    /// A response code other than OK is returned as error.
+    async fn read_response(&mut self, with_payload: bool) -> Result<Option<u64>> {
+        let mut header = [0u8; HEADER_LEN];
+        self.stream.read_exact(&mut header).await?;
+        match response_code(&header) {
+            Some(RC_OK) => (),
+            Some(response_code) => return Err(Error::from_response_code(response_code)),
+            None => return Err(Error::InvalidResponse("not an nFTP 1.0 response".to_string()))
+        };
+        if !with_payload {
+            return Ok(None);
+        }
+        Ok(Some(self.stream.read_u64().await?))
+    }
+
+    /// Read a response with its payload, in memory.
+    async fn read_payload(&mut self) -> Result<Vec<u8>> {
+        let payload_dim = self.read_response(true).await?.unwrap_or(0);
+        let payload_dim = usize::try_from(payload_dim)
+            .map_err(|_| Error::InvalidResponse("payload too big".to_string()))?;
+        let mut payload = vec![0u8; payload_dim];
+        self.stream.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+}
+
+
+/// Return the path of a temporary file next to `path`, hidden and with this extension.
+fn temp_path(path: &Path, extension: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}", file_name, extension))
+}
+
+/// Run a file operation on a blocking thread.
+async fn blocking<T, F>(task: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static
+{
+    tokio::task::spawn_blocking(task).await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+        .map_err(Error::Io)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use tokio::io::DuplexStream;
+    use protocol::response::{ResponseHeader, RC_PERMISSION_DENIED};
+    use super::*;
+
+    /// Serve the given responses, one for each request, and return the client side.
+    fn fake_server(responses: Vec<(u8, Option<&'static [u8]>)>) -> Client<DuplexStream> {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            for (response_code, payload) in responses {
+                if server.read(&mut buf).await.unwrap() == 0 {
+                    return;
+                }
+                let header = ResponseHeader::new(1, 0, response_code, payload.map(|p| p.len() as u64));
+                server.write_all(header.get_header()).await.unwrap();
+                if let Some(payload) = payload {
+                    server.write_all(payload).await.unwrap();
+                }
+            }
+        });
+        Client::new(client)
+    }
+
+    #[tokio::test]
+    async fn requests_should_return_the_payloads() {
+        let mut client = fake_server(vec![
+            (RC_OK, None),
+            (RC_OK, Some(b"root{a.txt,docs{}}")),
+            (RC_OK, Some(b"hello")),
+            (RC_OK, Some(&[7u8; 32]))
+        ]);
+
+        client.login_with_token(b"token").await.unwrap();
+        let tree = client.list().await.unwrap();
+        assert!(tree.find("docs").unwrap().is_dir());
+        assert_eq!(client.get("a.txt").await.unwrap(), b"hello");
+        assert_eq!(client.checksum("a.txt").await.unwrap(), [7u8; 32]);
+    }
+
+    #[tokio::test]
+    async fn get_to_file_should_write_the_file() {
+        let mut client = fake_server(vec![(RC_OK, Some(b"hello"))]);
+        let local_path = std::env::temp_dir().join(format!("nftp-client-{}.txt", std::process::id()));
+
+        assert_eq!(client.get_to_file("a.txt", &local_path).await.unwrap(), 5);
+        assert_eq!(std::fs::read(&local_path).unwrap(), b"hello");
+        std::fs::remove_file(&local_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn interrupted_get_to_file_should_leave_the_local_file_untouched() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut client = Client::new(client);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            assert!(server.read(&mut buf).await.unwrap() > 0);
+            server.write_all(ResponseHeader::new(1, 0, RC_OK, Some(10)).get_header()).await.unwrap();
+            server.write_all(b"new").await.unwrap();
+        });
+        let local_path = std::env::temp_dir().join(format!("nftp-client-interrupted-{}.txt", std::process::id()));
+        std::fs::write(&local_path, b"old").unwrap();
+
+        assert!(client.get_to_file("a.txt", &local_path).await.is_err());
+        assert_eq!(std::fs::read(&local_path).unwrap(), b"old");
+        assert!(!temp_path(&local_path, "nftp-part").exists());
+        std::fs::remove_file(&local_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn error_response_codes_should_return_typed_errors() {
+        let mut client = fake_server(vec![(RC_PERMISSION_DENIED, None), (RC_OK, Some(b"hello"))]);
+
+        assert!(matches!(client.get("secret.txt").await, Err(Error::PermissionDenied)));
+        assert_eq!(client.get("a.txt").await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn transfers_should_report_the_progress() {
+        let mut client = fake_server(vec![(RC_OK, Some(b"hello")), (RC_OK, None)]);
+        let local_path = std::env::temp_dir().join(format!("nftp-client-progress-{}.txt", std::process::id()));
+
+        let mut received = Vec::new();
+        client.get_to_file_with_progress("a.txt", &local_path, |done, total| received.push((done, total))).await.unwrap();
+        assert_eq!(received, vec![(0, 5), (5, 5)]);
+
+        let mut sent = Vec::new();
+        assert_eq!(client.put_from_file_with_progress("b.txt", &local_path, |done, total| sent.push((done, total))).await.unwrap(), 5);
+        assert_eq!(sent, vec![(0, 5), (5, 5)]);
+        std::fs::remove_file(&local_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stat_should_decode_the_payload() {
+        let stat = Stat { is_dir: true, size: 4096, modified: 1_700_000_000 };
+        let payload: &'static [u8] = Box::leak(Box::new(stat.encode()));
+        let mut client = fake_server(vec![(RC_OK, Some(payload)), (RC_OK, Some(b"short"))]);
+
+        assert_eq!(client.stat("docs").await.unwrap(), stat);
+        assert!(matches!(client.stat("docs").await, Err(Error::InvalidResponse(_))));
+    }
+
+    #[tokio::test]
+    async fn refused_upload_should_return_the_response_code() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut client = Client::new(client);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            assert!(server.read(&mut buf).await.unwrap() > 0);
+            server.write_all(ResponseHeader::new(1, 0, RC_PERMISSION_DENIED, None).get_header()).await.unwrap();
+        });
+
+        assert!(matches!(client.put("b.txt", &vec![0u8; 100_000]).await, Err(Error::PermissionDenied)));
+    }
+
+    #[tokio::test]
+    async fn get_delta_should_rebuild_the_local_file() {
+        let local_path = std::env::temp_dir().join(format!("nftp-client-delta-{}.bin", std::process::id()));
+        let old: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let mut new = old.clone();
+        new[4000..4010].fill(0);
+        std::fs::write(&local_path, &old).unwrap();
+        let signatures = Signatures::compute(old.as_slice(), delta::block_size_for(old.len() as u64)).unwrap();
+        let mut encoded = Vec::new();
+        delta::compute_delta(new.as_slice(), &signatures).unwrap().write(std::io::Cursor::new(&new), &mut encoded).unwrap();
+        let encoded_len = encoded.len() as u64;
+        let payload: &'static [u8] = Box::leak(encoded.into_boxed_slice());
+        let mut client = fake_server(vec![(RC_OK, Some(payload))]);
+
+        assert_eq!(client.get_delta("a.bin", &local_path).await.unwrap(), encoded_len);
+        assert_eq!(std::fs::read(&local_path).unwrap(), new);
+        assert!(!temp_path(&local_path, "nftp-delta").exists());
+        assert!(!temp_path(&local_path, "nftp-part").exists());
+        std::fs::remove_file(&local_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_delta_with_malformed_signatures_should_return_err() {
+        let mut client = fake_server(vec![(RC_OK, Some(b"short"))]);
+
+        assert!(matches!(client.put_delta("a.bin", "missing.bin").await, Err(Error::InvalidResponse(_))));
+    }
+
+    #[tokio::test]
+    async fn watch_should_return_the_events_until_unwatch() {
+        use protocol::watch::Event;
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut client = Client::new(client);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            assert!(server.read(&mut buf).await.unwrap() > 0);
+            server.write_all(ResponseHeader::new(1, 0, RC_OK, None).get_header()).await.unwrap();
+            server.write_all(&Event::Create("/docs/a.txt".to_string()).encode()).await.unwrap();
+            server.write_all(&Event::Modify("/docs/a.txt".to_string()).encode()).await.unwrap();
+            assert_eq!(server.read_u8().await.unwrap(), protocol::watch::UNWATCH);
+            server.write_all(&Event::End.encode()).await.unwrap();
+            assert!(server.read(&mut buf).await.unwrap() > 0);
+            server.write_all(ResponseHeader::new(1, 0, RC_OK, Some(5)).get_header()).await.unwrap();
+            server.write_all(b"hello").await.unwrap();
+        });
+
+        let mut watch = client.watch("docs").await.unwrap();
+        assert_eq!(watch.next().await.unwrap(), Event::Create("/docs/a.txt".to_string()));
+        watch.unwatch().await.unwrap();
+        assert_eq!(client.get("docs/a.txt").await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn search_should_return_the_matches_until_the_end() {
+        use protocol::search::{Match, Pattern, END};
+        let found = Match { path: "/docs/a.txt".to_string(), stat: Stat { is_dir: false, size: 5, modified: 1_700_000_000 } };
+        let mut payload = found.encode().unwrap();
+        payload.extend_from_slice(&END);
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut client = Client::new(client);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 128];
+            assert!(server.read(&mut buf).await.unwrap() > 0);
+            server.write_all(ResponseHeader::new(1, 0, RC_OK, None).get_header()).await.unwrap();
+            server.write_all(&payload).await.unwrap();
+        });
+
+        let search = client.search("docs", &Query::new(Pattern::Glob("*.txt".to_string()))).await.unwrap();
+        assert_eq!(search.collect().await.unwrap(), vec![found]);
+    }
+
+    #[tokio::test]
+    async fn invalid_response_should_return_err() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut client = Client::new(client);
+        server.write_all(b"HTTP/1.1 400").await.unwrap();
+
+        assert!(matches!(client.login("alice", b"secret").await, Err(Error::InvalidResponse(_))));
+    }
+}
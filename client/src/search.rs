@@ -0,0 +1,43 @@
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt};
+use protocol::{search::Match, stat::{Stat, STAT_LEN}};
+use crate::{Client, Error, Result};
+
+/// The results of a search, see `Client::search`.
+///
+/// The connection serves no other request until all the results have been read:
+/// dropping the search before leaves the connection unusable.
+pub struct Search<'a, S> {
+    pub(crate) client: &'a mut Client<S>,
+    pub(crate) ended: bool
+}
+impl<S: AsyncRead + AsyncWrite + Unpin> Search<'_, S> {
+    /// Wait for the next match, `None` after the last one.
+    pub async fn next(&mut self) -> Result<Option<Match>> {
+        if self.ended {
+            return Ok(None);
+        }
+        let stream = &mut self.client.stream;
+        let len = stream.read_u16().await? as usize;
+        if len == 0 {
+            self.ended = true;
+            return Ok(None);
+        }
+        let mut path = vec![0u8; len];
+        stream.read_exact(&mut path).await?;
+        let mut stat = [0u8; STAT_LEN];
+        stream.read_exact(&mut stat).await?;
+        Ok(Some(Match {
+            path: String::from_utf8_lossy(&path).into_owned(),
+            stat: Stat::decode(&stat).ok_or(Error::InvalidResponse("malformed stat".to_string()))?
+        }))
+    }
+
+    /// Read all the remaining matches.
+    pub async fn collect(mut self) -> Result<Vec<Match>> {
+        let mut matches = Vec::new();
+        while let Some(found) = self.next().await? {
+            matches.push(found);
+        }
+        Ok(matches)
+    }
+}
@@ -0,0 +1,45 @@
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
+use protocol::watch::{Event, UNWATCH};
+use crate::{Client, Error, Result};
+
+/// The events of a watched path, see `Client::watch`.
+///
+/// The connection serves no other request until `unwatch`: dropping the watch
+/// without it leaves the connection unusable.
+pub struct Watch<'a, S> {
+    pub(crate) client: &'a mut Client<S>,
+    pub(crate) ended: bool
+}
+impl<S: AsyncRead + AsyncWrite + Unpin> Watch<'_, S> {
+    /// Wait for the next event. After `Event::End` the server sends no more events:
+    /// the watch was stopped, e.g. because the server is shutting down.
+    pub async fn next(&mut self) -> Result<Event> {
+        if self.ended {
+            return Ok(Event::End);
+        }
+        let stream = &mut self.client.stream;
+        let kind = stream.read_u8().await?;
+        let n_paths = Event::n_paths(kind)
+            .ok_or(Error::InvalidResponse(format!("unknown event {}", kind)))?;
+        let mut paths = Vec::with_capacity(n_paths);
+        for _ in 0..n_paths {
+            let mut path = vec![0u8; stream.read_u16().await? as usize];
+            stream.read_exact(&mut path).await?;
+            paths.push(String::from_utf8_lossy(&path).into_owned());
+        }
+        let event = Event::from_parts(kind, paths)
+            .ok_or(Error::InvalidResponse("malformed event".to_string()))?;
+        self.ended = event == Event::End;
+        Ok(event)
+    }
+
+    /// Stop the events, discarding the ones not read yet, so that the connection
+    /// can serve other requests.
+    pub async fn unwatch(mut self) -> Result<()> {
+        if !self.ended {
+            self.client.stream.write_all(&[UNWATCH]).await?;
+        }
+        while self.next().await? != Event::End {}
+        Ok(())
+    }
+}
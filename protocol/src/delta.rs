@@ -0,0 +1,512 @@
+//! Delta transfers, in the style of rsync: the side that has an old copy of a file
+//! describes it with the signatures of its blocks, the other side answers with a delta
+//! made of references to the matching blocks and of the data that changed.
+//!
+//! Signatures: block size (u32), file dimension (u64), then for each block its weak
+//! rolling checksum (u32) and the first 16 bytes of its SHA-256. The last block can
+//! be shorter.
+//!
+//! Delta: block size (u32), then the operations, each one with a tag:
+//! `0` and a block index (u32), `1` and some data (u64 dimension + bytes), `2` and the
+//! SHA-256 of the whole new file (32 bytes), that ends the delta.
+
+use std::{collections::HashMap, io::{self, Read, Seek, SeekFrom, Write}};
+use sha2::{Digest, Sha256};
+
+/// Dimension of the strong checksum of a block
+pub const STRONG_LEN: usize = 16;
+
+/// Max dimension of the signatures accepted by the server
+pub const MAX_SIGNATURES_LEN: u64 = 64 * 1024 * 1024;
+
+/// Max block size, a window of this dimension is kept in memory
+pub const MAX_BLOCK_SIZE: u32 = 1 << 20;
+
+/// Min block size: the delta keeps an operation for each block matched, so smaller
+/// blocks would make it grow with the new file
+pub const MIN_BLOCK_SIZE: u32 = 2048;
+
+const SIGNATURES_HEADER_LEN: usize = 12;
+const BLOCK_SIGNATURE_LEN: usize = 4 + STRONG_LEN;
+
+const TAG_BLOCK: u8 = 0;
+const TAG_DATA: u8 = 1;
+const TAG_END: u8 = 2;
+
+/// Dimension of the chunks in which the files are read
+const CHUNK_SIZE: usize = 64 * 1024;
+
+
+/// Return the block size for a file: about the square root of its dimension, so that
+/// both the signatures and the granularity of the changes grow slowly.
+pub fn block_size_for(file_len: u64) -> u32 {
+    let block_size = (file_len as f64).sqrt() as u64;
+    block_size.clamp(MIN_BLOCK_SIZE as u64, MAX_BLOCK_SIZE as u64).next_multiple_of(1024) as u32
+}
+
+
+/// The rsync weak checksum of a window, that can be rolled one byte at a time.
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32
+}
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut rolling = Rolling { a: 0, b: 0, len };
+        for (i, byte) in window.iter().enumerate() {
+            rolling.a = rolling.a.wrapping_add(*byte as u32);
+            rolling.b = rolling.b.wrapping_add((len - i as u32).wrapping_mul(*byte as u32));
+        }
+        rolling
+    }
+
+    /// Move the window one byte forward.
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong(block: &[u8]) -> [u8; STRONG_LEN] {
+    let digest = Sha256::digest(block);
+    let mut strong = [0u8; STRONG_LEN];
+    strong.copy_from_slice(&digest[..STRONG_LEN]);
+    strong
+}
+
+
+/// Signature of a block of the old copy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: [u8; STRONG_LEN]
+}
+
+/// Signatures of all the blocks of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signatures {
+    pub block_size: u32,
+    pub file_len: u64,
+    pub blocks: Vec<BlockSignature>
+}
+impl Signatures {
+    /// Read a file and return the signatures of its blocks.
+    pub fn compute<R: Read>(mut reader: R, block_size: u32) -> io::Result<Self> {
+        let mut blocks = Vec::new();
+        let mut file_len = 0;
+        let mut block = vec![0u8; block_size as usize];
+        loop {
+            let n = read_full(&mut reader, &mut block)?;
+            if n == 0 {
+                break;
+            }
+            file_len += n as u64;
+            blocks.push(BlockSignature { weak: Rolling::new(&block[..n]).digest(), strong: strong(&block[..n]) });
+            if n < block.len() {
+                break;
+            }
+        }
+        Ok(Signatures { block_size, file_len, blocks })
+    }
+
+    /// Return the dimension of a block, shorter for the last one.
+    fn block_len(&self, index: usize) -> u64 {
+        let start = index as u64 * self.block_size as u64;
+        (self.file_len - start).min(self.block_size as u64)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SIGNATURES_HEADER_LEN + self.blocks.len() * BLOCK_SIGNATURE_LEN);
+        bytes.extend_from_slice(&self.block_size.to_be_bytes());
+        bytes.extend_from_slice(&self.file_len.to_be_bytes());
+        for block in &self.blocks {
+            bytes.extend_from_slice(&block.weak.to_be_bytes());
+            bytes.extend_from_slice(&block.strong);
+        }
+        bytes
+    }
+
+    /// Return `None` if the bytes are not valid signatures.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let block_size = u32::from_be_bytes(bytes.get(0..4)?.try_into().ok()?);
+        let file_len = u64::from_be_bytes(bytes.get(4..12)?.try_into().ok()?);
+        let blocks_len = bytes.len() - SIGNATURES_HEADER_LEN;
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) || !blocks_len.is_multiple_of(BLOCK_SIGNATURE_LEN) || file_len.div_ceil(block_size as u64) != (blocks_len / BLOCK_SIGNATURE_LEN) as u64 {
+            return None;
+        }
+        let blocks = bytes[SIGNATURES_HEADER_LEN..].chunks(BLOCK_SIGNATURE_LEN)
+            .map(|block| Some(BlockSignature {
+                weak: u32::from_be_bytes(block.get(0..4)?.try_into().ok()?),
+                strong: block.get(4..)?.try_into().ok()?
+            }))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Signatures { block_size, file_len, blocks })
+    }
+}
+
+
+/// An operation of a delta.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeltaOp {
+    /// Copy the block of the old copy with this index
+    Block(u32),
+    /// Copy the data of the new file at this offset, of this dimension
+    Data { offset: u64, len: u64 }
+}
+impl DeltaOp {
+    /// Return the encoding of the operation, without the data.
+    pub fn encode_header(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(9);
+        match self {
+            DeltaOp::Block(index) => {
+                bytes.push(TAG_BLOCK);
+                bytes.extend_from_slice(&index.to_be_bytes());
+            },
+            DeltaOp::Data { len, .. } => {
+                bytes.push(TAG_DATA);
+                bytes.extend_from_slice(&len.to_be_bytes());
+            }
+        };
+        bytes
+    }
+}
+
+/// The delta of a new file against the signatures of an old copy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub block_size: u32,
+    pub ops: Vec<DeltaOp>,
+    /// SHA-256 of the new file
+    pub checksum: [u8; 32]
+}
+impl Delta {
+    /// Return the dimension of the encoded delta.
+    pub fn encoded_len(&self) -> u64 {
+        let ops: u64 = self.ops.iter().map(|op| match op {
+            DeltaOp::Block(_) => 5,
+            DeltaOp::Data { len, .. } => 9 + len
+        }).sum();
+        4 + ops + 1 + 32
+    }
+
+    /// Return the bytes at the start of the encoded delta.
+    pub fn encode_header(&self) -> [u8; 4] {
+        self.block_size.to_be_bytes()
+    }
+
+    /// Return the bytes at the end of the encoded delta.
+    pub fn encode_trailer(&self) -> [u8; 33] {
+        let mut bytes = [0u8; 33];
+        bytes[0] = TAG_END;
+        bytes[1..].copy_from_slice(&self.checksum);
+        bytes
+    }
+
+    /// Write the whole encoded delta, reading the data from the new file.
+    pub fn write<R: Read + Seek, W: Write>(&self, mut new_file: R, mut out: W) -> io::Result<()> {
+        out.write_all(&self.encode_header())?;
+        for op in &self.ops {
+            out.write_all(&op.encode_header())?;
+            if let DeltaOp::Data { offset, len } = op {
+                new_file.seek(SeekFrom::Start(*offset))?;
+                copy_exact(&mut new_file, &mut out, *len)?;
+            }
+        }
+        out.write_all(&self.encode_trailer())
+    }
+}
+
+
+/// Read the new file and return its delta against the signatures of the old copy.
+/// The file is read once, keeping in memory only a block and a chunk.
+pub fn compute_delta<R: Read>(reader: R, signatures: &Signatures) -> io::Result<Delta> {
+    let block_size = signatures.block_size as usize;
+    let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (index, block) in signatures.blocks.iter().enumerate() {
+        by_weak.entry(block.weak).or_default().push(index);
+    }
+    let find = |weak: u32, window: &[u8]| -> Option<usize> {
+        let candidates = by_weak.get(&weak)?;
+        let strong_window = strong(window);
+        candidates.iter().copied().find(|&index| {
+            signatures.block_len(index) == window.len() as u64 && signatures.blocks[index].strong == strong_window
+        })
+    };
+
+    let mut window = Window { reader, buf: Vec::new(), chunk: vec![0u8; CHUNK_SIZE], start: 0, eof: false, hasher: Sha256::new() };
+    let mut ops = Vec::new();
+    let mut pos: u64 = 0;
+    let mut literal_start: u64 = 0;
+    let mut rolling: Option<Rolling> = None;
+    let push_literal = |ops: &mut Vec<DeltaOp>, start: u64, end: u64| {
+        if end > start {
+            ops.push(DeltaOp::Data { offset: start, len: end - start });
+        }
+    };
+
+    loop {
+        let available = window.fill(pos, block_size + 1)?;
+        if available >= block_size && block_size > 0 {
+            let bytes = window.at(pos);
+            let current = rolling.get_or_insert_with(|| Rolling::new(&bytes[..block_size]));
+            if let Some(index) = find(current.digest(), &bytes[..block_size]) {
+                push_literal(&mut ops, literal_start, pos);
+                ops.push(DeltaOp::Block(index as u32));
+                pos += block_size as u64;
+                literal_start = pos;
+                rolling = None;
+                continue;
+            }
+            if available > block_size {
+                current.roll(bytes[0], bytes[block_size]);
+            } else {
+                rolling = None;
+            }
+            pos += 1;
+            continue;
+        }
+
+        // the end of the file, shorter than a block: it can match only a shorter last block
+        let end = pos + available as u64;
+        if let Some(last) = signatures.blocks.len().checked_sub(1) {
+            let last_len = signatures.block_len(last);
+            if last_len < block_size as u64 && last_len <= available as u64 {
+                let tail = end - last_len;
+                let bytes = &window.at(tail)[..last_len as usize];
+                if find(Rolling::new(bytes).digest(), bytes) == Some(last) {
+                    push_literal(&mut ops, literal_start, tail);
+                    ops.push(DeltaOp::Block(last as u32));
+                    literal_start = end;
+                }
+            }
+        }
+        push_literal(&mut ops, literal_start, end);
+        break;
+    }
+    Ok(Delta { block_size: signatures.block_size, ops, checksum: window.hasher.finalize().into() })
+}
+
+/// The part of the file being read by `compute_delta`, hashed as it's read.
+struct Window<R> {
+    reader: R,
+    buf: Vec<u8>,
+    chunk: Vec<u8>,
+    /// Offset in the file of the first byte of the buffer
+    start: u64,
+    eof: bool,
+    hasher: Sha256
+}
+impl<R: Read> Window<R> {
+    /// Make sure that `len` bytes from `pos` are in the buffer, unless the file ends
+    /// before: the bytes before `pos` are dropped when more are read.
+    /// Return the bytes available from `pos`.
+    fn fill(&mut self, pos: u64, len: usize) -> io::Result<usize> {
+        let offset = (pos - self.start) as usize;
+        if self.buf.len() - offset < len && !self.eof {
+            self.buf.drain(..offset);
+            self.start = pos;
+            while self.buf.len() < len && !self.eof {
+                let n = self.reader.read(&mut self.chunk)?;
+                self.eof = n == 0;
+                self.hasher.update(&self.chunk[..n]);
+                self.buf.extend_from_slice(&self.chunk[..n]);
+            }
+        }
+        Ok(self.buf.len() - (pos - self.start) as usize)
+    }
+
+    fn at(&self, pos: u64) -> &[u8] {
+        &self.buf[(pos - self.start) as usize..]
+    }
+}
+
+
+/// Rebuild the new file from an encoded delta and the old copy, checking its SHA-256.
+/// Return the dimension of the new file.
+pub fn apply_delta<D: Read, B: Read + Seek, W: Write>(mut delta: D, mut old_copy: B, out: W) -> io::Result<u64> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut out = HashingWriter { inner: out, hasher: Sha256::new(), written: 0 };
+    let block_size = u32::from_be_bytes(read_array(&mut delta)?) as u64;
+
+    loop {
+        let [tag] = read_array(&mut delta)?;
+        match tag {
+            TAG_BLOCK => {
+                let index = u32::from_be_bytes(read_array(&mut delta)?) as u64;
+                old_copy.seek(SeekFrom::Start(index * block_size))?;
+                if io::copy(&mut (&mut old_copy).take(block_size), &mut out)? == 0 {
+                    return Err(invalid("block outside the old copy"));
+                }
+            },
+            TAG_DATA => {
+                let len = u64::from_be_bytes(read_array(&mut delta)?);
+                copy_exact(&mut delta, &mut out, len)?;
+            },
+            TAG_END => {
+                let checksum: [u8; 32] = read_array(&mut delta)?;
+                out.flush()?;
+                if out.hasher.finalize()[..] != checksum {
+                    return Err(invalid("the checksum of the rebuilt file doesn't match"));
+                }
+                return Ok(out.written);
+            },
+            _ => return Err(invalid("unknown delta operation"))
+        }
+    }
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    written: u64
+}
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read until the buffer is full or the end of the input, returning the bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 => break,
+            read => n += read
+        }
+    }
+    Ok(n)
+}
+
+fn copy_exact<R: Read, W: Write>(reader: &mut R, writer: &mut W, len: u64) -> io::Result<()> {
+    if io::copy(&mut reader.take(len), writer)? != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::io::Cursor;
+    use super::*;
+
+    fn file(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn round_trip(old: &[u8], new: &[u8], block_size: u32) -> Delta {
+        let signatures = Signatures::compute(Cursor::new(old), block_size).unwrap();
+        assert_eq!(Signatures::decode(&signatures.encode()), Some(signatures.clone()));
+
+        let delta = compute_delta(Cursor::new(new), &signatures).unwrap();
+        let mut encoded = Vec::new();
+        delta.write(Cursor::new(new), &mut encoded).unwrap();
+        assert_eq!(encoded.len() as u64, delta.encoded_len());
+
+        let mut rebuilt = Vec::new();
+        assert_eq!(apply_delta(Cursor::new(&encoded), Cursor::new(old), &mut rebuilt).unwrap(), new.len() as u64);
+        assert_eq!(rebuilt, new);
+        delta
+    }
+
+    fn data_len(delta: &Delta) -> u64 {
+        delta.ops.iter().map(|op| match op {
+            DeltaOp::Data { len, .. } => *len,
+            DeltaOp::Block(_) => 0
+        }).sum()
+    }
+
+    #[test]
+    fn rolling_should_match_the_checksum_of_the_window() {
+        let bytes = file(100, 1);
+        let mut rolling = Rolling::new(&bytes[0..20]);
+        for i in 0..80 {
+            rolling.roll(bytes[i], bytes[i + 20]);
+            assert_eq!(rolling.digest(), Rolling::new(&bytes[i + 1..i + 21]).digest());
+        }
+    }
+
+    #[test]
+    fn delta_of_a_small_change_should_send_only_the_changed_block() {
+        let old = file(100_000, 1);
+        let mut new = old.clone();
+        new[50_000] ^= 0xff;
+
+        let delta = round_trip(&old, &new, 2048);
+        assert_eq!(data_len(&delta), 2048);
+    }
+
+    #[test]
+    fn delta_should_find_moved_blocks_and_the_short_last_block() {
+        let old = file(10_500, 2);
+        let mut new = b"inserted at the start".to_vec();
+        new.extend_from_slice(&old);
+
+        let delta = round_trip(&old, &new, 2048);
+        assert_eq!(data_len(&delta), 21);
+        assert_eq!(delta.ops.last(), Some(&DeltaOp::Block(5)));
+    }
+
+    #[test]
+    fn delta_should_work_with_empty_and_different_files() {
+        round_trip(b"", &file(5000, 3), 2048);
+        round_trip(&file(5000, 3), b"", 2048);
+        let delta = round_trip(&file(5000, 3), &file(3000, 4), 2048);
+        assert_eq!(data_len(&delta), 3000);
+    }
+
+    #[test]
+    fn apply_delta_with_a_wrong_old_copy_should_return_err() {
+        let old = file(10_000, 5);
+        let mut new = old.clone();
+        new[0] ^= 1;
+        let signatures = Signatures::compute(Cursor::new(&old), 2048).unwrap();
+        let mut encoded = Vec::new();
+        compute_delta(Cursor::new(&new), &signatures).unwrap().write(Cursor::new(&new), &mut encoded).unwrap();
+
+        let other = file(10_000, 6);
+        let error = apply_delta(Cursor::new(&encoded), Cursor::new(&other), &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decode_should_refuse_blocks_under_the_min_size() {
+        let signatures = Signatures::compute(Cursor::new(file(256, 7)), 1).unwrap();
+        assert_eq!(Signatures::decode(&signatures.encode()), None);
+        let signatures = Signatures::compute(Cursor::new(file(256, 7)), MIN_BLOCK_SIZE).unwrap();
+        assert!(Signatures::decode(&signatures.encode()).is_some());
+    }
+
+    #[test]
+    fn block_size_should_grow_with_the_file() {
+        assert_eq!(block_size_for(0), 2048);
+        assert_eq!(block_size_for(1 << 32), 65536);
+        assert_eq!(block_size_for(u64::MAX), 1 << 20);
+    }
+}
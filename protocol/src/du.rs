@@ -0,0 +1,107 @@
+//! Payload of the DU response: for each requested path, in the order of the request,
+//! its total dimension and number of files as big endian u64, the number of its
+//! children as big endian u32, then for each child its name (u16 length + name),
+//! dimension and number of files. The children are sent only if requested.
+//!
+//! The request payload is one byte: `CHILDREN` to break the totals down per
+//! immediate child, `TOTALS` otherwise.
+
+/// Request payload: only the totals of each path
+pub const TOTALS: u8 = 0;
+
+/// Request payload: the totals and the usage of each immediate child
+pub const CHILDREN: u8 = 1;
+
+
+/// Space used by a file or a directory, with everything under it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    /// Sum of the dimensions of the files, in bytes
+    pub size: u64,
+    /// Number of files, directories excluded
+    pub files: u64
+}
+impl Usage {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.files.to_be_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(Usage {
+            size: u64::from_be_bytes(bytes.get(..8)?.try_into().ok()?),
+            files: u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?)
+        })
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.size = self.size.saturating_add(other.size);
+        self.files = self.files.saturating_add(other.files);
+    }
+}
+
+
+/// Usage of a requested path and, if requested, of its immediate children.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathUsage {
+    pub total: Usage,
+    /// Name and usage of each child, sorted by name
+    pub children: Vec<(String, Usage)>
+}
+
+
+/// Return the bytes of the DU response payload, `None` if a name is too long.
+pub fn encode_usages(usages: &[PathUsage]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for usage in usages {
+        usage.total.encode(&mut bytes);
+        bytes.extend_from_slice(&u32::try_from(usage.children.len()).ok()?.to_be_bytes());
+        for (name, child) in &usage.children {
+            bytes.extend_from_slice(&u16::try_from(name.len()).ok()?.to_be_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            child.encode(&mut bytes);
+        }
+    }
+    Some(bytes)
+}
+
+/// Return the usages in a DU response payload, `None` if it's malformed.
+pub fn decode_usages(mut bytes: &[u8]) -> Option<Vec<PathUsage>> {
+    let mut usages = Vec::new();
+    while !bytes.is_empty() {
+        let total = Usage::decode(bytes)?;
+        let n_children = u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?);
+        bytes = &bytes[20..];
+        let mut children = Vec::new();
+        for _ in 0..n_children {
+            let len = u16::from_be_bytes(bytes.get(..2)?.try_into().ok()?) as usize;
+            let name = String::from_utf8_lossy(bytes.get(2..2 + len)?).into_owned();
+            children.push((name, Usage::decode(&bytes[2 + len..])?));
+            bytes = &bytes[18 + len..];
+        }
+        usages.push(PathUsage { total, children });
+    }
+    Some(usages)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn decode_should_return_the_encoded_usages() {
+        let usages = vec![
+            PathUsage {
+                total: Usage { size: 1 << 33, files: 3 },
+                children: vec![("a.txt".to_string(), Usage { size: 5, files: 1 }), ("docs".to_string(), Usage { size: (1 << 33) - 5, files: 2 })]
+            },
+            PathUsage { total: Usage { size: 0, files: 0 }, children: vec![] }
+        ];
+        let bytes = encode_usages(&usages).unwrap();
+        assert_eq!(decode_usages(&bytes), Some(usages));
+        assert_eq!(decode_usages(&bytes[..bytes.len() - 1]), None);
+    }
+}
@@ -0,0 +1,55 @@
+//! Istruction codes of the version 1.0.
+
+/// Download a file: one path, the response payload is the file
+pub const GET: u8 = 0;
+
+/// Serialized tree of the user space: no paths, the response payload is the tree
+pub const LIST: u8 = 1;
+
+/// Upload a file: one path, the payload is the file; an existing file is replaced
+pub const PUT: u8 = 2;
+
+/// Authentication: one path (the username) and the password as payload,
+/// or no paths and a pre-shared API token as payload
+pub const LOGIN: u8 = 3;
+
+/// Remove a file or an empty directory: one path
+pub const RM: u8 = 4;
+
+/// Create a directory: one path, its parent must exist
+pub const MKDIR: u8 = 5;
+
+/// Move or rename: two paths, the source and the destination, that must not exist
+pub const MV: u8 = 6;
+
+/// Metadata of a path: one path, the response payload is a `Stat`
+pub const STAT: u8 = 7;
+
+/// SHA-256 of a file: one path, the response payload is the 32 Byte digest
+pub const CHECKSUM: u8 = 8;
+
+/// Delta download: one path, the payload is the `Signatures` of the old copy of the
+/// client, the response payload is the `Delta` of the file against them
+pub const GET_DELTA: u8 = 9;
+
+/// Signatures of a file: one path, the response payload is its `Signatures`
+pub const SIGNATURES: u8 = 10;
+
+/// Delta upload: one path, the payload is the `Delta` of the new file against the
+/// `Signatures` of the file on the server, that is replaced
+pub const PUT_DELTA: u8 = 11;
+
+/// Events under a path: one path, a file or a directory; after the OK response the
+/// server sends a `watch::Event` for each change, until the client unsubscribes
+pub const WATCH: u8 = 12;
+
+/// Search under a path: one path, a directory, and a `search::Query` as payload; after
+/// the OK response the server sends the matching paths, then the end of the results
+pub const SEARCH: u8 = 13;
+
+/// Disk usage: one or more paths and a `du` option as payload; the response payload
+/// is the total dimension and number of files of each path, optionally per child
+pub const DU: u8 = 14;
+
+/// Capacity of the server: no paths, the response payload is a `space::Space`
+pub const SPACE: u8 = 15;
\ No newline at end of file
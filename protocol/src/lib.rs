@@ -0,0 +1,19 @@
+//! Types and constants of the nFTP protocol (see `nFTP_documentation.txt` in the
+//! server), shared by the server and the client so that they can't drift apart.
+
+pub mod delta;
+pub mod du;
+pub mod istruction;
+pub mod request;
+pub mod response;
+pub mod search;
+pub mod space;
+pub mod stat;
+pub mod tree;
+pub mod watch;
+
+/// Name of the protocol, at the start of every request and response
+pub const MAGIC: &[u8; 4] = b"nFTP";
+
+/// Version 1.0: the first 4 bits are the major, the last 4 bits the minor
+pub const VERSION_1_0: u8 = 0b0001_0000;
@@ -0,0 +1,120 @@
+//! Request of the nFTP protocol: magic, version, istruction code, the paths
+//! (each one with its u16 length) and an optional payload (with its u64 length).
+
+use std::fmt;
+use super::{MAGIC, VERSION_1_0};
+
+/// Max number of paths in a request
+pub const MAX_PATHS: u8 = 10;
+
+/// Max dimension of a request: the server reads each request at once, in a buffer
+/// of this dimension
+pub const MAX_REQUEST_LEN: usize = 1024;
+
+/// A version 1.0 request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub istruction: u8,
+    pub paths: Vec<String>,
+    pub payload: Option<Vec<u8>>
+}
+impl Request {
+    pub fn new(istruction: u8) -> Self {
+        Request { istruction, paths: Vec::new(), payload: None }
+    }
+
+    /// Add a path to the request.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Set the payload of the request.
+    pub fn payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = Some(payload.into());
+        self
+    }
+
+    /// Return the bytes of the request without its payload, but with the dimension
+    /// of the payload that will follow, to stream it. Checks the limits of the protocol.
+    pub fn encode_with_payload_dim(&self, payload_dim: u64) -> Result<Vec<u8>, EncodeError> {
+        let mut bytes = Request { payload: None, ..self.clone() }.encode()?;
+        bytes.extend_from_slice(&payload_dim.to_be_bytes());
+        Ok(bytes)
+    }
+
+    /// Return the bytes of the request, checking the limits of the protocol.
+    pub fn encode(&self) -> Result<Vec<u8>, EncodeError> {
+        if self.paths.len() > MAX_PATHS as usize {
+            return Err(EncodeError::TooManyPaths);
+        }
+
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION_1_0);
+        bytes.push(self.istruction);
+        bytes.push(self.paths.len() as u8);
+        for path in &self.paths {
+            let len = u16::try_from(path.len()).map_err(|_| EncodeError::TooLong)?;
+            bytes.extend_from_slice(&len.to_be_bytes());
+            bytes.extend_from_slice(path.as_bytes());
+        }
+        if let Some(payload) = &self.payload {
+            bytes.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(payload);
+        }
+
+        if bytes.len() > MAX_REQUEST_LEN {
+            return Err(EncodeError::TooLong);
+        }
+        Ok(bytes)
+    }
+}
+
+
+/// A request that can't be encoded within the limits of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EncodeError {
+    /// More than `MAX_PATHS` paths.
+    TooManyPaths,
+    /// Longer than `MAX_REQUEST_LEN` bytes.
+    TooLong
+}
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::TooManyPaths => write!(f, "more than {} paths in the request", MAX_PATHS),
+            EncodeError::TooLong => write!(f, "the request is longer than {} bytes", MAX_REQUEST_LEN)
+        }
+    }
+}
+impl std::error::Error for EncodeError {}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::istruction::{GET, LOGIN, PUT};
+
+    #[test]
+    fn encode_should_return_the_bytes_of_the_request() {
+        let bytes = Request::new(GET).path("dir/a.txt").encode().unwrap();
+        assert_eq!(bytes, b"nFTP\x10\x00\x01\x00\x09dir/a.txt");
+
+        let bytes = Request::new(LOGIN).payload(b"tok".to_vec()).encode().unwrap();
+        assert_eq!(bytes, b"nFTP\x10\x03\x00\x00\x00\x00\x00\x00\x00\x00\x03tok");
+
+        let bytes = Request::new(PUT).path("a").encode_with_payload_dim(1 << 32).unwrap();
+        assert_eq!(bytes, b"nFTP\x10\x02\x01\x00\x01a\x00\x00\x00\x01\x00\x00\x00\x00");
+    }
+
+    #[test]
+    fn encode_over_the_limits_should_return_err() {
+        let mut request = Request::new(GET);
+        for _ in 0..=MAX_PATHS {
+            request = request.path("a");
+        }
+        assert_eq!(request.encode(), Err(EncodeError::TooManyPaths));
+        assert_eq!(Request::new(GET).path("a".repeat(MAX_REQUEST_LEN)).encode(), Err(EncodeError::TooLong));
+    }
+}
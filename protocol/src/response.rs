@@ -0,0 +1,194 @@
+//! Response of the nFTP protocol: the header, with its response code, and the
+//! optional dimension of the payload that follows it.
+
+use super::{MAGIC, VERSION_1_0};
+
+/// Dimension of a response header without the payload dimension
+pub const HEADER_LEN: usize = 6;
+
+/// Response code OK
+pub const RC_OK: u8 = 1;
+
+/// Response code GENERIC ERROR
+pub const RC_ERROR: u8 = 100;
+
+/// Response code AUTHENTICATION FAILED: wrong credentials, or an istruction
+/// sent before a successful LOGIN
+pub const RC_AUTH_FAILED: u8 = 101;
+
+/// Response code LOCKED OUT: too many failed logins from the same address
+pub const RC_LOCKED_OUT: u8 = 102;
+
+/// Response code PERMISSION DENIED: the user has not the right to access the path
+pub const RC_PERMISSION_DENIED: u8 = 103;
+
+/// Response code SERVER BUSY: too many sessions, in total or from the same address
+pub const RC_SERVER_BUSY: u8 = 104;
+
+/// Response code TIMEOUT: the client was too slow to send a request or to receive
+/// a transfer; the connection is closed
+pub const RC_TIMEOUT: u8 = 105;
+
+/// Response code QUOTA EXCEEDED: the upload doesn't fit in a quota of the user or
+/// of a directory
+pub const RC_QUOTA_EXCEEDED: u8 = 106;
+
+/// Represents a response header for the nFTP protocol.
+pub struct ResponseHeader {
+    header_bytes: Vec<u8>
+}
+impl ResponseHeader {
+    pub fn new(version_major: u8, version_minor: u8, response_code: u8, payload_dim: Option<u64>) -> Self {
+        let mut output_bytes = Vec::with_capacity(
+            if payload_dim.is_some() {14} else {6}
+        );
+
+        output_bytes.extend_from_slice(MAGIC);
+        output_bytes.push((version_major << 4) | version_minor);
+        output_bytes.push(response_code);
+        if let Some(payload_dim) = payload_dim {
+            output_bytes.extend_from_slice(&payload_dim.to_be_bytes());
+        }
+
+        ResponseHeader { header_bytes: output_bytes }
+    }
+
+    #[inline]
+    pub fn set_new_version(&mut self, version_major: u8, version_minor: u8) {
+        self.header_bytes[4] = (version_major << 4) | version_minor;
+    }
+
+    #[inline]
+    pub fn set_new_response_code(&mut self, response_code: u8) {
+        self.header_bytes[5] = response_code;
+    }
+
+    #[inline]
+    pub fn set_new_payload_dim(&mut self, payload_dim: Option<u64>) {
+        if self.header_bytes.len() > 6 {
+            match payload_dim {
+                Some(payload_dim) => {
+                    self.header_bytes[6..14].copy_from_slice(&payload_dim.to_be_bytes());
+                },
+                None => for _ in 0..8 { 
+                    self.header_bytes.pop(); 
+                }
+            };
+        } else if let Some(payload_dim) = payload_dim {
+            self.header_bytes.extend_from_slice(&payload_dim.to_be_bytes());
+        }
+    }
+
+    #[inline]
+    pub fn get_header(&self) -> &Vec<u8> {
+        &self.header_bytes
+    }
+}
+
+
+/// Check the magic and the version of a response header and return its response code.
+/// Return `None` if the bytes are not a version 1.0 response header.
+pub fn response_code(header: &[u8; HEADER_LEN]) -> Option<u8> {
+    if &header[0..4] != MAGIC || header[4] != VERSION_1_0 {
+        return None;
+    }
+    Some(header[5])
+}
+
+
+/// Reassembles a vector of 8 bytes into a 64-bit unsigned integer.
+/// Utility function.
+pub fn reassemble_u64_from_bytes(bytes: &[u8]) -> u64 {
+    let mut res: u64 = 0;
+    let mut b: u8 = 64;
+    for byte in bytes.iter().take(8) {
+        b -= 8;
+        res |= (*byte as u64) << b;
+    }
+    res
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn response_code_should_check_magic_and_version() {
+        let header = ResponseHeader::new(1, 0, RC_PERMISSION_DENIED, None);
+        let header: [u8; HEADER_LEN] = header.get_header().as_slice().try_into().unwrap();
+        assert_eq!(response_code(&header), Some(RC_PERMISSION_DENIED));
+
+        let mut wrong_version = header;
+        wrong_version[4] = 0b0010_0000;
+        assert_eq!(response_code(&wrong_version), None);
+        assert_eq!(response_code(b"nftp\x10\x01"), None);
+    }
+
+    #[test]
+    fn reassemble_u64_from_bytes_should_return_the_correct_u64_number() {
+        let number = 18_446_744_073_709_551_615u64;
+        let bytes = number.to_be_bytes();
+        assert_eq!(number, reassemble_u64_from_bytes(&bytes));
+    }
+
+    #[test]
+    fn response_header_should_return_the_correct_array_of_bytes() {
+        let res = ResponseHeader::new(1, 2, 200, Some(18_446_744_073_709_551_615u64));
+        let output_bytes = res.get_header();
+
+        assert_eq!(output_bytes.len(), 14);
+        assert_eq!(&output_bytes[0..4], b"nFTP");
+        assert_eq!((output_bytes[4] & 0b1111_0000) >> 4, 1u8);
+        assert_eq!(output_bytes[4] & 0b0000_1111, 2u8);
+        assert_eq!(output_bytes[5], 200u8);
+        let payload_dim = reassemble_u64_from_bytes(&output_bytes[6..]);
+        assert_eq!(payload_dim, 18_446_744_073_709_551_615u64);
+    }
+
+    #[test]
+    fn response_header_with_none_payload_dim_should_return_the_correct_array_of_bytes() {
+        let res = ResponseHeader::new(1, 2, 200, None);
+        let output_bytes = res.get_header();
+
+        assert_eq!(output_bytes.len(), 6);
+        assert_eq!(&output_bytes[0..4], b"nFTP");
+        assert_eq!((output_bytes[4] & 0b1111_0000) >> 4, 1u8);
+        assert_eq!(output_bytes[4] & 0b0000_1111, 2u8);
+        assert_eq!(output_bytes[5], 200u8);
+    }
+
+    #[test]
+    fn response_header_after_setting_new_payload_dim_should_contains_the_new_payload_dim() {
+        let mut h = ResponseHeader::new(1, 0, 200, Some(18_446_744_073_709_551_615u64));
+        
+        let new_payload_dimension = 17_000_744_111_709_555_001u64;
+        h.set_new_payload_dim(Some(new_payload_dimension));
+        assert_eq!(reassemble_u64_from_bytes(&h.get_header()[6..]), new_payload_dimension);
+    }
+
+    #[test]
+    fn response_header_after_delete_payload_dim_should_not_contains_payload_dim() {
+        let mut h = ResponseHeader::new(1, 0, 200, Some(18_446_744_073_709_551_615u64));
+        
+        h.set_new_payload_dim(None);
+        assert_eq!(h.get_header().len(), 6);
+    }
+
+    #[test]
+    fn response_header_without_payload_dim_should_have_len_6_after_setting_none() {
+        let mut h = ResponseHeader::new(1, 0, 200, None);
+        
+        h.set_new_payload_dim(None);
+        assert_eq!(h.get_header().len(), 6);
+    }
+
+    #[test]
+    fn response_header_without_payload() {
+        let mut h = ResponseHeader::new(1, 0, 200, None);
+        
+        let new_payload_dimension = 17_000_744_111_709_555_001u64;
+        h.set_new_payload_dim(Some(new_payload_dimension));
+        assert_eq!(reassemble_u64_from_bytes(&h.get_header()[6..]), new_payload_dimension);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,141 @@
+//! Query and results of the SEARCH istruction.
+//!
+//! Query (the request payload): kind of pattern (0 glob, 1 regex), min and max dimension,
+//! modified after and before (seconds since the Unix epoch), as big endian u64, then
+//! the pattern in UTF-8. A filter that is not set is 0 for the minimums and `u64::MAX`
+//! for the maximums.
+//!
+//! Results (after the OK response): for each match the path (u16 length + path) and its
+//! `Stat`, then a u16 zero that ends the results.
+
+use crate::stat::{Stat, STAT_LEN};
+
+const GLOB: u8 = 0;
+const REGEX: u8 = 1;
+const QUERY_HEADER_LEN: usize = 33;
+
+/// The bytes that end the results
+pub const END: [u8; 2] = [0, 0];
+
+
+/// How the paths are matched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// A glob matched against the name or, if it contains a `/`, against the path
+    /// relative to the starting directory: `*` doesn't cross the `/`, `**` does
+    Glob(String),
+    /// A regular expression searched in the whole path of the user space
+    Regex(String)
+}
+
+/// What a SEARCH looks for. With a dimension filter only files match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub pattern: Pattern,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub modified_after: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub modified_before: Option<u64>
+}
+impl Query {
+    /// A query matching the pattern, without filters.
+    pub fn new(pattern: Pattern) -> Self {
+        Query { pattern, min_size: None, max_size: None, modified_after: None, modified_before: None }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, pattern) = match &self.pattern {
+            Pattern::Glob(pattern) => (GLOB, pattern),
+            Pattern::Regex(pattern) => (REGEX, pattern)
+        };
+        let mut bytes = Vec::with_capacity(QUERY_HEADER_LEN + pattern.len());
+        bytes.push(kind);
+        for (value, unset) in [(self.min_size, 0), (self.max_size, u64::MAX), (self.modified_after, 0), (self.modified_before, u64::MAX)] {
+            bytes.extend_from_slice(&value.unwrap_or(unset).to_be_bytes());
+        }
+        bytes.extend_from_slice(pattern.as_bytes());
+        bytes
+    }
+
+    /// Return `None` if the bytes are not a query.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let header = bytes.get(..QUERY_HEADER_LEN)?;
+        let value = |i: usize, unset: u64| {
+            let value = u64::from_be_bytes(header[1 + i * 8..9 + i * 8].try_into().unwrap_or_default());
+            (value != unset).then_some(value)
+        };
+        let pattern = String::from_utf8(bytes[QUERY_HEADER_LEN..].to_vec()).ok()?;
+        let pattern = match header[0] {
+            GLOB => Pattern::Glob(pattern),
+            REGEX => Pattern::Regex(pattern),
+            _ => return None
+        };
+        Some(Query {
+            pattern,
+            min_size: value(0, 0),
+            max_size: value(1, u64::MAX),
+            modified_after: value(2, 0),
+            modified_before: value(3, u64::MAX)
+        })
+    }
+
+    /// Return true if the metadata passes the dimension and time filters.
+    pub fn filters(&self, stat: &Stat) -> bool {
+        let sized = self.min_size.is_some() || self.max_size.is_some();
+        !(sized && stat.is_dir)
+            && self.min_size.is_none_or(|min| stat.size >= min)
+            && self.max_size.is_none_or(|max| stat.size <= max)
+            && self.modified_after.is_none_or(|after| stat.modified > after)
+            && self.modified_before.is_none_or(|before| stat.modified < before)
+    }
+}
+
+
+/// A path found by a SEARCH, in the user space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub path: String,
+    pub stat: Stat
+}
+impl Match {
+    /// Return the bytes of the match, `None` if the path is empty or too long.
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let len = u16::try_from(self.path.len()).ok().filter(|len| *len > 0)?;
+        let mut bytes = Vec::with_capacity(2 + self.path.len() + STAT_LEN);
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(self.path.as_bytes());
+        bytes.extend_from_slice(&self.stat.encode());
+        Some(bytes)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn decode_should_return_the_encoded_query() {
+        let query = Query { min_size: Some(1024), modified_before: Some(1_700_000_000), ..Query::new(Pattern::Regex(r"\.pdf$".to_string())) };
+        assert_eq!(Query::decode(&query.encode()), Some(query));
+
+        let query = Query::new(Pattern::Glob("*.txt".to_string()));
+        assert_eq!(Query::decode(&query.encode()), Some(query));
+        assert_eq!(Query::decode(&[GLOB; 10]), None);
+    }
+
+    #[test]
+    fn filters_should_check_the_dimension_and_the_time() {
+        let file = Stat { is_dir: false, size: 100, modified: 1000 };
+        let dir = Stat { is_dir: true, size: 4096, modified: 1000 };
+        let query = |min_size, modified_after| Query { min_size, modified_after, ..Query::new(Pattern::Glob("*".to_string())) };
+
+        assert!(query(None, None).filters(&dir));
+        assert!(query(Some(100), Some(999)).filters(&file));
+        assert!(!query(Some(101), None).filters(&file));
+        assert!(!query(None, Some(1000)).filters(&file));
+        assert!(!query(Some(1), None).filters(&dir));
+    }
+}
@@ -0,0 +1,70 @@
+//! Payload of the SPACE response: total and available dimension of the filesystem
+//! holding the served files, then the remaining byte and file quota of the user, as
+//! big endian u64. A quota that is not configured is `u64::MAX`.
+
+/// Dimension of the SPACE payload
+pub const SPACE_LEN: usize = 32;
+
+
+/// Capacity of the server, as seen by the logged user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Space {
+    /// Dimension of the filesystem, in bytes
+    pub total: u64,
+    /// Bytes that can still be written on the filesystem
+    pub available: u64,
+    /// Bytes the user can still upload, `None` without a byte quota
+    pub remaining_bytes: Option<u64>,
+    /// Files the user can still create, `None` without a file quota
+    pub remaining_files: Option<u64>
+}
+impl Space {
+    pub fn encode(&self) -> [u8; SPACE_LEN] {
+        let mut bytes = [0u8; SPACE_LEN];
+        let values = [self.total, self.available, self.remaining_bytes.unwrap_or(u64::MAX), self.remaining_files.unwrap_or(u64::MAX)];
+        for (i, value) in values.iter().enumerate() {
+            bytes[i * 8..(i + 1) * 8].copy_from_slice(&value.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Return `None` if the bytes are not a SPACE payload.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != SPACE_LEN {
+            return None;
+        }
+        let value = |i: usize| u64::from_be_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap_or_default());
+        let quota = |i: usize| Some(value(i)).filter(|value| *value != u64::MAX);
+        Some(Space { total: value(0), available: value(1), remaining_bytes: quota(2), remaining_files: quota(3) })
+    }
+
+    /// Return true if a new file of this dimension fits, on the filesystem and in the quotas.
+    pub fn fits(&self, size: u64) -> bool {
+        size <= self.available
+            && self.remaining_bytes.is_none_or(|remaining| size <= remaining)
+            && self.remaining_files.is_none_or(|remaining| remaining >= 1)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn decode_should_return_the_encoded_space() {
+        let space = Space { total: 1 << 40, available: 1 << 30, remaining_bytes: Some(1 << 20), remaining_files: None };
+        assert_eq!(Space::decode(&space.encode()), Some(space));
+        assert_eq!(Space::decode(&space.encode()[..31]), None);
+    }
+
+    #[test]
+    fn fits_should_check_the_filesystem_and_the_quotas() {
+        let space = Space { total: 1000, available: 500, remaining_bytes: None, remaining_files: None };
+
+        assert!(space.fits(500));
+        assert!(!space.fits(501));
+        assert!(!Space { remaining_bytes: Some(100), ..space }.fits(101));
+        assert!(!Space { remaining_files: Some(0), ..space }.fits(1));
+    }
+}
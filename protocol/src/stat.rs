@@ -0,0 +1,48 @@
+//! Payload of the STAT response: kind (0 file, 1 directory), dimension in bytes
+//! and last modification time in seconds since the Unix epoch, as big endian u64.
+
+/// Dimension of the STAT payload
+pub const STAT_LEN: usize = 17;
+
+/// Metadata of a path of the user space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stat {
+    pub is_dir: bool,
+    pub size: u64,
+    /// Last modification, in seconds since the Unix epoch
+    pub modified: u64
+}
+impl Stat {
+    pub fn encode(&self) -> [u8; STAT_LEN] {
+        let mut bytes = [0u8; STAT_LEN];
+        bytes[0] = self.is_dir as u8;
+        bytes[1..9].copy_from_slice(&self.size.to_be_bytes());
+        bytes[9..17].copy_from_slice(&self.modified.to_be_bytes());
+        bytes
+    }
+
+    /// Return `None` if the bytes are not a STAT payload.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != STAT_LEN || bytes[0] > 1 {
+            return None;
+        }
+        Some(Stat {
+            is_dir: bytes[0] == 1,
+            size: u64::from_be_bytes(bytes[1..9].try_into().ok()?),
+            modified: u64::from_be_bytes(bytes[9..17].try_into().ok()?)
+        })
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn decode_should_return_the_encoded_stat() {
+        let stat = Stat { is_dir: false, size: 1 << 40, modified: 1_700_000_000 };
+        assert_eq!(Stat::decode(&stat.encode()), Some(stat));
+        assert_eq!(Stat::decode(&stat.encode()[..16]), None);
+    }
+}
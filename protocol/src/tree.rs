@@ -0,0 +1,95 @@
+//! Tree of the user space, as serialized in the LIST response:
+//! after the name of each directory its contents are between `{` and `}`,
+//! after the name of each file there is a `,`.
+//!
+//! `/{dir_1{file.txt,dir_2{file.pdf,}}dir_3{}}`
+
+use std::{iter::Peekable, str::Chars};
+
+/// A file or a directory of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    File(String),
+    Dir { name: String, entries: Vec<Entry> }
+}
+impl Entry {
+    pub fn name(&self) -> &str {
+        match self {
+            Entry::File(name) => name,
+            Entry::Dir { name, .. } => name
+        }
+    }
+
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        matches!(self, Entry::Dir { .. })
+    }
+
+    /// Return the entry at the path, relative to this directory (a leading `/` is ignored).
+    pub fn find(&self, path: &str) -> Option<&Entry> {
+        let mut entry = self;
+        for name in path.split('/').filter(|name| !name.is_empty() && *name != ".") {
+            entry = match entry {
+                Entry::Dir { entries, .. } => entries.iter().find(|e| e.name() == name)?,
+                Entry::File(_) => return None
+            };
+        }
+        Some(entry)
+    }
+}
+
+
+/// Parse a serialized tree, returning its root directory.
+/// Return `None` if the serialization is malformed.
+pub fn parse_tree(serialized: &str) -> Option<Entry> {
+    let mut chars = serialized.chars().peekable();
+    let root = parse_entry(&mut chars)?;
+    if !root.is_dir() || chars.next().is_some() {
+        return None;
+    }
+    Some(root)
+}
+
+fn parse_entry(chars: &mut Peekable<Chars>) -> Option<Entry> {
+    let mut name = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            ',' => return Some(Entry::File(name)),
+            '{' => {
+                let mut entries = Vec::new();
+                while *chars.peek()? != '}' {
+                    entries.push(parse_entry(chars)?);
+                }
+                chars.next();
+                return Some(Entry::Dir { name, entries });
+            },
+            '}' => return None,
+            c => name.push(c)
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn parse_tree_should_return_the_entries() {
+        let root = parse_tree("/{dir_1{file.txt,dir_2{file.pdf,}}dir_3{}}").unwrap();
+
+        assert_eq!(root.name(), "/");
+        assert_eq!(root.find("dir_1/dir_2/file.pdf"), Some(&Entry::File("file.pdf".to_string())));
+        assert_eq!(root.find("/dir_3"), Some(&Entry::Dir { name: "dir_3".to_string(), entries: vec![] }));
+        assert!(root.find("dir_1/file.txt/x").is_none());
+        assert!(root.find("missing").is_none());
+    }
+
+    #[test]
+    fn parse_malformed_tree_should_return_none() {
+        assert!(parse_tree("/{dir_1{file.txt,}").is_none());
+        assert!(parse_tree("/{}}").is_none());
+        assert!(parse_tree("file.txt,").is_none());
+    }
+}
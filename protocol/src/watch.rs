@@ -0,0 +1,93 @@
+//! Events of the WATCH istruction. After the OK response, the server sends one event
+//! for each change under the watched path: its kind (1 byte), then its paths, each one
+//! with its u16 length. The client unsubscribes sending the `UNWATCH` byte, and the
+//! server answers with an `End` event, after which the requests are served again.
+
+/// Byte sent by the client to stop the events
+pub const UNWATCH: u8 = 0;
+
+const CREATE: u8 = 0;
+const MODIFY: u8 = 1;
+const DELETE: u8 = 2;
+const RENAME: u8 = 3;
+const OVERFLOW: u8 = 4;
+const END: u8 = 5;
+
+/// A change under the watched path; the paths are in the user space.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Create(String),
+    Modify(String),
+    Delete(String),
+    Rename { from: String, to: String },
+    /// Some events were lost: the client should LIST again
+    Overflow,
+    /// No more events: the client unsubscribed, or the server is shutting down
+    End
+}
+impl Event {
+    /// Return the number of paths that follow the kind of an event, `None` if the
+    /// kind doesn't exist.
+    pub fn n_paths(kind: u8) -> Option<usize> {
+        match kind {
+            CREATE | MODIFY | DELETE => Some(1),
+            RENAME => Some(2),
+            OVERFLOW | END => Some(0),
+            _ => None
+        }
+    }
+
+    /// Build an event from its kind and its paths, `None` if they don't match.
+    pub fn from_parts(kind: u8, paths: Vec<String>) -> Option<Self> {
+        let mut paths = paths.into_iter();
+        let event = match kind {
+            CREATE => Event::Create(paths.next()?),
+            MODIFY => Event::Modify(paths.next()?),
+            DELETE => Event::Delete(paths.next()?),
+            RENAME => Event::Rename { from: paths.next()?, to: paths.next()? },
+            OVERFLOW => Event::Overflow,
+            END => Event::End,
+            _ => return None
+        };
+        paths.next().is_none().then_some(event)
+    }
+
+    /// Return the bytes of the event. Paths longer than a u16 are truncated.
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, paths): (u8, Vec<&str>) = match self {
+            Event::Create(path) => (CREATE, vec![path]),
+            Event::Modify(path) => (MODIFY, vec![path]),
+            Event::Delete(path) => (DELETE, vec![path]),
+            Event::Rename { from, to } => (RENAME, vec![from, to]),
+            Event::Overflow => (OVERFLOW, vec![]),
+            Event::End => (END, vec![])
+        };
+        let mut bytes = vec![kind];
+        for path in paths {
+            let path = &path.as_bytes()[..path.len().min(u16::MAX as usize)];
+            bytes.extend_from_slice(&(path.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(path);
+        }
+        bytes
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn from_parts_should_rebuild_the_encoded_event() {
+        let event = Event::Rename { from: "/a.txt".to_string(), to: "/docs/a.txt".to_string() };
+        let bytes = event.encode();
+        assert_eq!(bytes[..3], [RENAME, 0, 6]);
+        assert_eq!(Event::n_paths(bytes[0]), Some(2));
+        assert_eq!(Event::from_parts(RENAME, vec!["/a.txt".to_string(), "/docs/a.txt".to_string()]), Some(event));
+
+        assert_eq!(Event::End.encode(), vec![END]);
+        assert_eq!(Event::from_parts(CREATE, vec![]), None);
+        assert_eq!(Event::from_parts(END, vec!["/a.txt".to_string()]), None);
+        assert_eq!(Event::n_paths(200), None);
+    }
+}
@@ -1,36 +1,95 @@
 mod server;
 
-use std::sync::Arc;
-use tokio::net::TcpListener;
-use crate::server::{parser::Parser, response::{send_error_response, RC_ERROR}};
+use std::{sync::Arc, io::BufRead};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+use crate::server::{
+    parser::Parser,
+    config::{Config, CONFIG_PATH},
+    auth::hash_password,
+    logging,
+    listener::Listener,
+    metrics,
+    shutdown::wait_for_signal
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    println!("Hello from nFTP server!"); 
+    if std::env::args().nth(1).as_deref() == Some("--hash-password") {
+        // Read a password from stdin and print the Argon2 hash for config.toml
+        let mut password = String::new();
+        std::io::stdin().lock().read_line(&mut password)?;
+        println!("{}", hash_password(password.trim_end_matches(['\r', '\n']).as_bytes())?);
+        return Ok(());
+    }
 
-    let server_parser = Arc::new(Parser::new());
-    let listener = match TcpListener::bind("127.0.0.1:3000").await {
-        Ok(listener) => listener,
-        Err(e) => panic!("{}", e)
+    let mut config = match Config::load(CONFIG_PATH) {
+        Ok(config) => config,
+        Err(e) => panic!("Read error in config.toml file: {}", e)
     };
-    println!("Listening on 127.0.0.1:3000");
-
-    loop {
-        let server_parser = Arc::clone(&server_parser);
-        let mut socket = match listener.accept().await {
-            Ok((socket, _)) => socket,
-            Err(e) => {
-                println!("{}\n", e);
-                continue
-            }
+    if let Err(e) = logging::init(&config.log) {
+        panic!("Logging setup failed: {}", e);
+    }
+    info!("Hello from nFTP server!");
+
+    let mut listeners = Vec::with_capacity(config.listen.len());
+    for listen_config in std::mem::take(&mut config.listen) {
+        match Listener::bind(&listen_config).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => panic!("{}", e)
         };
+    }
+
+    let metrics_listener = match config.metrics.address {
+        Some(address) => match tokio::net::TcpListener::bind(address).await {
+            Ok(listener) => Some(listener),
+            Err(e) => panic!("{}: {}", address, e)
+        },
+        None => None
+    };
+
+    let server_parser = Arc::new(Parser::new(config));
+    if let Some(metrics_listener) = metrics_listener {
+        info!(address = %metrics_listener.local_addr()?, "Metrics endpoint listening on /metrics");
+        tokio::spawn(metrics::serve_http(metrics_listener, Arc::clone(&server_parser)));
+    }
 
-        tokio::spawn(async move {
-            if !server_parser.process_request(&mut socket).await {
-                send_error_response(&mut socket, RC_ERROR).await;
-            }
-        });
+    let mut accept_tasks = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        info!(address = %listener.description(), "Listening");
+        accept_tasks.push(tokio::spawn(listener.accept(Arc::clone(&server_parser))));
     }
 
+    tokio::spawn(reload_on_hangup(Arc::clone(&server_parser)));
+
+    let signal = wait_for_signal().await?;
+    info!(signal, "Shutting down");
+    server_parser.shutdown.trigger();
+    for accept_task in accept_tasks {
+        accept_task.await?;
+    }
+    if server_parser.shutdown.drain().await {
+        info!("All the sessions are closed");
+    } else {
+        warn!("Grace period expired, interrupting the running transfers");
+    }
+    server_parser.audit.flush();
+
+    Ok(())
+}
+
+
+/// On SIGHUP, read config.toml again and apply its bandwidth limits.
+async fn reload_on_hangup(server_parser: Arc<Parser>) -> std::io::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        match Config::load(CONFIG_PATH) {
+            Ok(config) => {
+                info!(limits = ?config.throttle, "SIGHUP received, new bandwidth limits");
+                server_parser.throttle.set_rates(config.throttle);
+            },
+            Err(e) => warn!(error = %e, "SIGHUP received, but config.toml can't be read")
+        }
+    }
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, path::{Path, PathBuf, Component}};
+use serde::Deserialize;
+use super::config::AclRule;
+
+/// The rights that can be given on a path.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    List,
+    Write,
+    Delete
+}
+
+
+/// Access control lists: which rights users and groups have on the path prefixes
+/// of their spaces.
+///
+/// For each request, only the rules of the user (directly or through one of its groups)
+/// with the longest prefix of the path are considered, so a rule on a subdirectory
+/// overrides the rules on its parents. Without a matching rule, the access is denied.
+/// Without rules at all, every access is allowed.
+#[derive(Clone)]
+pub struct AccessControl {
+    rules: Vec<AclRule>,
+    groups: HashMap<String, Vec<String>>
+}
+impl AccessControl {
+    pub fn new(mut rules: Vec<AclRule>, groups: HashMap<String, Vec<String>>) -> Self {
+        for rule in rules.iter_mut() {
+            rule.path = normalize(&rule.path);
+        }
+        AccessControl { rules, groups }
+    }
+
+    /// Return true if the user has the permission on the path of its space.
+    pub fn is_allowed(&self, user: &str, path: &Path, permission: Permission) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+
+        let path = normalize(path);
+        let mut longest_prefix = 0;
+        let mut allowed = false;
+
+        for rule in self.rules.iter().filter(|rule| self.applies_to(rule, user) && path.starts_with(&rule.path)) {
+            let prefix = rule.path.components().count();
+            if prefix > longest_prefix {
+                longest_prefix = prefix;
+                allowed = false;
+            }
+            if prefix == longest_prefix {
+                allowed |= rule.allow.contains(&permission);
+            }
+        }
+        allowed
+    }
+
+    /// Return true if the user has the permission on the path and on everything under it,
+    /// i.e. also on the paths of the rules of the user below it.
+    pub fn is_allowed_under(&self, user: &str, path: &Path, permission: Permission) -> bool {
+        let path = normalize(path);
+        self.is_allowed(user, &path, permission) && self.rules.iter()
+            .filter(|rule| self.applies_to(rule, user) && rule.path.starts_with(&path))
+            .all(|rule| self.is_allowed(user, &rule.path, permission))
+    }
+
+    fn applies_to(&self, rule: &AclRule, user: &str) -> bool {
+        rule.users.iter().any(|u| u == user) || rule.groups.iter().any(|group| {
+            self.groups.get(group).is_some_and(|members| members.iter().any(|u| u == user))
+        })
+    }
+}
+
+
+/// Return the path as an absolute path without `.` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    normalized.extend(path.components().filter(|c| matches!(c, Component::Normal(_))));
+    normalized
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn rule(path: &str, users: &[&str], groups: &[&str], allow: &[Permission]) -> AclRule {
+        AclRule {
+            path: PathBuf::from(path),
+            users: users.iter().map(|u| u.to_string()).collect(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            allow: allow.to_vec()
+        }
+    }
+
+    fn access_control(rules: Vec<AclRule>) -> AccessControl {
+        let mut groups = HashMap::new();
+        groups.insert("dev".to_string(), vec!["alice".to_string(), "bob".to_string()]);
+        AccessControl::new(rules, groups)
+    }
+
+    #[test]
+    fn without_rules_everything_should_be_allowed() {
+        let acl = access_control(vec![]);
+        assert!(acl.is_allowed("alice", Path::new("/any/file"), Permission::Delete));
+    }
+
+    #[test]
+    fn without_matching_rules_access_should_be_denied() {
+        let acl = access_control(vec![rule("/docs", &["bob"], &[], &[Permission::Read])]);
+
+        assert!(acl.is_allowed("bob", Path::new("/docs/file.txt"), Permission::Read));
+        assert!(!acl.is_allowed("bob", Path::new("/docs/file.txt"), Permission::Write));
+        assert!(!acl.is_allowed("bob", Path::new("/docsx/file.txt"), Permission::Read));
+        assert!(!acl.is_allowed("alice", Path::new("/docs/file.txt"), Permission::Read));
+    }
+
+    #[test]
+    fn group_rules_should_apply_to_the_members() {
+        let acl = access_control(vec![rule("/", &[], &["dev"], &[Permission::Read, Permission::List])]);
+
+        assert!(acl.is_allowed("alice", Path::new("file.txt"), Permission::Read));
+        assert!(acl.is_allowed("bob", Path::new("/"), Permission::List));
+        assert!(!acl.is_allowed("carol", Path::new("/"), Permission::List));
+    }
+
+    #[test]
+    fn the_longest_prefix_should_override_the_parents() {
+        let acl = access_control(vec![
+            rule("/", &[], &["dev"], &[Permission::Read, Permission::Write]),
+            rule("/./private/", &["alice"], &[], &[]),
+            rule("/private", &["alice"], &[], &[Permission::List])
+        ]);
+
+        assert!(acl.is_allowed("alice", Path::new("/public/file.txt"), Permission::Write));
+        assert!(!acl.is_allowed("alice", Path::new("/private/file.txt"), Permission::Read));
+        assert!(acl.is_allowed("alice", Path::new("/private"), Permission::List));
+        assert!(acl.is_allowed("bob", Path::new("/private/file.txt"), Permission::Read));
+    }
+
+    #[test]
+    fn permission_under_a_path_should_need_every_rule_below_it() {
+        let acl = access_control(vec![
+            rule("/", &["alice"], &[], &[Permission::Delete]),
+            rule("/docs/private", &["alice"], &[], &[Permission::Read]),
+            rule("/docs/private", &["bob"], &[], &[Permission::Delete])
+        ]);
+
+        assert!(!acl.is_allowed_under("alice", Path::new("/docs"), Permission::Delete));
+        assert!(!acl.is_allowed_under("alice", Path::new("/"), Permission::Delete));
+        assert!(acl.is_allowed_under("alice", Path::new("/public"), Permission::Delete));
+        assert!(acl.is_allowed_under("bob", Path::new("/docs/private"), Permission::Delete));
+        assert!(acl.is_allowed_under("alice", Path::new("/docs/private"), Permission::Read));
+    }
+}
@@ -0,0 +1,305 @@
+use std::{fs::{File, OpenOptions}, io::Write, path::{Path, PathBuf}, sync::{mpsc, atomic::{AtomicBool, AtomicU64, Ordering}}};
+use serde::Serialize;
+use time::{OffsetDateTime, Date, format_description::well_known::Rfc3339, macros::format_description};
+use super::{config::AuditConfig, session::Session};
+
+/// Records waiting for the writing thread: over it they are dropped
+const QUEUE_LEN: usize = 4096;
+
+/// Append-only log of the requests, one JSON object per line, kept apart from the
+/// diagnostic logs. The file is rotated when it grows over the maximum size or,
+/// if daily rotation is enabled, on the first request of a new day (UTC): the old
+/// file is renamed with the time of the rotation as suffix.
+///
+/// The records are written by a dedicated thread, so the sessions never wait for the
+/// file: `flush` waits until the records sent before have been written. When the
+/// thread doesn't keep up and its queue is full, the records are dropped and counted.
+pub struct AuditLog {
+    /// Sends the records to the writing thread
+    writer: Option<mpsc::SyncSender<Message>>,
+    /// Records dropped because the queue was full
+    dropped: AtomicU64,
+    /// True while the records are being dropped, to warn once for each burst
+    dropping: AtomicBool
+}
+
+enum Message {
+    /// A line of the log, with the time of its request
+    Record(OffsetDateTime, Vec<u8>),
+    /// Answered when the records sent before have been written
+    Flush(mpsc::Sender<()>)
+}
+
+/// The writing thread of the audit log, owning the file.
+struct AuditWriter {
+    file: AuditFile,
+    max_size_bytes: u64,
+    daily: bool
+}
+
+struct AuditFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// Day of the first record in the file.
+    opened_on: Date
+}
+
+/// A line of the audit log.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    peer: String,
+    user: Option<&'a str>,
+    /// Username given by a failed LOGIN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempted_user: Option<&'a str>,
+    istruction: u8,
+    paths: Vec<String>,
+    response_code: u8,
+    bytes: u64
+}
+
+impl AuditLog {
+    /// Open the audit log of the config and start its writing thread; without a path,
+    /// nothing is recorded.
+    pub fn new(audit_config: &AuditConfig) -> Result<Self, String> {
+        let Some(path) = &audit_config.path else { return Ok(AuditLog::with_writer(None)) };
+        let file = AuditFile::open(path, OffsetDateTime::now_utc().date()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let writer = AuditWriter { file, max_size_bytes: audit_config.max_size_bytes, daily: audit_config.daily };
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || writer.run(receiver))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(AuditLog::with_writer(Some(sender)))
+    }
+
+    fn with_writer(writer: Option<mpsc::SyncSender<Message>>) -> Self {
+        AuditLog { writer, dropped: AtomicU64::new(0), dropping: AtomicBool::new(false) }
+    }
+
+    /// Record a request executed in the session.
+    ///
+    /// # Arguments
+    /// * `session` - the session, with its peer, its user and the bytes transferred.
+    /// * `istruction` - the istruction code.
+    /// * `paths` - the paths of the request.
+    /// * `username` - the username given by the request, recorded if the session isn't authenticated.
+    /// * `response_code` - the response code sent back to the client.
+    ///
+    pub fn record(&self, session: &Session, istruction: u8, paths: &[PathBuf], username: Option<&str>, response_code: u8) {
+        self.record_at(OffsetDateTime::now_utc(), session, istruction, paths, username, response_code);
+    }
+
+    /// Return the number of records dropped because the writing thread didn't keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the records sent before have been written, e.g. before exiting.
+    pub fn flush(&self) {
+        let Some(writer) = &self.writer else { return };
+        let (ack, written) = mpsc::channel();
+        if writer.send(Message::Flush(ack)).is_ok() {
+            let _ = written.recv();
+        }
+    }
+
+    fn record_at(&self, now: OffsetDateTime, session: &Session, istruction: u8, paths: &[PathBuf], username: Option<&str>, response_code: u8) {
+        let Some(writer) = &self.writer else { return };
+        let record = AuditRecord {
+            timestamp: now.format(&Rfc3339).unwrap_or_default(),
+            peer: session.peer.to_string(),
+            user: session.user.as_deref(),
+            attempted_user: username.filter(|_| session.user.is_none()),
+            istruction,
+            paths: paths.iter().map(|path| path.display().to_string()).collect(),
+            response_code,
+            bytes: session.transferred
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!(error = %e, "Audit record not serializable");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        match writer.try_send(Message::Record(now, line)) {
+            Ok(()) => self.dropping.store(false, Ordering::Relaxed),
+            Err(mpsc::TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if !self.dropping.swap(true, Ordering::Relaxed) {
+                    tracing::warn!(dropped, "Audit records dropped, the audit log doesn't keep up");
+                }
+            },
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                tracing::error!("Audit record not written, the writing thread has stopped");
+            }
+        }
+    }
+}
+
+impl AuditWriter {
+    /// Write the records until the audit log is dropped.
+    fn run(mut self, receiver: mpsc::Receiver<Message>) {
+        for message in receiver {
+            match message {
+                Message::Record(now, line) => self.write(now, &line),
+                Message::Flush(ack) => {
+                    let _ = ack.send(());
+                }
+            }
+        }
+    }
+
+    /// Append the line, rotating the file first if needed.
+    fn write(&mut self, now: OffsetDateTime, line: &[u8]) {
+        let file = &mut self.file;
+        let rotate = (self.max_size_bytes != 0 && file.size != 0 && file.size + line.len() as u64 > self.max_size_bytes)
+            || (self.daily && file.opened_on != now.date());
+        if rotate {
+            if let Err(e) = file.rotate(now) {
+                tracing::error!(error = %e, "Audit log rotation failed");
+            }
+        }
+        match file.file.write_all(line) {
+            Ok(()) => file.size += line.len() as u64,
+            Err(e) => tracing::error!(error = %e, "Audit record not written")
+        }
+    }
+}
+
+impl AuditFile {
+    fn open(path: &Path, today: Date) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditFile { path: path.to_path_buf(), file, size, opened_on: today })
+    }
+
+    /// Rename the current file with the time as suffix and start a new one.
+    fn rotate(&mut self, now: OffsetDateTime) -> std::io::Result<()> {
+        let suffix = now
+            .format(format_description!("[year][month][day]-[hour][minute][second]"))
+            .map_err(std::io::Error::other)?;
+        let mut rotated = PathBuf::from(format!("{}.{}", self.path.display(), suffix));
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{}.{}.{}", self.path.display(), suffix, n));
+            n += 1;
+        }
+        std::fs::rename(&self.path, &rotated)?;
+        *self = AuditFile::open(&self.path, now.date())?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use time::macros::datetime;
+    use crate::server::session::Peer;
+    use super::*;
+
+    fn audit_log(name: &str, max_size_bytes: u64, daily: bool) -> (AuditLog, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("nftp-audit-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+        let audit_config = AuditConfig { path: Some(path.clone()), max_size_bytes, daily };
+        (AuditLog::new(&audit_config).unwrap(), path)
+    }
+
+    fn session() -> Session {
+        let mut session = Session::new(Peer::Tcp("127.0.0.1:40000".parse().unwrap()));
+        session.user = Some("alice".to_string());
+        session.transferred = 42;
+        session
+    }
+
+    fn rotated_files(path: &Path) -> usize {
+        std::fs::read_dir(path.parent().unwrap()).unwrap().count() - 1
+    }
+
+    #[test]
+    fn record_should_append_a_json_line() {
+        let (audit_log, path) = audit_log("record", 0, false);
+        audit_log.record_at(datetime!(2026-01-02 03:04:05 UTC), &session(), 0, &[PathBuf::from("/docs/a.txt")], None, 1);
+        audit_log.record(&session(), 1, &[], None, 103);
+        audit_log.flush();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], serde_json::json!({
+            "timestamp": "2026-01-02T03:04:05Z",
+            "peer": "127.0.0.1:40000",
+            "user": "alice",
+            "istruction": 0,
+            "paths": ["/docs/a.txt"],
+            "response_code": 1,
+            "bytes": 42
+        }));
+        assert_eq!(lines[1]["response_code"], 103);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn failed_login_should_record_the_attempted_user() {
+        let (audit_log, path) = audit_log("login", 0, false);
+        let anonymous = Session::new(Peer::Tcp("127.0.0.1:40000".parse().unwrap()));
+        audit_log.record(&anonymous, 3, &[], Some("mallory"), 101);
+        audit_log.record(&session(), 3, &[], Some("alice"), 1);
+        audit_log.flush();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!((&lines[0]["user"], &lines[0]["attempted_user"]), (&serde_json::Value::Null, &serde_json::json!("mallory")));
+        assert!(lines[1].get("attempted_user").is_none());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn records_over_the_queue_should_be_dropped_and_counted() {
+        let (sender, _receiver) = mpsc::sync_channel(1);
+        let audit_log = AuditLog::with_writer(Some(sender));
+        for _ in 0..3 {
+            audit_log.record(&session(), 1, &[], None, 1);
+        }
+        assert_eq!(audit_log.dropped(), 2);
+    }
+
+    #[test]
+    fn log_over_the_max_size_should_be_rotated() {
+        let (audit_log, path) = audit_log("size", 200, false);
+        let now = datetime!(2026-01-02 03:04:05 UTC);
+        for _ in 0..3 {
+            audit_log.record_at(now, &session(), 0, &[PathBuf::from("/docs/a.txt")], None, 1);
+        }
+        audit_log.flush();
+
+        assert_eq!(rotated_files(&path), 2);
+        assert!(path.with_file_name("audit.log.20260102-030405").exists());
+        assert!(path.with_file_name("audit.log.20260102-030405.1").exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn daily_log_should_be_rotated_on_a_new_day() {
+        let (audit_log, path) = audit_log("daily", 0, true);
+        let today = OffsetDateTime::now_utc();
+        audit_log.record_at(today, &session(), 1, &[], None, 1);
+        audit_log.record_at(today, &session(), 1, &[], None, 1);
+        audit_log.flush();
+        assert_eq!(rotated_files(&path), 0);
+
+        audit_log.record_at(today + time::Duration::days(1), &session(), 1, &[], None, 1);
+        audit_log.flush();
+        assert_eq!(rotated_files(&path), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Duration};
+use argon2::{Argon2, PasswordHash, PasswordVerifier, PasswordHasher, password_hash::{SaltString, rand_core::OsRng}};
+use tokio::time::Instant;
+use super::{
+    config::{AuthConfig, UserConfig, LocalPeer},
+    session::Peer,
+    response::{RC_AUTH_FAILED, RC_LOCKED_OUT}
+};
+
+/// Hash verified for the unknown users and the users without a password, so that
+/// their logins take as long as the ones of the users with a password
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$pdiqi93BOHOIsJVF9X2m0w$AD0aiTY7hLytR9+c7djSXoVlz56bHOsl+YWg+E3T974";
+
+/// Where the failed logins come from: the IP address of a TCP peer
+/// or the uid of a local peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Origin {
+    Ip(IpAddr),
+    Uid(u32)
+}
+impl From<&Peer> for Origin {
+    fn from(peer: &Peer) -> Self {
+        match peer {
+            Peer::Tcp(address) => Origin::Ip(address.ip()),
+            Peer::Unix { uid, .. } => Origin::Uid(*uid)
+        }
+    }
+}
+
+/// Login attempts of a single origin since its last successful login.
+struct Failures {
+    count: u32,
+    last_attempt: Instant,
+    locked_until: Option<Instant>
+}
+impl Failures {
+    /// Return true if the attempts are older than `window` and no lockout is running:
+    /// they are forgotten.
+    fn expired(&self, now: Instant, window: Duration) -> bool {
+        self.locked_until.is_none_or(|locked_until| now >= locked_until) && now >= self.last_attempt + window
+    }
+}
+
+/// The attempts of every origin, swept of the expired ones once per lockout duration.
+struct FailureLog {
+    origins: HashMap<Origin, Failures>,
+    last_sweep: Instant
+}
+
+/// Checks the credentials sent with the LOGIN istruction and keeps track
+/// of the failed attempts, locking out the peers that fail too many times.
+/// The attempts are forgotten after a lockout duration without new ones.
+/// Local peers can also be authenticated by their credentials, without LOGIN.
+pub struct Authenticator {
+    users: Vec<UserConfig>,
+    local_peers: Vec<LocalPeer>,
+    max_failed_attempts: u32,
+    lockout: Duration,
+    failures: Mutex<FailureLog>
+}
+impl Authenticator {
+    pub fn new(auth_config: AuthConfig, users: Vec<UserConfig>) -> Self {
+        Authenticator {
+            users,
+            local_peers: auth_config.local_peers,
+            max_failed_attempts: auth_config.max_failed_attempts,
+            lockout: Duration::from_secs(auth_config.lockout_seconds),
+            failures: Mutex::new(FailureLog { origins: HashMap::new(), last_sweep: Instant::now() })
+        }
+    }
+
+    /// Return the user a local peer is authenticated as, according to its uid and gid.
+    pub fn login_with_peer_credentials(&self, uid: u32, gid: u32) -> Option<String> {
+        self.local_peers.iter()
+            .find(|local_peer| local_peer.matches(uid, gid))
+            .map(|local_peer| local_peer.user.clone())
+    }
+
+    /// Authenticate a user with its password.
+    /// Return the name of the user or the error response code.
+    pub async fn login_with_password(&self, peer: &Peer, username: &str, password: &[u8]) -> Result<String, u8> {
+        let peer = Origin::from(peer);
+        let attempt = self.start_attempt(peer)?;
+
+        let password_hash = self.users.iter()
+            .find(|user| user.name == username)
+            .and_then(|user| user.password_hash.clone());
+
+        // without a hash the dummy one is verified anyway: the time of the answer
+        // must not tell which users exist
+        let known = password_hash.is_some();
+        let password_hash = password_hash.unwrap_or_else(|| DUMMY_PASSWORD_HASH.to_string());
+        let password = password.to_vec();
+        // Argon2 is slow by design, keep it away from the async workers.
+        let verified = tokio::task::spawn_blocking(move || verify_password(&password_hash, &password))
+            .await
+            .unwrap_or(false) && known;
+
+        if !verified {
+            return Err(self.failure(peer, attempt));
+        }
+        self.reset_failures(peer);
+        Ok(username.to_string())
+    }
+
+    /// Authenticate a user with one of its pre-shared API tokens.
+    /// Return the name of the user or the error response code.
+    pub fn login_with_token(&self, peer: &Peer, token: &[u8]) -> Result<String, u8> {
+        let peer = Origin::from(peer);
+        let attempt = self.start_attempt(peer)?;
+
+        let user = self.users.iter().find(|user| {
+            user.tokens.iter().any(|t| constant_time_eq(t.as_bytes(), token))
+        });
+
+        match user {
+            Some(user) => {
+                self.reset_failures(peer);
+                Ok(user.name.clone())
+            },
+            None => Err(self.failure(peer, attempt))
+        }
+    }
+
+    /// Count an attempt of the peer before its credentials are checked, so that
+    /// concurrent attempts can't go over the maximum, and return its number.
+    /// The attempt that reaches the maximum starts the lockout, lifted if it succeeds.
+    fn start_attempt(&self, peer: Origin) -> Result<u32, u8> {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        if now >= failures.last_sweep + self.lockout {
+            failures.origins.retain(|_, f| !f.expired(now, self.lockout));
+            failures.last_sweep = now;
+        }
+
+        let f = failures.origins.entry(peer).or_insert(Failures { count: 0, last_attempt: now, locked_until: None });
+        if f.locked_until.is_some_and(|locked_until| now < locked_until) {
+            return Err(RC_LOCKED_OUT);
+        }
+        if f.expired(now, self.lockout) {
+            *f = Failures { count: 0, last_attempt: now, locked_until: None };
+        }
+        f.count += 1;
+        f.last_attempt = now;
+        if f.count >= self.max_failed_attempts {
+            f.locked_until = Some(now + self.lockout);
+        }
+        Ok(f.count)
+    }
+
+    /// Return the response code to send to the peer after a failed attempt.
+    fn failure(&self, peer: Origin, attempt: u32) -> u8 {
+        if attempt >= self.max_failed_attempts {
+            tracing::warn!(origin = ?peer, failures = attempt, "Locked out after too many failed logins");
+            return RC_LOCKED_OUT;
+        }
+        RC_AUTH_FAILED
+    }
+
+    fn reset_failures(&self, peer: Origin) {
+        self.failures.lock().unwrap().origins.remove(&peer);
+    }
+}
+
+
+/// Check a password against an Argon2 hash in PHC string format.
+pub fn verify_password(password_hash: &str, password: &[u8]) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default().verify_password(password, &hash).is_ok(),
+        Err(e) => {
+            tracing::error!(error = %e, "Invalid password hash in config");
+            false
+        }
+    }
+}
+
+
+/// Hash a password with Argon2, returning the PHC string to put in the config.
+pub fn hash_password(password: &[u8]) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password, &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+
+/// Compare two byte strings in a time that doesn't depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use super::*;
+
+    const PEER: &Peer = &Peer::Tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40000));
+    const OTHER_PORT: &Peer = &Peer::Tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40001));
+
+    fn authenticator(max_failed_attempts: u32) -> Authenticator {
+        let users = vec![
+            UserConfig {
+                name: "alice".to_string(),
+                password_hash: Some(hash_password(b"secret").unwrap()),
+                ..Default::default()
+            },
+            UserConfig {
+                name: "bot".to_string(),
+                tokens: vec!["token-123".to_string()],
+                ..Default::default()
+            }
+        ];
+        let local_peers = vec![
+            LocalPeer { uid: Some(1000), gid: None, user: "alice".to_string() },
+            LocalPeer { uid: None, gid: Some(50), user: "bot".to_string() }
+        ];
+        Authenticator::new(AuthConfig { max_failed_attempts, lockout_seconds: 60, local_peers }, users)
+    }
+
+    #[tokio::test]
+    async fn login_with_correct_password_should_return_the_user() {
+        let auth = authenticator(3);
+        assert_eq!(auth.login_with_password(PEER, "alice", b"secret").await, Ok("alice".to_string()));
+    }
+
+    #[tokio::test]
+    async fn login_with_wrong_password_should_return_auth_failed() {
+        let auth = authenticator(3);
+        assert_eq!(auth.login_with_password(PEER, "alice", b"wrong").await, Err(RC_AUTH_FAILED));
+        assert_eq!(auth.login_with_password(PEER, "nobody", b"secret").await, Err(RC_AUTH_FAILED));
+        assert_eq!(auth.login_with_password(PEER, "bot", b"").await, Err(RC_LOCKED_OUT));
+    }
+
+    #[tokio::test]
+    async fn locked_out_peer_should_be_refused_even_with_correct_password() {
+        let auth = authenticator(1);
+        assert_eq!(auth.login_with_password(PEER, "alice", b"wrong").await, Err(RC_LOCKED_OUT));
+        assert_eq!(auth.login_with_password(PEER, "alice", b"secret").await, Err(RC_LOCKED_OUT));
+        assert_eq!(auth.login_with_token(OTHER_PORT, b"token-123"), Err(RC_LOCKED_OUT));
+        assert!(auth.login_with_token(&Peer::Unix { uid: 1000, gid: 1000 }, b"token-123").is_ok());
+    }
+
+    #[tokio::test]
+    async fn successful_login_should_reset_the_failed_attempts() {
+        let auth = authenticator(2);
+        assert_eq!(auth.login_with_password(PEER, "alice", b"wrong").await, Err(RC_AUTH_FAILED));
+        assert!(auth.login_with_password(PEER, "alice", b"secret").await.is_ok());
+        assert_eq!(auth.login_with_password(PEER, "alice", b"wrong").await, Err(RC_AUTH_FAILED));
+    }
+
+    #[tokio::test]
+    async fn login_of_an_unknown_user_should_verify_a_password_anyway() {
+        let auth = authenticator(10);
+        let start = std::time::Instant::now();
+        assert_eq!(auth.login_with_password(PEER, "alice", b"wrong").await, Err(RC_AUTH_FAILED));
+        let known = start.elapsed();
+
+        let start = std::time::Instant::now();
+        assert_eq!(auth.login_with_password(PEER, "nobody", b"wrong").await, Err(RC_AUTH_FAILED));
+        assert!(start.elapsed() * 4 > known);
+        assert!(verify_password(DUMMY_PASSWORD_HASH, b"nftp-dummy-password"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_attempts_should_be_forgotten_after_the_lockout_duration() {
+        let auth = authenticator(3);
+        let other = &Peer::Tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 40000));
+        assert_eq!(auth.login_with_token(PEER, b"wrong"), Err(RC_AUTH_FAILED));
+        assert_eq!(auth.login_with_token(PEER, b"wrong"), Err(RC_AUTH_FAILED));
+        assert_eq!(auth.login_with_token(other, b"wrong"), Err(RC_AUTH_FAILED));
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(auth.login_with_token(PEER, b"wrong"), Err(RC_AUTH_FAILED));
+        // the sweep left only the origin of the new attempt
+        assert_eq!(auth.failures.lock().unwrap().origins.len(), 1);
+    }
+
+    #[test]
+    fn concurrent_attempts_should_be_counted_before_the_check() {
+        let auth = authenticator(2);
+        let peer = Origin::from(PEER);
+        assert_eq!(auth.start_attempt(peer), Ok(1));
+        assert_eq!(auth.start_attempt(peer), Ok(2));
+        assert_eq!(auth.start_attempt(peer), Err(RC_LOCKED_OUT));
+    }
+
+    #[test]
+    fn login_with_token_should_return_the_owner_of_the_token() {
+        let auth = authenticator(3);
+        assert_eq!(auth.login_with_token(PEER, b"token-123"), Ok("bot".to_string()));
+        assert_eq!(auth.login_with_token(PEER, b"token-124"), Err(RC_AUTH_FAILED));
+        assert_eq!(auth.login_with_token(PEER, b""), Err(RC_AUTH_FAILED));
+    }
+
+    #[test]
+    fn login_with_peer_credentials_should_match_uid_or_gid() {
+        let auth = authenticator(3);
+        assert_eq!(auth.login_with_peer_credentials(1000, 1000), Some("alice".to_string()));
+        assert_eq!(auth.login_with_peer_credentials(1001, 50), Some("bot".to_string()));
+        assert_eq!(auth.login_with_peer_credentials(1001, 1001), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,501 @@
+use std::{path::PathBuf, net::SocketAddr, collections::{BTreeMap, HashMap}};
+use serde::Deserialize;
+use super::acl::Permission;
+
+/// Path of the configuration file, relative to the working directory.
+pub const CONFIG_PATH: &str = "./config.toml";
+
+/// The server configuration, read from the `config.toml` file.
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// Root of the served filesystem.
+    pub main_path: PathBuf,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    /// Members of each group, by group name.
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub acl: Vec<AclRule>,
+    #[serde(default)]
+    pub quotas: Vec<QuotaConfig>,
+    /// The addresses to listen on, all served by the same parser.
+    #[serde(default = "default_listen")]
+    pub listen: Vec<ListenConfig>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    #[serde(default)]
+    pub error_response: ErrorResponseConfig,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+}
+impl Config {
+    /// Read and deserialize the configuration file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Config::parse(&content)
+    }
+
+    /// Deserialize a configuration from its TOML representation.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| e.to_string())
+    }
+}
+
+
+/// Settings of the authentication handshake.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Failed logins accepted from a peer before it is locked out.
+    pub max_failed_attempts: u32,
+    /// Duration of the lockout, in seconds; the failed logins are also forgotten
+    /// after this long without new attempts.
+    pub lockout_seconds: u64,
+    /// Local peers, connected through the Unix socket, authenticated without LOGIN.
+    pub local_peers: Vec<LocalPeer>,
+}
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig { max_failed_attempts: 5, lockout_seconds: 300, local_peers: vec![] }
+    }
+}
+
+
+/// Local peers with this uid and/or gid are authenticated as `user`.
+#[derive(Debug, Deserialize)]
+pub struct LocalPeer {
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+    pub user: String,
+}
+impl LocalPeer {
+    /// Return true if the credentials match all the ids of this local peer.
+    pub fn matches(&self, uid: u32, gid: u32) -> bool {
+        (self.uid.is_some() || self.gid.is_some())
+            && self.uid.is_none_or(|u| u == uid)
+            && self.gid.is_none_or(|g| g == gid)
+    }
+}
+
+
+/// A user allowed to log in.
+#[derive(Debug, Default, Deserialize)]
+pub struct UserConfig {
+    pub name: String,
+    /// Argon2 hash of the password, in PHC string format.
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    /// Pre-shared API tokens that authenticate as this user.
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    /// Root directory of the user, relative to `main_path` (default: the user name).
+    #[serde(default)]
+    pub home: Option<PathBuf>,
+    /// Named directories that make up a virtual root, used instead of `home`.
+    #[serde(default)]
+    pub mounts: BTreeMap<String, PathBuf>,
+}
+
+
+/// Rights given to some users and groups on a path prefix of their spaces.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AclRule {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub allow: Vec<Permission>,
+}
+
+
+/// Byte and/or file limits on the space of a user, or under a directory for everybody.
+#[derive(Debug, Deserialize)]
+pub struct QuotaConfig {
+    /// The user whose whole space is limited.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The directory limited with everything under it, relative to `main_path`.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Maximum total dimension of the files, in bytes.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Maximum number of files.
+    #[serde(default)]
+    pub max_files: Option<u64>,
+}
+
+
+/// Certificate and key of the server, in PEM format, for TLS connections.
+#[derive(Debug, Deserialize)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// CA that signs the client certificates; if present, clients must authenticate
+    /// with a certificate.
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    /// Accept also clients without a certificate, verifying only the ones that have it.
+    #[serde(default)]
+    pub client_auth_optional: bool,
+}
+
+
+/// Settings of the graceful shutdown.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// Time given to the running transfers to finish, in seconds.
+    pub grace_seconds: u64,
+}
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { grace_seconds: 30 }
+    }
+}
+
+
+/// Caps on the concurrent sessions.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Maximum number of sessions on all the listeners.
+    pub max_sessions: usize,
+    /// Maximum number of sessions from the same IP address.
+    pub max_sessions_per_ip: usize,
+    /// Maximum number of WATCH requests served at the same time.
+    pub max_watches: usize,
+}
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { max_sessions: 1024, max_sessions_per_ip: 16, max_watches: 64 }
+    }
+}
+
+
+/// Time limits of the socket operations, in seconds.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TimeoutsConfig {
+    /// Time given to a new connection to send its first request.
+    pub header_seconds: u64,
+    /// Time a session can stay without requests.
+    pub idle_seconds: u64,
+    /// Time given to the client to receive each chunk of a transfer.
+    pub chunk_seconds: u64,
+}
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        TimeoutsConfig { header_seconds: 10, idle_seconds: 300, chunk_seconds: 30 }
+    }
+}
+
+
+/// Retries of the error responses whose writing fails.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ErrorResponseConfig {
+    /// Number of retries after the first attempt.
+    pub retries: u32,
+    /// Wait before the first retry, in milliseconds.
+    pub backoff_ms: u64,
+    /// Factor by which the wait grows at each retry.
+    pub backoff_factor: u32,
+}
+impl Default for ErrorResponseConfig {
+    fn default() -> Self {
+        ErrorResponseConfig { retries: 2, backoff_ms: 500, backoff_factor: 4 }
+    }
+}
+
+
+/// Bandwidth limits, in bytes per second; 0 means unlimited.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    /// Limit of each session.
+    pub session_bytes_per_second: u64,
+    /// Limit of all the sessions of the same user.
+    pub user_bytes_per_second: u64,
+    /// Limit of the whole server.
+    pub global_bytes_per_second: u64,
+}
+
+
+/// Settings of the diagnostic logs.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Level of the logs, also per target (e.g. `info,server::server::auth=debug`).
+    pub level: String,
+    pub format: LogFormat,
+}
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig { level: "info".to_string(), format: LogFormat::Text }
+    }
+}
+
+/// Output format of the diagnostic logs.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per event.
+    Text,
+    /// Many lines per event, easier to read.
+    Pretty,
+    /// One JSON object per line, with the spans of the event.
+    Json
+}
+
+
+/// Settings of the audit log of the requests.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// File of the audit log; without it, the requests are not audited.
+    pub path: Option<PathBuf>,
+    /// Size over which the file is rotated, 0 for no limit.
+    pub max_size_bytes: u64,
+    /// Rotate the file every day.
+    pub daily: bool,
+}
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig { path: None, max_size_bytes: 100 * 1024 * 1024, daily: false }
+    }
+}
+
+
+/// Settings of the Prometheus metrics endpoint.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Address of the HTTP endpoint; without it, the metrics are not exposed.
+    pub address: Option<SocketAddr>,
+}
+
+
+/// An address to listen on.
+#[derive(Debug, Deserialize)]
+pub struct ListenConfig {
+    pub address: ListenAddress,
+    /// Without this section the connections are in plaintext.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Permissions of the socket file, for Unix domain sockets.
+    #[serde(default = "default_unix_mode")]
+    pub mode: u32,
+}
+
+fn default_unix_mode() -> u32 {
+    0o660
+}
+
+fn default_listen() -> Vec<ListenConfig> {
+    vec![ListenConfig {
+        address: ListenAddress::Tcp(SocketAddr::from(([127, 0, 0, 1], 3000))),
+        tls: None,
+        mode: default_unix_mode()
+    }]
+}
+
+
+/// A TCP address, IPv4 (`127.0.0.1:3000`, `0.0.0.0:3000`) or IPv6 (`[::1]:3000`, `[::]:3000`),
+/// or the path of a Unix domain socket (`unix:/run/nftp.sock`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf)
+}
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(address: String) -> Result<Self, Self::Error> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+        }
+        address.parse()
+            .map(ListenAddress::Tcp)
+            .map_err(|_| format!("invalid listen address `{}`", address))
+    }
+}
+impl std::fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display())
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn parse_with_only_main_path_should_use_default_auth_settings() {
+        let config = Config::parse("main_path = \"/srv/nftp\"").unwrap();
+
+        assert_eq!(config.main_path, PathBuf::from("/srv/nftp"));
+        assert_eq!(config.auth.max_failed_attempts, 5);
+        assert_eq!(config.auth.lockout_seconds, 300);
+        assert!(config.users.is_empty());
+        assert!(config.acl.is_empty());
+        assert_eq!(config.listen.len(), 1);
+        assert_eq!(config.listen[0].address.to_string(), "127.0.0.1:3000");
+        assert!(config.listen[0].tls.is_none());
+        assert_eq!(config.shutdown.grace_seconds, 30);
+        assert_eq!(config.limits.max_sessions, 1024);
+        assert_eq!(config.limits.max_sessions_per_ip, 16);
+        assert_eq!(config.timeouts.idle_seconds, 300);
+        assert_eq!(config.error_response.retries, 2);
+        assert_eq!(config.throttle.global_bytes_per_second, 0);
+        assert_eq!(config.log.level, "info");
+        assert_eq!(config.log.format, LogFormat::Text);
+        assert!(config.audit.path.is_none());
+        assert!(config.metrics.address.is_none());
+    }
+
+    #[test]
+    fn parse_should_read_listen_addresses_with_tls() {
+        let config = Config::parse(r#"
+            main_path = "/srv/nftp"
+
+            [[listen]]
+            address = "0.0.0.0:3000"
+
+            [[listen]]
+            address = "[::]:3443"
+
+            [listen.tls]
+            cert = "/etc/nftp/cert.pem"
+            key = "/etc/nftp/key.pem"
+
+            [[listen]]
+            address = "unix:/run/nftp.sock"
+            mode = 0o600
+        "#).unwrap();
+
+        assert_eq!(config.listen.len(), 3);
+        assert_eq!(config.listen[0].address, ListenAddress::Tcp("0.0.0.0:3000".parse().unwrap()));
+        assert!(config.listen[0].tls.is_none());
+        assert_eq!(config.listen[1].address, ListenAddress::Tcp("[::]:3443".parse().unwrap()));
+        let tls = config.listen[1].tls.as_ref().unwrap();
+        assert_eq!(tls.cert, PathBuf::from("/etc/nftp/cert.pem"));
+        assert_eq!(tls.key, PathBuf::from("/etc/nftp/key.pem"));
+        assert_eq!(tls.client_ca, None);
+        assert!(!tls.client_auth_optional);
+        assert_eq!(config.listen[2].address, ListenAddress::Unix(PathBuf::from("/run/nftp.sock")));
+        assert_eq!(config.listen[2].mode, 0o600);
+    }
+
+    #[test]
+    fn parse_with_invalid_listen_address_should_return_err() {
+        assert!(Config::parse("main_path = \"/\"\n[[listen]]\naddress = \"localhost\"").is_err());
+    }
+
+    #[test]
+    fn parse_should_read_users() {
+        let config = Config::parse(r#"
+            main_path = "/srv/nftp"
+
+            [auth]
+            max_failed_attempts = 3
+
+            [[users]]
+            name = "alice"
+            password_hash = "$argon2id$hash"
+
+            [[users]]
+            name = "bot"
+            tokens = ["t1", "t2"]
+            home = "shared"
+
+            [users.mounts]
+            docs = "/srv/docs"
+        "#).unwrap();
+
+        assert_eq!(config.auth.max_failed_attempts, 3);
+        assert_eq!(config.auth.lockout_seconds, 300);
+        assert_eq!(config.users.len(), 2);
+        assert_eq!(config.users[0].password_hash.as_deref(), Some("$argon2id$hash"));
+        assert!(config.users[0].tokens.is_empty());
+        assert_eq!(config.users[1].password_hash, None);
+        assert_eq!(config.users[1].tokens, vec!["t1", "t2"]);
+        assert_eq!(config.users[0].home, None);
+        assert_eq!(config.users[1].home, Some(PathBuf::from("shared")));
+        assert_eq!(config.users[1].mounts.get("docs"), Some(&PathBuf::from("/srv/docs")));
+    }
+
+    #[test]
+    fn parse_should_read_groups_and_acl() {
+        let config = Config::parse(r#"
+            main_path = "/srv/nftp"
+
+            [groups]
+            dev = ["alice", "bob"]
+
+            [[acl]]
+            path = "/docs"
+            groups = ["dev"]
+            allow = ["read", "list"]
+        "#).unwrap();
+
+        assert_eq!(config.groups.get("dev"), Some(&vec!["alice".to_string(), "bob".to_string()]));
+        assert_eq!(config.acl.len(), 1);
+        assert_eq!(config.acl[0].path, PathBuf::from("/docs"));
+        assert!(config.acl[0].users.is_empty());
+        assert_eq!(config.acl[0].allow, vec![Permission::Read, Permission::List]);
+    }
+
+    #[test]
+    fn parse_with_unknown_permission_should_return_err() {
+        assert!(Config::parse("main_path = \"/\"\n[[acl]]\npath = \"/\"\nallow = [\"execute\"]").is_err());
+    }
+
+    #[test]
+    fn parse_should_read_local_peers() {
+        let config = Config::parse(r#"
+            main_path = "/srv/nftp"
+
+            [[auth.local_peers]]
+            uid = 1000
+            user = "alice"
+        "#).unwrap();
+
+        assert_eq!(config.auth.local_peers.len(), 1);
+        assert!(config.auth.local_peers[0].matches(1000, 1));
+        assert!(!config.auth.local_peers[0].matches(1001, 1));
+    }
+
+    #[test]
+    fn local_peer_without_ids_should_match_nobody() {
+        let local_peer = LocalPeer { uid: None, gid: None, user: "alice".to_string() };
+        assert!(!local_peer.matches(0, 0));
+    }
+
+    #[test]
+    fn parse_without_main_path_should_return_err() {
+        assert!(Config::parse("[auth]\nmax_failed_attempts = 3").is_err());
+    }
+}
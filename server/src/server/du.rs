@@ -0,0 +1,103 @@
+use std::{fs::DirEntry, path::Path};
+use tracing::debug;
+use protocol::du::{PathUsage, Usage};
+use super::is_temporary;
+
+/// Return the space used by the path, a file or a directory with everything under it,
+/// and with `children` also the space used by each immediate child, sorted by name.
+/// `user_path` is the path in the user space: only the entries whose path there is
+/// `visible` are counted, a hidden directory is skipped with everything under it.
+/// The symbolic links are counted as files and not followed; the directories that
+/// can't be read and the temporary files of the uploads are skipped.
+pub fn disk_usage(path: &Path, user_path: &str, children: bool, visible: &dyn Fn(&str) -> bool) -> std::io::Result<PathUsage> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(PathUsage { total: Usage { size: metadata.len(), files: 1 }, children: Vec::new() });
+    }
+
+    let mut usage = PathUsage::default();
+    for (entry, user_path) in entries(path, user_path, visible)? {
+        let child = walk(&entry, user_path, visible);
+        usage.total += child;
+        if children {
+            usage.children.push((entry.file_name().to_string_lossy().into_owned(), child));
+        }
+    }
+    usage.children.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(usage)
+}
+
+/// Return the visible entries of the directory, with their path in the user space.
+fn entries(directory: &Path, user_path: &str, visible: &dyn Fn(&str) -> bool) -> std::io::Result<Vec<(DirEntry, String)>> {
+    Ok(std::fs::read_dir(directory)?.flatten()
+        .filter(|entry| !is_temporary(&entry.file_name()))
+        .map(|entry| {
+            let user_path = format!("{}/{}", user_path.trim_end_matches('/'), entry.file_name().to_string_lossy());
+            (entry, user_path)
+        })
+        .filter(|(_, user_path)| visible(user_path))
+        .collect())
+}
+
+/// Return the space used by the entry and everything visible under it.
+fn walk(entry: &DirEntry, user_path: String, visible: &dyn Fn(&str) -> bool) -> Usage {
+    let Ok(metadata) = entry.metadata() else { return Usage::default() };
+    if !metadata.is_dir() {
+        return Usage { size: metadata.len(), files: 1 };
+    }
+
+    let mut usage = Usage::default();
+    let mut directories = vec![(entry.path(), user_path)];
+    while let Some((directory, user_path)) = directories.pop() {
+        let entries = match entries(&directory, &user_path, visible) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!(error = %e, directory = %directory.display(), "Directory not readable for DU request");
+                continue;
+            }
+        };
+        for (entry, user_path) in entries {
+            let Ok(metadata) = entry.metadata() else { continue };
+            if metadata.is_dir() {
+                directories.push((entry.path(), user_path));
+            } else {
+                usage += Usage { size: metadata.len(), files: 1 };
+            }
+        }
+    }
+    usage
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn disk_usage_should_sum_the_files_under_the_path() {
+        let root = Path::new("./tests/tree_serialization/root");
+
+        let usage = disk_usage(&root.join("dir_1"), "/dir_1", true, &|_| true).unwrap();
+        assert_eq!(usage.total, Usage { size: 13, files: 3 });
+        assert_eq!(usage.children, vec![
+            ("dir_5".to_string(), Usage { size: 4, files: 1 }),
+            ("file_1.txt".to_string(), Usage { size: 5, files: 1 }),
+            ("file_3.txt".to_string(), Usage { size: 4, files: 1 })
+        ]);
+
+        let usage = disk_usage(root, "/", false, &|_| true).unwrap();
+        assert_eq!(usage.total, Usage { size: 17, files: 4 });
+        assert!(usage.children.is_empty());
+        assert_eq!(disk_usage(&root.join("file.txt"), "/file.txt", true, &|_| true).unwrap().total, Usage { size: 4, files: 1 });
+        assert!(disk_usage(&root.join("missing"), "/missing", false, &|_| true).is_err());
+    }
+
+    #[test]
+    fn disk_usage_should_skip_the_hidden_paths() {
+        let root = Path::new("./tests/tree_serialization/root");
+        let visible = |path: &str| path != "/dir_1/dir_5" && path != "/file.txt";
+
+        assert_eq!(disk_usage(root, "/", false, &visible).unwrap().total, Usage { size: 9, files: 2 });
+        assert_eq!(disk_usage(&root.join("dir_1"), "/dir_1", true, &visible).unwrap().children.len(), 2);
+    }
+}
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex}};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use super::{config::LimitsConfig, session::Peer};
+
+/// Refused connections answered at the same time: over it they are closed without an answer
+const MAX_REFUSALS: usize = 8;
+
+/// Caps on the concurrent sessions: a global maximum and a maximum for each peer IP.
+/// Local peers on Unix domain sockets count only for the global maximum.
+/// Also caps the WATCH requests served at the same time.
+pub struct ConnectionLimits {
+    max_sessions: usize,
+    max_sessions_per_ip: usize,
+    counters: Arc<Mutex<Counters>>,
+    refusals: Arc<Semaphore>,
+    watches: Arc<Semaphore>
+}
+
+#[derive(Default)]
+struct Counters {
+    sessions: usize,
+    per_ip: HashMap<IpAddr, usize>
+}
+
+impl ConnectionLimits {
+    pub fn new(config: &LimitsConfig) -> Self {
+        ConnectionLimits {
+            max_sessions: config.max_sessions,
+            max_sessions_per_ip: config.max_sessions_per_ip,
+            counters: Arc::new(Mutex::new(Counters::default())),
+            refusals: Arc::new(Semaphore::new(MAX_REFUSALS)),
+            watches: Arc::new(Semaphore::new(config.max_watches.min(Semaphore::MAX_PERMITS)))
+        }
+    }
+
+    /// Reserve a session for the peer, if both the caps allow it.
+    /// The session is released when the returned permit is dropped.
+    pub fn try_acquire(&self, peer: &Peer) -> Option<SessionPermit> {
+        let mut counters = self.counters.lock().unwrap();
+        if counters.sessions >= self.max_sessions {
+            return None;
+        }
+        let ip = match peer {
+            Peer::Tcp(address) => Some(address.ip()),
+            Peer::Unix { .. } => None
+        };
+        if let Some(ip) = ip {
+            // checked before inserting, so a refused peer leaves no entry behind
+            if counters.per_ip.get(&ip).copied().unwrap_or(0) >= self.max_sessions_per_ip {
+                return None;
+            }
+            *counters.per_ip.entry(ip).or_insert(0) += 1;
+        }
+        counters.sessions += 1;
+        Some(SessionPermit { ip, counters: Arc::clone(&self.counters) })
+    }
+
+    /// Reserve the answer to a refused connection, if not too many are already
+    /// being answered. The answer is released when the returned permit is dropped.
+    pub fn try_refuse(&self) -> Option<OwnedSemaphorePermit> {
+        Arc::clone(&self.refusals).try_acquire_owned().ok()
+    }
+
+    /// Reserve a WATCH request, if not too many are already being served.
+    /// The request is released when the returned permit is dropped.
+    pub fn try_watch(&self) -> Option<OwnedSemaphorePermit> {
+        Arc::clone(&self.watches).try_acquire_owned().ok()
+    }
+}
+
+
+/// A session reserved by `ConnectionLimits::try_acquire`.
+pub struct SessionPermit {
+    ip: Option<IpAddr>,
+    counters: Arc<Mutex<Counters>>
+}
+impl Drop for SessionPermit {
+    fn drop(&mut self) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.sessions -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(per_ip) = counters.per_ip.get_mut(&ip) {
+                *per_ip -= 1;
+                if *per_ip == 0 {
+                    counters.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn tcp_peer(address: &str) -> Peer {
+        Peer::Tcp(address.parse().unwrap())
+    }
+
+    #[test]
+    fn per_ip_cap_should_not_limit_the_other_addresses() {
+        let limits = ConnectionLimits::new(&LimitsConfig { max_sessions: 10, max_sessions_per_ip: 2, ..LimitsConfig::default() });
+
+        let first = limits.try_acquire(&tcp_peer("10.0.0.1:4000"));
+        let second = limits.try_acquire(&tcp_peer("10.0.0.1:4001"));
+        assert!(first.is_some() && second.is_some());
+        assert!(limits.try_acquire(&tcp_peer("10.0.0.1:4002")).is_none());
+        assert!(limits.try_acquire(&tcp_peer("10.0.0.2:4000")).is_some());
+
+        drop(first);
+        assert!(limits.try_acquire(&tcp_peer("10.0.0.1:4003")).is_some());
+    }
+
+    #[test]
+    fn global_cap_should_count_all_the_peers() {
+        let limits = ConnectionLimits::new(&LimitsConfig { max_sessions: 2, max_sessions_per_ip: 2, ..LimitsConfig::default() });
+
+        let _tcp = limits.try_acquire(&tcp_peer("10.0.0.1:4000")).unwrap();
+        let unix = limits.try_acquire(&Peer::Unix { uid: 1000, gid: 1000 }).unwrap();
+        assert!(limits.try_acquire(&tcp_peer("10.0.0.2:4000")).is_none());
+
+        drop(unix);
+        assert!(limits.try_acquire(&tcp_peer("10.0.0.2:4000")).is_some());
+        assert_eq!(limits.counters.lock().unwrap().per_ip.len(), 1);
+    }
+
+    #[test]
+    fn refused_peers_should_not_be_counted() {
+        let limits = ConnectionLimits::new(&LimitsConfig { max_sessions: 2, max_sessions_per_ip: 0, ..LimitsConfig::default() });
+
+        assert!(limits.try_acquire(&tcp_peer("10.0.0.1:4000")).is_none());
+        assert!(limits.counters.lock().unwrap().per_ip.is_empty());
+        assert!(limits.try_acquire(&Peer::Unix { uid: 1000, gid: 1000 }).is_some());
+    }
+
+    #[test]
+    fn try_refuse_should_cap_the_answers_in_progress() {
+        let limits = ConnectionLimits::new(&LimitsConfig::default());
+
+        let refusals: Vec<_> = (0..MAX_REFUSALS).map(|_| limits.try_refuse().unwrap()).collect();
+        assert!(limits.try_refuse().is_none());
+        drop(refusals);
+        assert!(limits.try_refuse().is_some());
+    }
+
+    #[test]
+    fn watches_should_be_capped() {
+        let limits = ConnectionLimits::new(&LimitsConfig { max_watches: 1, ..LimitsConfig::default() });
+
+        let watch = limits.try_watch().unwrap();
+        assert!(limits.try_watch().is_none());
+        drop(watch);
+        assert!(limits.try_watch().is_some());
+    }
+}
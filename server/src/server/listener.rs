@@ -0,0 +1,316 @@
+use std::{sync::Arc, path::Path, future::Future, os::unix::fs::{PermissionsExt, FileTypeExt}};
+use std::time::Duration;
+use tokio::{net::{TcpListener, UnixListener}, io::AsyncWriteExt};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+use super::{
+    parser::Parser,
+    config::{ListenConfig, ListenAddress},
+    session::Peer,
+    tls::build_acceptor,
+    response::{ResponseHeader, RC_SERVER_BUSY},
+    Stream
+};
+
+/// Time given to a refused client to complete the TLS handshake and receive the
+/// server busy response
+const REFUSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Wait after the first failed accept, doubled at each following failure
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+/// Longest wait after a failed accept
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// A bound address, with the optional TLS of its connections.
+pub struct Listener {
+    socket: ListenerSocket,
+    tls_acceptor: Option<TlsAcceptor>,
+    address: ListenAddress
+}
+
+enum ListenerSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener)
+}
+
+impl Listener {
+    /// Bind the address of the config, preparing its TLS acceptor if needed.
+    /// For Unix domain sockets, set the permissions of the socket file.
+    pub async fn bind(listen_config: &ListenConfig) -> Result<Self, String> {
+        let address = listen_config.address.clone();
+        let tls_acceptor = match &listen_config.tls {
+            Some(tls_config) => Some(build_acceptor(tls_config).map_err(|e| format!("{}: {}", address, e))?),
+            None => None
+        };
+        let socket = match &address {
+            ListenAddress::Tcp(tcp_address) => ListenerSocket::Tcp(
+                TcpListener::bind(tcp_address).await.map_err(|e| format!("{}: {}", address, e))?
+            ),
+            ListenAddress::Unix(path) => ListenerSocket::Unix(
+                bind_unix(path, listen_config.mode).map_err(|e| format!("{}: {}", address, e))?
+            )
+        };
+        Ok(Listener { socket, tls_acceptor, address })
+    }
+
+    /// Return a description of the address, for the logs.
+    pub fn description(&self) -> String {
+        format!("{}{}", self.address, if self.tls_acceptor.is_some() { " (TLS)" } else { "" })
+    }
+
+    /// Accept the connections until the shutdown, serving each client in its own task.
+    /// After a failed accept, e.g. when the process is out of file descriptors, the
+    /// next one is delayed, see `AcceptBackoff`.
+    pub async fn accept(self, server_parser: Arc<Parser>) {
+        let mut backoff = AcceptBackoff::default();
+        loop {
+            let tls_acceptor = self.tls_acceptor.clone();
+
+            let accepted = match &self.socket {
+                ListenerSocket::Tcp(listener) => match cancellable_accept(listener.accept(), &server_parser).await {
+                    None => break,
+                    Some(Ok((socket, address))) => {
+                        spawn_client(socket, Peer::Tcp(address), Arc::clone(&server_parser), tls_acceptor);
+                        true
+                    },
+                    Some(Err(e)) => {
+                        warn!(address = %self.address, error = %e, "Accept failed");
+                        false
+                    }
+                },
+                ListenerSocket::Unix(listener) => match cancellable_accept(listener.accept(), &server_parser).await {
+                    None => break,
+                    Some(Ok((socket, _))) => {
+                        match socket.peer_cred() {
+                            Ok(credentials) => {
+                                let peer = Peer::Unix { uid: credentials.uid(), gid: credentials.gid() };
+                                spawn_client(socket, peer, Arc::clone(&server_parser), tls_acceptor);
+                            },
+                            Err(e) => warn!(address = %self.address, error = %e, "Peer credentials not available")
+                        }
+                        true
+                    },
+                    Some(Err(e)) => {
+                        warn!(address = %self.address, error = %e, "Accept failed");
+                        false
+                    }
+                }
+            };
+            if accepted {
+                backoff.reset();
+            } else if !backoff.wait(&server_parser).await {
+                break;
+            }
+        }
+
+        info!(address = %self.address, "No longer accepting connections");
+        if let ListenAddress::Unix(path) = &self.address {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+
+/// Wait for a new connection, or for the shutdown. 
+/// In case of shutdown return `None`.
+async fn cancellable_accept<T>(accept: impl Future<Output = std::io::Result<T>>, server_parser: &Parser) -> Option<std::io::Result<T>> {
+    tokio::select! {
+        biased;
+        _ = server_parser.shutdown.triggered() => None,
+        accepted = accept => Some(accepted)
+    }
+}
+
+
+/// Delay of the accepts after a failure, so that a persistent error like EMFILE
+/// doesn't make the loop spin: 100 ms after the first failure, doubling up to 1 s,
+/// until a connection is accepted again.
+#[derive(Default)]
+pub struct AcceptBackoff {
+    next: Option<Duration>
+}
+impl AcceptBackoff {
+    /// Wait after one more failed accept, or until the shutdown.
+    /// In case of shutdown return false.
+    pub async fn wait(&mut self, server_parser: &Parser) -> bool {
+        let delay = self.failed();
+        tokio::select! {
+            biased;
+            _ = server_parser.shutdown.triggered() => false,
+            _ = tokio::time::sleep(delay) => true
+        }
+    }
+
+    /// Record a failed accept and return the wait before the next one.
+    fn failed(&mut self) -> Duration {
+        let delay = self.next.unwrap_or(ACCEPT_BACKOFF_MIN);
+        self.next = Some((delay * 2).min(ACCEPT_BACKOFF_MAX));
+        delay
+    }
+
+    /// Record an accepted connection: the next failure waits the least again.
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+}
+
+
+/// Serve a client in its own task.
+/// With an acceptor, the TLS handshake is performed before serving the client.
+/// If the connection limits are reached, the client is answered with the
+/// server busy response code and the connection is closed; if too many clients
+/// are already being answered, the connection is closed right away.
+fn spawn_client<S: Stream + 'static>(mut socket: S, peer: Peer, server_parser: Arc<Parser>, tls_acceptor: Option<TlsAcceptor>) {
+    let permit = server_parser.limits.try_acquire(&peer);
+    server_parser.metrics.connection_accepted(permit.is_none());
+    if permit.is_none() {
+        let Some(refusal) = server_parser.limits.try_refuse() else {
+            warn!(%peer, "Too many sessions and refusals, the connection is closed");
+            return;
+        };
+        warn!(%peer, "Too many sessions, the connection is refused");
+        server_parser.shutdown.spawn_session(async move {
+            let _refusal = refusal;
+            let refuse = async {
+                match tls_acceptor {
+                    Some(tls_acceptor) => if let Ok(mut tls_socket) = tls_acceptor.accept(socket).await {
+                        refuse_client(&mut tls_socket).await;
+                    },
+                    None => refuse_client(&mut socket).await
+                }
+            };
+            let _ = tokio::time::timeout(REFUSE_TIMEOUT, refuse).await;
+        });
+        return;
+    }
+
+    let shutdown_parser = Arc::clone(&server_parser);
+    shutdown_parser.shutdown.spawn_session(async move {
+        let _permit = permit;
+        match tls_acceptor {
+            Some(tls_acceptor) => match tokio::time::timeout(server_parser.timeouts.header, tls_acceptor.accept(socket)).await {
+                Ok(Ok(mut tls_socket)) => server_parser.serve(&mut tls_socket, peer).await,
+                Ok(Err(e)) => warn!(%peer, error = %e, "TLS handshake failed"),
+                Err(_) => warn!(%peer, "TLS handshake timed out")
+            },
+            None => server_parser.serve(&mut socket, peer).await
+        };
+    });
+}
+
+
+/// Send the server busy response code, without retrying.
+async fn refuse_client<S: Stream>(socket: &mut S) {
+    let response_header = ResponseHeader::new(1, 0, RC_SERVER_BUSY, None);
+    if socket.write_all(response_header.get_header()).await.is_ok() {
+        let _ = socket.shutdown().await;
+    }
+}
+
+
+/// Bind the Unix domain socket and set the permissions of its file.
+/// A socket file left by a previous run is removed.
+fn bind_unix(path: &Path, mode: u32) -> std::io::Result<UnixListener> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::{os::unix::fs::MetadataExt, path::PathBuf};
+    use tokio::{net::{TcpStream, UnixStream}, io::AsyncReadExt};
+    use crate::server::{test_utils::*, response::*};
+    use super::*;
+
+    fn listen_config(address: ListenAddress, mode: u32) -> ListenConfig {
+        ListenConfig { address, tls: None, mode }
+    }
+
+    #[tokio::test]
+    async fn unix_socket_should_have_the_mode_and_authenticate_local_peers() {
+        let path = std::env::temp_dir().join(format!("nftp-test-{}.sock", std::process::id()));
+        let listen_config = listen_config(ListenAddress::Unix(path.clone()), 0o600);
+        // a stale socket file must not prevent the bind
+        drop(Listener::bind(&listen_config).await.unwrap());
+        let listener = Listener::bind(&listen_config).await.unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+        // the socket file is owned by the current user, the same of the client
+        let uid = metadata.uid();
+        let parser = test_parser(&format!("[[auth.local_peers]]\nuid = {}\nuser = \"alice\"", uid));
+        tokio::spawn(listener.accept(parser));
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        let (response_code, _) = send(&mut client, &request(1, &[], None), true).await;
+        assert_eq!(response_code, RC_OK);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn many_listeners_should_share_the_same_parser() {
+        let parser = test_parser("");
+        let mut addresses = Vec::new();
+        for address in ["127.0.0.1:0", "[::1]:0"] {
+            let listener = Listener::bind(&listen_config(ListenAddress::Tcp(address.parse().unwrap()), 0)).await.unwrap();
+            let ListenerSocket::Tcp(tcp_listener) = &listener.socket else { unreachable!() };
+            addresses.push(tcp_listener.local_addr().unwrap());
+            tokio::spawn(listener.accept(Arc::clone(&parser)));
+        }
+
+        let mut client = TcpStream::connect(addresses[0]).await.unwrap();
+        assert_eq!(send(&mut client, &request(1, &[], None), true).await, (RC_AUTH_FAILED, None));
+        assert_eq!(send(&mut client, &request(3, &[], Some(TOKEN)), false).await, (RC_OK, None));
+
+        let mut client = TcpStream::connect(addresses[1]).await.unwrap();
+        assert_eq!(send(&mut client, &request(3, &[], Some(TOKEN)), false).await, (RC_OK, None));
+    }
+
+    #[tokio::test]
+    async fn connections_over_the_per_ip_cap_should_be_refused() {
+        let parser = test_parser("[limits]\nmax_sessions_per_ip = 1");
+        let listener = Listener::bind(&listen_config(ListenAddress::Tcp("127.0.0.1:0".parse().unwrap()), 0)).await.unwrap();
+        let ListenerSocket::Tcp(tcp_listener) = &listener.socket else { unreachable!() };
+        let address = tcp_listener.local_addr().unwrap();
+        tokio::spawn(listener.accept(parser));
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        assert_eq!(send(&mut client, &request(3, &[], Some(TOKEN)), false).await, (RC_OK, None));
+
+        let mut refused = TcpStream::connect(address).await.unwrap();
+        let mut header = [0u8; 6];
+        refused.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[5], RC_SERVER_BUSY);
+        assert_eq!(refused.read(&mut header).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn bind_with_bad_tls_config_should_return_err() {
+        let mut listen_config = listen_config(ListenAddress::Tcp("127.0.0.1:0".parse().unwrap()), 0);
+        listen_config.tls = Some(crate::server::config::TlsConfig {
+            cert: PathBuf::from("./tests/tls/missing.pem"),
+            key: PathBuf::from("./tests/tls/server.key"),
+            client_ca: None,
+            client_auth_optional: false
+        });
+        assert!(Listener::bind(&listen_config).await.is_err());
+    }
+
+    #[test]
+    fn accept_backoff_should_double_up_to_the_max() {
+        let mut backoff = AcceptBackoff::default();
+
+        let delays: Vec<u64> = (0..6).map(|_| backoff.failed().as_millis() as u64).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        backoff.reset();
+        assert_eq!(backoff.failed(), ACCEPT_BACKOFF_MIN);
+    }
+}
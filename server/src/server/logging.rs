@@ -0,0 +1,35 @@
+use tracing_subscriber::EnvFilter;
+use super::config::{LogConfig, LogFormat};
+
+/// Install the global subscriber that writes the diagnostics on stdout,
+/// with the level and the format of the config.
+pub fn init(log_config: &LogConfig) -> Result<(), String> {
+    let filter = build_filter(&log_config.level)?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match log_config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Pretty => builder.pretty().try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init()
+    };
+    result.map_err(|e| e.to_string())
+}
+
+
+/// Parse the level directives: a level (`info`) or a list of targets with their
+/// levels (`info,server::server::auth=debug`).
+fn build_filter(level: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(level).map_err(|e| format!("Invalid log level `{}`: {}", level, e))
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn build_filter_should_accept_levels_and_targets() {
+        assert!(build_filter("debug").is_ok());
+        assert!(build_filter("warn,server::server::auth=trace").is_ok());
+        assert!(build_filter("info,server=loud").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    collections::BTreeMap, fmt::Write as _, io, pin::Pin, sync::{Arc, Mutex},
+    sync::atomic::{AtomicU64, AtomicI64, Ordering::Relaxed}, task::{Context, Poll}, time::Duration
+};
+use tokio::{io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt, ReadBuf}, net::{TcpListener, TcpStream}};
+use super::{parser::Parser, listener::AcceptBackoff};
+
+/// Upper bounds of the buckets of the duration histograms, in seconds
+const DURATION_BUCKETS: [f64; 11] = [0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+/// Max dimension of an HTTP request to the metrics endpoint
+const MAX_HTTP_REQUEST: usize = 8 * 1024;
+/// Time given to an HTTP client to send its request
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters and histograms of the server, exposed in the Prometheus text format.
+pub struct Metrics {
+    connections: AtomicU64,
+    connections_refused: AtomicU64,
+    sessions_active: AtomicI64,
+    /// Requests by istruction code
+    requests: [AtomicU64; 256],
+    /// Responses by response code
+    responses: [AtomicU64; 256],
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Durations of the requests by istruction code
+    durations: Mutex<BTreeMap<u8, Histogram>>
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            connections: AtomicU64::new(0),
+            connections_refused: AtomicU64::new(0),
+            sessions_active: AtomicI64::new(0),
+            requests: std::array::from_fn(|_| AtomicU64::new(0)),
+            responses: std::array::from_fn(|_| AtomicU64::new(0)),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            durations: Mutex::new(BTreeMap::new())
+        }
+    }
+
+    /// Count an accepted connection; `refused` if it was over the connection limits.
+    pub fn connection_accepted(&self, refused: bool) {
+        self.connections.fetch_add(1, Relaxed);
+        if refused {
+            self.connections_refused.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Count a running session until the returned guard is dropped.
+    pub fn session_started(&self) -> ActiveSession<'_> {
+        self.sessions_active.fetch_add(1, Relaxed);
+        ActiveSession { metrics: self }
+    }
+
+    /// Count an executed istruction and its duration.
+    pub fn request_executed(&self, istruction: u8, duration: Duration) {
+        self.requests[istruction as usize].fetch_add(1, Relaxed);
+        let seconds = duration.as_secs_f64();
+        let mut durations = self.durations.lock().unwrap();
+        let histogram = durations.entry(istruction).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Count a response sent to a client.
+    pub fn response_sent(&self, response_code: u8) {
+        self.responses[response_code as usize].fetch_add(1, Relaxed);
+    }
+
+    /// Render all the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::with_capacity(4096);
+        counter(&mut out, "nftp_connections_total", "Accepted connections.", self.connections.load(Relaxed));
+        counter(&mut out, "nftp_connections_refused_total", "Connections refused for the connection limits.",
+            self.connections_refused.load(Relaxed));
+        let _ = writeln!(out, "# HELP nftp_sessions_active Sessions being served.\n# TYPE nftp_sessions_active gauge");
+        let _ = writeln!(out, "nftp_sessions_active {}", self.sessions_active.load(Relaxed));
+        counter(&mut out, "nftp_bytes_received_total", "Bytes received from the clients.", self.bytes_received.load(Relaxed));
+        counter(&mut out, "nftp_bytes_sent_total", "Bytes sent to the clients.", self.bytes_sent.load(Relaxed));
+
+        labeled_counters(&mut out, "nftp_requests_total", "Executed requests by istruction code.", "istruction", &self.requests);
+        labeled_counters(&mut out, "nftp_responses_total", "Responses by response code.", "code", &self.responses);
+
+        let _ = writeln!(out, "# HELP nftp_request_duration_seconds Duration of the requests by istruction code.");
+        let _ = writeln!(out, "# TYPE nftp_request_duration_seconds histogram");
+        for (istruction, histogram) in self.durations.lock().unwrap().iter() {
+            for (bucket, bound) in histogram.buckets.iter().zip(DURATION_BUCKETS) {
+                let _ = writeln!(out, "nftp_request_duration_seconds_bucket{{istruction=\"{}\",le=\"{}\"}} {}", istruction, bound, bucket);
+            }
+            let _ = writeln!(out, "nftp_request_duration_seconds_bucket{{istruction=\"{}\",le=\"+Inf\"}} {}", istruction, histogram.count);
+            let _ = writeln!(out, "nftp_request_duration_seconds_sum{{istruction=\"{}\"}} {}", istruction, histogram.sum);
+            let _ = writeln!(out, "nftp_request_duration_seconds_count{{istruction=\"{}\"}} {}", istruction, histogram.count);
+        }
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+}
+
+fn labeled_counters(out: &mut String, name: &str, help: &str, label: &str, values: &[AtomicU64; 256]) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (code, value) in values.iter().enumerate() {
+        let value = value.load(Relaxed);
+        if value != 0 {
+            let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, code, value);
+        }
+    }
+}
+
+
+/// A running session, counted until dropped.
+pub struct ActiveSession<'a> {
+    metrics: &'a Metrics
+}
+impl Drop for ActiveSession<'_> {
+    fn drop(&mut self) {
+        self.metrics.sessions_active.fetch_sub(1, Relaxed);
+    }
+}
+
+
+/// A stream that counts the bytes received and sent through it.
+pub struct MeteredStream<'a, S: ?Sized> {
+    inner: &'a mut S,
+    metrics: &'a Metrics
+}
+impl<'a, S: ?Sized> MeteredStream<'a, S> {
+    pub fn new(inner: &'a mut S, metrics: &'a Metrics) -> Self {
+        MeteredStream { inner, metrics }
+    }
+}
+impl<S: AsyncRead + Unpin + ?Sized> AsyncRead for MeteredStream<'_, S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.metrics.bytes_received.fetch_add((buf.filled().len() - before) as u64, Relaxed);
+        }
+        poll
+    }
+}
+impl<S: AsyncWrite + Unpin + ?Sized> AsyncWrite for MeteredStream<'_, S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.metrics.bytes_sent.fetch_add(n as u64, Relaxed);
+        }
+        poll
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+
+/// Serve the metrics of the parser on `GET /metrics`, until the shutdown.
+/// The failed accepts are retried with the backoff of the listeners.
+pub async fn serve_http(listener: TcpListener, server_parser: Arc<Parser>) {
+    let mut backoff = AcceptBackoff::default();
+    loop {
+        let socket = tokio::select! {
+            _ = server_parser.shutdown.triggered() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Metrics endpoint accept failed");
+                    if !backoff.wait(&server_parser).await {
+                        break;
+                    }
+                    continue
+                }
+            }
+        };
+        backoff.reset();
+        let server_parser = Arc::clone(&server_parser);
+        tokio::spawn(async move {
+            if let Err(e) = tokio::time::timeout(HTTP_TIMEOUT, answer_http(socket, &server_parser)).await {
+                tracing::debug!(error = %e, "Metrics request timed out");
+            }
+        });
+    }
+}
+
+/// Read an HTTP request and answer with the metrics, or with 404 for any other path.
+async fn answer_http(mut socket: TcpStream, server_parser: &Parser) {
+    let mut request = Vec::with_capacity(1024);
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) if request.len() + n > MAX_HTTP_REQUEST => return,
+            Ok(n) => request.extend_from_slice(&buf[..n])
+        }
+    }
+
+    let response = if request.starts_with(b"GET /metrics ") || request.starts_with(b"GET /metrics?") {
+        let mut body = server_parser.metrics.render();
+        counter(&mut body, "nftp_audit_records_dropped_total", "Audit records dropped because the audit log didn't keep up.",
+            server_parser.audit.dropped());
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body)
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    let _ = socket.write_all(response.as_bytes()).await;
+    let _ = socket.shutdown().await;
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use crate::server::{test_utils::*, response::RC_OK};
+    use super::*;
+
+    #[test]
+    fn render_should_return_the_prometheus_text_format() {
+        let metrics = Metrics::new();
+        metrics.connection_accepted(false);
+        metrics.connection_accepted(true);
+        let session = metrics.session_started();
+        metrics.request_executed(0, Duration::from_millis(200));
+        metrics.response_sent(1);
+        metrics.response_sent(1);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE nftp_connections_total counter\nnftp_connections_total 2\n"));
+        assert!(text.contains("nftp_connections_refused_total 1\n"));
+        assert!(text.contains("nftp_sessions_active 1\n"));
+        assert!(text.contains("nftp_requests_total{istruction=\"0\"} 1\n"));
+        assert!(text.contains("nftp_responses_total{code=\"1\"} 2\n"));
+        assert!(text.contains("nftp_request_duration_seconds_bucket{istruction=\"0\",le=\"0.1\"} 0\n"));
+        assert!(text.contains("nftp_request_duration_seconds_bucket{istruction=\"0\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("nftp_request_duration_seconds_count{istruction=\"0\"} 1\n"));
+
+        drop(session);
+        assert!(metrics.render().contains("nftp_sessions_active 0\n"));
+    }
+
+    #[tokio::test]
+    async fn metrics_endpoint_should_expose_the_served_requests() {
+        let parser = test_parser("");
+        let mut client = logged_client(Arc::clone(&parser)).await;
+        // the first LIST is counted before the second one is served
+        assert_eq!(send(&mut client, &request(1, &[], None), true).await.0, RC_OK);
+        assert_eq!(send(&mut client, &request(1, &[], None), true).await.0, RC_OK);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(listener, parser));
+
+        let mut http = TcpStream::connect(address).await.unwrap();
+        http.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("nftp_requests_total{istruction=\"1\"} "));
+        assert!(response.contains("nftp_requests_total{istruction=\"3\"} 1\n"));
+        assert!(response.contains("nftp_sessions_active 1\n"));
+        assert!(!response.contains("nftp_bytes_sent_total 0\n"));
+
+        let mut http = TcpStream::connect(address).await.unwrap();
+        http.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -2,31 +2,85 @@ pub mod parser;
 pub mod version_trait;
 pub mod version_structs;
 pub mod response;
+pub mod config;
+pub mod auth;
+pub mod session;
+pub mod user_space;
+pub mod acl;
+pub mod tls;
+pub mod listener;
+pub mod shutdown;
+pub mod limits;
+pub mod timeouts;
+pub mod throttle;
+pub mod logging;
+pub mod audit;
+pub mod metrics;
+pub mod watch;
+pub mod search;
+pub mod du;
+pub mod quotas;
+#[cfg(test)]
+pub mod test_utils;
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt};
 
-use std::path::PathBuf;
-use tokio::{net::TcpStream, io::AsyncReadExt};
+/// Any bidirectional stream a client can be served on: a plain TCP connection,
+/// a TLS connection, an in-memory duplex pipe, ...
+/// The istructions receive it as a trait object, so the same code serves every transport.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + ?Sized> Stream for T {}
 
 /// Read bytes from the socket and return the number of bytes readed.
 #[inline]
-async fn read_bytes(socket: &mut TcpStream, buf: &mut Vec<u8>) -> Option<usize> {
-    match (*socket).read(&mut buf[..]).await {
-        Ok(n) if n <= 0 => {
-            println!("{}: Read 0 bytes", socket.peer_addr().unwrap(),);
-            return None;
+async fn read_bytes<S: AsyncRead + Unpin + ?Sized>(socket: &mut S, buf: &mut [u8]) -> Option<usize> {
+    match (*socket).read(buf).await {
+        Ok(0) => {
+            tracing::debug!("Connection closed by the client");
+            None
         },
-        Ok(n) => return Some(n),
+        Ok(n) => Some(n),
         Err(e) => {
-            println!("{}: {}", socket.peer_addr().unwrap(), e);
-            return None;
+            tracing::warn!(error = %e, "Read failed");
+            None
         }
     }
 }
 
 
+/// Extensions of the temporary files written by the uploads in progress
+const TEMPORARY_EXTENSIONS: [&str; 2] = ["nftp-part", "nftp-delta"];
+
+/// Return the path of a new temporary file next to `path`, with the given extension
+/// (one of `TEMPORARY_EXTENSIONS`). The name is hidden and unique within the server,
+/// so concurrent uploads to the same path don't share it: the file should still be
+/// created with `create_new`, to never reuse one left by another process.
+pub fn temporary_path(path: &Path, extension: &str) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}-{}.{}", file_name, std::process::id(), id, extension))
+}
+
+/// Return true if the name is the one of a temporary file of an upload in progress:
+/// LIST, SEARCH, DU, WATCH and the quotas ignore them.
+pub fn is_temporary(name: &OsStr) -> bool {
+    let Some(name) = name.to_str() else { return false };
+    name.starts_with('.') && TEMPORARY_EXTENSIONS.iter()
+        .any(|extension| name.strip_suffix(extension).is_some_and(|stem| stem.ends_with('.')))
+}
+
+
 /// Light Weight Serialization for the tree structure of filesystem.
 /// The rules are:
 /// 1. for each directory, after the name use '{' and '}' to represent its contents;
 /// 2. for each file, after the name use ',' to represent that it's a file;
+///
+/// The entries whose name isn't UTF-8 or can't be serialized (see `is_serializable`)
+/// are left out.
 /// 
 /// # Examples
 /// ```
@@ -46,35 +100,64 @@ async fn read_bytes(socket: &mut TcpStream, buf: &mut Vec<u8>) -> Option<usize>
 /// 
 /// # Arguments
 /// * `path` - the path from which to start the serialization.
+/// * `visible` - tells if an entry, by its path under `path` (e.g. `/dir_1/file.txt`), is serialized:
+///   a hidden directory is skipped with everything under it.
 /// * `result` - the mutable string that will contain the result.
 /// 
-pub fn tree_serialization(path: &PathBuf, result: &mut String) {
-    let dir = std::fs::read_dir(&path).expect("It is impossibile to read this Directory...");
+/// # Errors
+/// If a directory or one of its entries can't be read.
+pub fn tree_serialization(path: &Path, visible: &dyn Fn(&str) -> bool, result: &mut String) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    named_tree_serialization(&name, path, "/", visible, result)
+}
+
+
+/// Same as `tree_serialization`, but the starting directory is serialized with
+/// the given name instead of its own, and the paths given to `visible` start
+/// with `user_path`, the path of the directory in the user space.
+pub fn named_tree_serialization(name: &str, path: &Path, user_path: &str, visible: &dyn Fn(&str) -> bool, result: &mut String) -> std::io::Result<()> {
+    let dir = std::fs::read_dir(path)?;
 
-    result.push_str(path.file_name().unwrap().to_str().unwrap());
+    result.push_str(name);
     result.push('{');
 
     for result_path in dir {
-        let p = result_path.expect("It is impossible to get a path in the Directory...");
-        
-        if !p.file_type().unwrap().is_dir() {
+        let p = result_path?;
+        let file_name = p.file_name();
+        let Some(file_name) = file_name.to_str().filter(|name| is_serializable(name)) else {
+            tracing::debug!(name = ?file_name, "Name not serializable for LIST request");
+            continue;
+        };
+        let child_user_path = format!("{}/{}", user_path.trim_end_matches('/'), file_name);
+        if !visible(&child_user_path) {
+            continue;
+        }
+
+        if !p.file_type()?.is_dir() {
             // is file
-            result.push_str(p.file_name().to_str().unwrap());
+            result.push_str(file_name);
             result.push(',');
             continue;
         }
         // is dir
-        tree_serialization(&path.join(p.file_name()), result);
+        named_tree_serialization(file_name, &path.join(file_name), &child_user_path, visible, result)?;
     }
 
     result.push('}');
+    Ok(())
+}
+
+/// Return true if the name can be an entry of the serialized tree: it has none of its
+/// delimiters and it isn't the name of a temporary file of an upload.
+pub fn is_serializable(name: &str) -> bool {
+    !name.contains(['{', '}', ',']) && !is_temporary(OsStr::new(name))
 }
 
 
 #[cfg(test)]
 pub mod test {
     use std::path::PathBuf;
-    use super::tree_serialization;
+    use super::*;
 
     #[test]
     fn tree_serialization_each_opening_parenthesis_is_properly_closed() {
@@ -82,7 +165,7 @@ pub mod test {
         let path = PathBuf::from("./tests/tree_serialization/root");
         let mut counter = 0;
 
-        tree_serialization(&path, &mut result);
+        tree_serialization(&path, &|_| true, &mut result).unwrap();
 
         for char in result.chars() {
             if char == '{' {
@@ -100,8 +183,10 @@ pub mod test {
     fn tree_serialization_result_contains_all_the_strings_of_the_filesystem() {
         let mut result = String::new();
         let path = PathBuf::from("./tests/tree_serialization/root");
+        // git doesn't keep track of empty directories
+        std::fs::create_dir_all(path.join("dir_2/dir_3/dir_4")).unwrap();
 
-        tree_serialization(&path, &mut result);
+        tree_serialization(&path, &|_| true, &mut result).unwrap();
 
         assert!(result.contains("root{"));
         assert!(result.contains("dir_1{"));
@@ -111,4 +196,27 @@ pub mod test {
         assert!(result.contains("file_1.txt,"));
         assert!(result.contains("file_3.txt,"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn temporary_paths_should_be_unique_and_recognized() {
+        let path = Path::new("/home/alice/file.txt");
+        let first = temporary_path(path, "nftp-part");
+        let second = temporary_path(path, "nftp-part");
+
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), path.parent());
+        assert!(is_temporary(first.file_name().unwrap()));
+        assert!(is_temporary(temporary_path(path, "nftp-delta").file_name().unwrap()));
+        assert!(!is_temporary(OsStr::new("file.txt")));
+        assert!(!is_temporary(OsStr::new("notes.nftp-part")));
+    }
+
+    #[test]
+    fn tree_serialization_should_fail_on_a_missing_directory() {
+        let mut result = String::new();
+
+        assert!(tree_serialization(Path::new("./tests/tree_serialization/missing"), &|_| true, &mut result).is_err());
+        assert!(is_serializable("file.txt"));
+        assert!(!is_serializable("a,b") && !is_serializable("{dir}"));
+    }
+}
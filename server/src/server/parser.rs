@@ -1,56 +1,308 @@
-use std::{str::from_utf8, path::PathBuf};
-use tokio::net::TcpStream;
+use std::{str::from_utf8, path::{Path, PathBuf}, collections::HashMap, time::Duration};
+use tokio::{time::{timeout, Instant}, io::{AsyncWrite, AsyncWriteExt}};
+use protocol::request::MAX_PATHS;
+use tracing::{debug, info, warn, info_span, field, Instrument, Span};
 use super::{
     version_trait::Version, 
     version_structs::version_1_0::Version1_0,
-    read_bytes
+    config::Config,
+    auth::Authenticator,
+    session::{Session, Peer},
+    user_space::UserSpace,
+    acl::{AccessControl, Permission},
+    shutdown::Shutdown,
+    limits::ConnectionLimits,
+    timeouts::{Timeouts, write_with_timeout, read_with_timeout, CHUNK_SIZE},
+    throttle::Throttle,
+    audit::AuditLog,
+    quotas::Quotas,
+    metrics::{Metrics, MeteredStream},
+    response::{send_error_response, reassemble_u64_from_bytes, RetryPolicy, RC_OK, RC_ERROR, RC_AUTH_FAILED, RC_PERMISSION_DENIED, RC_TIMEOUT, RC_QUOTA_EXCEEDED},
+    read_bytes,
+    Stream
 };
 
-/// Max number of accepted paths
-const MAX_PATHS: u8 = 10;
 /// Max dimension of the header buffer
-const MAX_HEADER_BUF: usize = 1024;
+const MAX_HEADER_BUF: usize = protocol::request::MAX_REQUEST_LEN;
 
 pub struct Parser {
-    main_path: PathBuf
+    /// Root of the served filesystem
+    pub main_path: PathBuf,
+    pub authenticator: Authenticator,
+    /// The space of each user, by user name
+    pub user_spaces: HashMap<String, UserSpace>,
+    pub access_control: AccessControl,
+    pub quotas: Quotas,
+    pub shutdown: Shutdown,
+    pub limits: ConnectionLimits,
+    pub timeouts: Timeouts,
+    /// How the error responses are retried
+    pub error_retry: RetryPolicy,
+    pub throttle: Throttle,
+    pub audit: AuditLog,
+    pub metrics: Metrics
 }
 impl Parser {
-    /// Create a parser struct. Opens the config.txt file and extracts the main path from it.
-    pub fn new() -> Self {
-        let main_path = PathBuf::from(
-            std::fs::read_to_string("./config.txt").expect("Read error in config.txt file")
-        );
-        println!("The MAIN PATH: {}", main_path.display());
-        if !main_path.exists() || !main_path.is_absolute() {
-            panic!("The path read from config.txt file does not exists or isn't absolute");
-        }
-        Parser { main_path }
+    /// Create a parser struct. Extracts the main path, the users and the access control 
+    /// lists from the config, mapping each user to its own space.
+    pub fn new(config: Config) -> Self {
+        info!(main_path = %config.main_path.display(), "Serving the main path");
+        if !config.main_path.exists() || !config.main_path.is_absolute() {
+            panic!("The path read from config.toml file does not exists or isn't absolute");
+        }
+        if config.users.is_empty() {
+            warn!("No users in config.toml file, nobody will be able to log in");
+        }
+
+        let mut user_spaces = HashMap::with_capacity(config.users.len());
+        for user in &config.users {
+            let space = UserSpace::from_config(&config.main_path, user);
+            for root in space.roots() {
+                if !root.is_dir() {
+                    panic!("The directory {} of the user {} does not exists", root.display(), user.name);
+                }
+            }
+            user_spaces.insert(user.name.clone(), space);
+        }
+        for local_peer in &config.auth.local_peers {
+            if !user_spaces.contains_key(&local_peer.user) {
+                panic!("The local peer user {} does not exists", local_peer.user);
+            }
+        }
+
+        let quotas = match Quotas::new(config.quotas, &config.main_path, &user_spaces) {
+            Ok(quotas) => quotas,
+            Err(e) => panic!("Invalid quota in config.toml file: {}", e)
+        };
+
+        let audit = match AuditLog::new(&config.audit) {
+            Ok(audit) => audit,
+            Err(e) => panic!("The audit log can't be opened: {}", e)
+        };
+
+        Parser {
+            audit,
+            quotas,
+            metrics: Metrics::new(),
+            authenticator: Authenticator::new(config.auth, config.users),
+            access_control: AccessControl::new(config.acl, config.groups),
+            shutdown: Shutdown::new(Duration::from_secs(config.shutdown.grace_seconds)),
+            limits: ConnectionLimits::new(&config.limits),
+            timeouts: Timeouts::new(&config.timeouts),
+            throttle: Throttle::new(config.throttle),
+            error_retry: RetryPolicy::new(&config.error_response, Duration::from_secs(config.timeouts.chunk_seconds)),
+            user_spaces,
+            main_path: config.main_path
+        }
     }
 
-    /// Parse the input bytes according to nFTP protocol.
+    /// Return the space of the user authenticated in the session.
     #[inline]
-    pub async fn process_request(&self, socket: &mut TcpStream) -> bool {
+    pub fn user_space(&self, session: &Session) -> Option<&UserSpace> {
+        self.user_spaces.get(session.user.as_ref()?)
+    }
+
+    /// Check that the user authenticated in the session has the permission on the path.
+    /// Return the response code to send back to the client if it hasn't.
+    #[inline]
+    pub fn check_permission(&self, session: &Session, path: &Path, permission: Permission) -> Result<(), u8> {
+        let user = session.user.as_ref().ok_or(RC_AUTH_FAILED)?;
+        if !self.access_control.is_allowed(user, path, permission) {
+            warn!(?permission, %user, path = %path.display(), "Permission denied");
+            return Err(RC_PERMISSION_DENIED);
+        }
+        Ok(())
+    }
+
+    /// Check the permission of the session on the path and map it to the real path
+    /// on the filesystem.
+    /// Return the response code to send back to the client if it isn't possible.
+    pub fn resolve(&self, session: &Session, path: &Path, permission: Permission) -> Result<PathBuf, u8> {
+        self.check_permission(session, path, permission)?;
+        let user_space = self.user_space(session).ok_or(RC_AUTH_FAILED)?;
+        match user_space.resolve(path) {
+            Some(real_path) => Ok(real_path),
+            None => {
+                warn!(path = %path.display(), "The path is outside the user space");
+                Err(RC_ERROR)
+            }
+        }
+    }
+
+    /// Read a payload from the socket into the writer (a file, a buffer, ...) one chunk
+    /// at a time, respecting the bandwidth limits and the chunk timeout. `received` is
+    /// the part of the payload already read with the request.
+    /// In case of error the rest of the payload is not consumed, so the session is closed.
+    pub async fn read_payload(&self,
+        socket: &mut dyn Stream,
+        received: &[u8],
+        payload_dim: u64,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+        session: &mut Session
+    ) -> Result<(), u8>
+    {
+        let result = self.copy_payload(socket, received, payload_dim, writer, session).await;
+        if result.is_err() {
+            session.closing = true;
+        }
+        result
+    }
+
+    async fn copy_payload(&self,
+        socket: &mut dyn Stream,
+        received: &[u8],
+        payload_dim: u64,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+        session: &mut Session
+    ) -> Result<(), u8>
+    {
+        let file_error = |e: std::io::Error| {
+            warn!(error = %e, "File not writable");
+            if e.kind() == std::io::ErrorKind::QuotaExceeded { RC_QUOTA_EXCEEDED } else { RC_ERROR }
+        };
+        writer.write_all(received).await.map_err(file_error)?;
+        let mut remaining = payload_dim - received.len() as u64;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        while remaining > 0 {
+            let to_read = chunk.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+            let n = read_with_timeout(socket, &mut chunk[..to_read], self.timeouts.chunk).await?;
+            self.throttle.acquire(session, n).await;
+            session.transferred += n as u64;
+            writer.write_all(&chunk[..n]).await.map_err(file_error)?;
+            remaining -= n as u64;
+        }
+        writer.flush().await.map_err(file_error)
+    }
+
+    /// Write a payload into the socket one chunk at a time, respecting the bandwidth
+    /// limits and the chunk timeout.
+    pub async fn write_payload(&self, socket: &mut dyn Stream, bytes: &[u8], session: &mut Session) -> Result<(), u8> {
+        for chunk in bytes.chunks(CHUNK_SIZE) {
+            self.throttle.acquire(session, chunk.len()).await;
+            write_with_timeout(socket, chunk, self.timeouts.chunk).await?;
+            session.transferred += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Serve all the requests of a client, until it closes the connection, the
+    /// server shuts down or a timeout fires. A running request is always completed
+    /// before closing for the shutdown.
+    /// Local peers with trusted credentials start already authenticated.
+    pub async fn serve<S: Stream>(&self, socket: &mut S, peer: Peer) {
+        self.serve_session(socket, peer).instrument(info_span!("session", %peer)).await
+    }
+
+    async fn serve_session<S: Stream>(&self, socket: &mut S, peer: Peer) {
+        info!("Session started");
+        let _active_session = self.metrics.session_started();
+        let socket = &mut MeteredStream::new(socket, &self.metrics);
+        let mut session = Session::new(peer);
+        if let Peer::Unix { uid, gid } = peer {
+            session.user = self.authenticator.login_with_peer_credentials(uid, gid);
+            if let Some(user) = &session.user {
+                info!(%user, "Authenticated by its credentials");
+            }
+        }
         let mut input_bytes: Vec<u8> = vec![0; MAX_HEADER_BUF];
-        
-        let readed_bytes = read_bytes(socket, &mut input_bytes).await;
-        let readed_bytes = if readed_bytes.is_none() { return false } else { readed_bytes.unwrap() };
-        println!("{}: readed bytes {}", socket.peer_addr().unwrap(), readed_bytes); // log
+        let mut read_timeout = self.timeouts.header;
+
+        loop {
+            let readed_bytes = tokio::select! {
+                biased;
+                _ = self.shutdown.triggered() => {
+                    info!("Closing the session for the shutdown");
+                    break
+                },
+                readed_bytes = timeout(read_timeout, read_bytes(socket, &mut input_bytes)) => match readed_bytes {
+                    Ok(Some(readed_bytes)) => readed_bytes,
+                    Ok(None) => break,
+                    Err(_) => {
+                        warn!(timeout = ?read_timeout, "No request in time, closing the session");
+                        send_error_response(socket, RC_TIMEOUT, &self.error_retry, &self.shutdown).await;
+                        break
+                    }
+                }
+            };
+            read_timeout = self.timeouts.idle;
+
+            let span = info_span!("request",
+                user = session.user.as_deref(), istruction = field::Empty, paths = field::Empty);
+            let start = Instant::now();
+            self.throttle.acquire(&session, readed_bytes).await;
+            session.transferred = readed_bytes as u64;
+            let request = &input_bytes[..readed_bytes];
+            let result = self.process_request(socket, request, &mut session).instrument(span.clone()).await;
+            span.in_scope(|| info!(
+                response_code = result.err().unwrap_or(RC_OK),
+                bytes = session.transferred,
+                duration_ms = start.elapsed().as_millis() as u64,
+                "Request completed"
+            ));
+            self.metrics.response_sent(result.err().unwrap_or(RC_OK));
+
+            match result {
+                Ok(()) if session.closing => {
+                    info!("Closing the session after the request");
+                    break
+                },
+                Ok(()) => (),
+                Err(RC_TIMEOUT) => {
+                    send_error_response(socket, RC_TIMEOUT, &self.error_retry, &self.shutdown).instrument(span).await;
+                    break
+                },
+                Err(response_code) => {
+                    if !send_error_response(socket, response_code, &self.error_retry, &self.shutdown).instrument(span).await {
+                        warn!("Closing the session, the error response can't be sent");
+                        break
+                    }
+                    if session.closing {
+                        info!("Closing the session, the rest of the request can't be consumed");
+                        break
+                    }
+                }
+            }
+        }
+        info!("Session closed");
+    }
+
+    /// Parse the input bytes according to nFTP protocol and execute the istruction,
+    /// recording it in the audit log.
+    /// Return the response code to send back to the client in case of error.
+    #[inline]
+    pub async fn process_request(&self, socket: &mut dyn Stream, input_bytes: &[u8], session: &mut Session) -> Result<(), u8> {
+        debug!(readed_bytes = input_bytes.len(), "Request received");
+        let start = Instant::now();
 
         let total_len = input_bytes.len();
         let mut acc_len: usize = 4;
         let mut index: usize = 0;
         
-        if !protocol_recognition(&input_bytes, &total_len, &mut acc_len, &mut index) { return false }
-
-        let version = version_recognition(&input_bytes, &total_len, &mut acc_len, &mut index);
-        let version = if version.is_none() { return false } else { version.unwrap() };
-
-        let istruction = version.parse(&input_bytes, &total_len, &mut acc_len, &mut index);
-        let istruction = if istruction.is_none() { return false } else { istruction.unwrap() };
-
-        if !istruction.execute(socket, &input_bytes, &self.main_path).await { return false }
-        
-        true
+        if !protocol_recognition(input_bytes, &total_len, &mut acc_len, &mut index) { return Err(RC_ERROR) }
+
+        let version = match version_recognition(input_bytes, &total_len, &mut acc_len, &mut index) {
+            Some(version) => version,
+            None => return Err(RC_ERROR)
+        };
+
+        let istruction = match version.parse(input_bytes, &total_len, &mut acc_len, &mut index) {
+            Some(istruction) => istruction,
+            None => return Err(RC_ERROR)
+        };
+        let current_span = Span::current();
+        current_span.record("istruction", istruction.get_istruction_code());
+        current_span.record("paths", field::debug(istruction.get_paths()));
+        debug!(version = format_args!("{:#010b}", version.get_version()), "Request parsed");
+
+        let result = if istruction.requires_authentication() && session.user.is_none() {
+            warn!("istruction refused, the session is not authenticated");
+            Err(RC_AUTH_FAILED)
+        } else {
+            istruction.execute(socket, input_bytes, self, session).await
+        };
+        self.audit.record(session, istruction.get_istruction_code(), istruction.get_paths(), istruction.get_username(), result.err().unwrap_or(RC_OK));
+        self.metrics.request_executed(istruction.get_istruction_code(), start.elapsed());
+        result
     }
 }
 
@@ -65,15 +317,15 @@ impl Parser {
 /// 
 #[inline]
 pub fn protocol_recognition(
-    input_bytes: &Vec<u8>, 
+    input_bytes: &[u8], 
     total_len: &usize, 
     acc_len: &mut usize, 
     index: &mut usize
 ) -> bool
 {
     if (total_len <= acc_len) || 
-        (from_utf8(&input_bytes[*index..*acc_len]).unwrap() != "nFTP") { 
-        println!("The request doesn't start with `nFTP`");
+        (&input_bytes[*index..*acc_len] != protocol::MAGIC) { 
+        debug!("The request doesn't start with `nFTP`");
         return false;
     }
     *index = *acc_len;
@@ -92,14 +344,14 @@ pub fn protocol_recognition(
 /// 
 #[inline]
 pub fn version_recognition(
-    input_bytes: &Vec<u8>, 
+    input_bytes: &[u8], 
     total_len: &usize, 
     acc_len: &mut usize, 
     index: &mut usize
 ) -> Option<Box<dyn Version>>
 {
     if total_len <= acc_len { 
-        println!("The total length isn't enough for version recognition");
+        debug!("The total length isn't enough for version recognition");
         return None;
     }
     let mask = 0b1111_0000u8;
@@ -107,16 +359,16 @@ pub fn version_recognition(
         // major
         1u8 => match input_bytes[*index] & (!mask) {
             // minor
-            0u8 => return Some(Box::new(Version1_0)),
+            0u8 => Some(Box::new(Version1_0)),
             _ => {
-                println!("The minor version isn't correct");
-                return None;
+                debug!("The minor version isn't correct");
+                None
             }
         },  _ => {
-            println!("The major version isn't correct");
-            return None;
+            debug!("The major version isn't correct");
+            None
         }
-    };
+    }
 }
 
 
@@ -130,7 +382,7 @@ pub fn version_recognition(
 /// 
 #[inline]
 pub fn istruction_recognition(
-    input_bytes: &Vec<u8>, 
+    input_bytes: &[u8], 
     total_len: &usize, 
     acc_len: &mut usize, 
     index: &mut usize
@@ -139,7 +391,7 @@ pub fn istruction_recognition(
     *index = *acc_len;
     *acc_len += 1;
     if total_len <= acc_len { 
-        println!("The total length isn't enough for istruction recognition");
+        debug!("The total length isn't enough for istruction recognition");
         return None;
     }
     Some(input_bytes[*index])
@@ -156,7 +408,7 @@ pub fn istruction_recognition(
 /// 
 #[inline]
 pub fn path_recognition(
-    input_bytes: &Vec<u8>, 
+    input_bytes: &[u8], 
     total_len: &usize, 
     acc_len: &mut usize, 
     index: &mut usize
@@ -165,13 +417,13 @@ pub fn path_recognition(
     *index = *acc_len;
     *acc_len += 1;
     if total_len < acc_len {
-        println!("n_paths error");
+        debug!("n_paths error");
         return None;
     }
     
     let n_paths = input_bytes[*index];
     if n_paths > MAX_PATHS {
-        println!("error, n_paths is {} but should be {}", n_paths, MAX_PATHS);
+        debug!("error, n_paths is {} but should be {}", n_paths, MAX_PATHS);
         return None;
     }
 
@@ -181,7 +433,7 @@ pub fn path_recognition(
         *index = *acc_len;
         *acc_len += 2;
         if total_len <= acc_len {
-            println!("path dim error");
+            debug!("path dim error");
             return None;
         }
 
@@ -190,22 +442,24 @@ pub fn path_recognition(
         *acc_len += path_dimension as usize;
 
         if total_len < acc_len {
-            println!("path error");
+            debug!("path error");
             return None;
         }
 
-        let p = from_utf8(&input_bytes[*index..*acc_len]);
-        if p.is_err() {
-            println!("from_utf8 error");
-            return None;
-        }
+        let p = match from_utf8(&input_bytes[*index..*acc_len]) {
+            Ok(p) => p,
+            Err(_) => {
+                debug!("from_utf8 error");
+                return None;
+            }
+        };
         
         paths.push(PathBuf::from(
-            if p.unwrap().contains("..") {
-                println!("The path is not real absolute");
+            if p.contains("..") {
+                debug!("The path is not real absolute");
                 return None;
             } else {
-                p.unwrap()
+                p
             }
         ));
     }
@@ -214,6 +468,44 @@ pub fn path_recognition(
 }
 
 
+/// Parse the input bytes and return the dimension of the payload according to nFTP protocol.
+/// At the end, `index` points to the first byte of the payload and `acc_len` to the
+/// first byte after it. The payload can be longer than the input bytes array.
+/// 
+/// # Arguments
+/// * `input_bytes` - the input bytes to parse.
+/// * `total_len` - the total length of the input bytes array.
+/// * `acc_len` - stands for "accumulator length". It serves as a temporary total length, for checks.
+/// * `index` - the index from which to start parsing the input bytes array.
+/// 
+#[inline]
+pub fn payload_recognition(
+    input_bytes: &[u8], 
+    total_len: &usize, 
+    acc_len: &mut usize, 
+    index: &mut usize
+) -> Option<u64>
+{
+    *index = *acc_len;
+    *acc_len += 8;
+    if total_len < acc_len {
+        debug!("payload dim error");
+        return None;
+    }
+
+    let payload_dim = reassemble_u64_from_bytes(&input_bytes[*index..*acc_len]);
+    *index = *acc_len;
+    *acc_len = match usize::try_from(payload_dim).ok().and_then(|dim| acc_len.checked_add(dim)) {
+        Some(end) => end,
+        None => {
+            debug!("payload dim too big");
+            return None;
+        }
+    };
+    Some(payload_dim)
+}
+
+
 
 #[cfg(test)]
 pub mod test {
@@ -230,7 +522,7 @@ pub mod test {
             let mut acc_len: usize = 4;
             let mut index: usize = 0;
             
-            assert_eq!(protocol_recognition(&input_bytes, &input_bytes.len(), &mut acc_len, &mut index), true);
+            assert!(protocol_recognition(&input_bytes, &input_bytes.len(), &mut acc_len, &mut index));
             assert_eq!(index, 4);
             assert_eq!(acc_len, 5);
         }
@@ -240,7 +532,7 @@ pub mod test {
             let mut input_bytes: Vec<u8> = Vec::new();
             input_bytes.extend_from_slice(b"nFTP");
             
-            assert_eq!(protocol_recognition(&input_bytes, &input_bytes.len(), &mut 4, &mut 0), false);
+            assert!(!protocol_recognition(&input_bytes, &input_bytes.len(), &mut 4, &mut 0));
         }
 
         #[test]
@@ -249,7 +541,7 @@ pub mod test {
             input_bytes.extend_from_slice(b"nftp");
             input_bytes.push(0u8);
             
-            assert_eq!(protocol_recognition(&input_bytes, &input_bytes.len(), &mut 4, &mut 0), false);
+            assert!(!protocol_recognition(&input_bytes, &input_bytes.len(), &mut 4, &mut 0));
         }
 
         #[test]
@@ -258,7 +550,7 @@ pub mod test {
             input_bytes.extend_from_slice(b"nF");
             input_bytes.push(0u8);
             
-            assert_eq!(protocol_recognition(&input_bytes, &input_bytes.len(), &mut 4, &mut 0), false);
+            assert!(!protocol_recognition(&input_bytes, &input_bytes.len(), &mut 4, &mut 0));
         }   
     }
 
@@ -344,4 +636,164 @@ pub mod test {
             assert_eq!(paths, Some(vec![]));
         }
     }
+
+    pub mod payload_recognition_test {
+        use super::super::*;
+
+        #[test]
+        fn payload_recognition_test() {
+            let mut input_bytes: Vec<u8> = vec![];
+            input_bytes.extend_from_slice(&5u64.to_be_bytes());
+            input_bytes.extend_from_slice(b"hello");
+
+            let total_len = input_bytes.len();
+            let mut acc_len = 0;
+            let mut index = 0;
+
+            let payload_dim = payload_recognition(&input_bytes, &total_len, &mut acc_len, &mut index);
+            assert_eq!(payload_dim, Some(5));
+            assert_eq!(&input_bytes[index..acc_len], b"hello");
+        }
+
+        #[test]
+        fn payload_recognition_with_truncated_dimension_should_return_err() {
+            let input_bytes: Vec<u8> = vec![0, 0, 0, 5];
+
+            let total_len = input_bytes.len();
+            let mut acc_len = 0;
+            let mut index = 0;
+
+            assert!(payload_recognition(&input_bytes, &total_len, &mut acc_len, &mut index).is_none());
+        }
+
+        #[test]
+        fn payload_recognition_with_huge_dimension_should_return_err() {
+            let input_bytes: Vec<u8> = u64::MAX.to_be_bytes().to_vec();
+
+            let total_len = input_bytes.len();
+            let mut acc_len = 0;
+            let mut index = 0;
+
+            assert!(payload_recognition(&input_bytes, &total_len, &mut acc_len, &mut index).is_none());
+        }
+    }
+
+    /// Tests for the function "serve", on in-memory streams
+    pub mod serve_test {
+        use std::sync::Arc;
+        use tokio::io::AsyncReadExt;
+        use crate::server::{test_utils::*, response::*, session::Peer};
+
+        #[tokio::test]
+        async fn istruction_before_login_should_return_auth_failed() {
+            let mut client = connect(test_parser(""));
+            assert_eq!(send(&mut client, &request(1, &[], None), true).await, (RC_AUTH_FAILED, None));
+        }
+
+        #[tokio::test]
+        async fn login_with_wrong_token_should_return_auth_failed() {
+            let mut client = connect(test_parser(""));
+            assert_eq!(send(&mut client, &request(3, &[], Some(b"wrong")), false).await, (RC_AUTH_FAILED, None));
+            assert_eq!(send(&mut client, &request(1, &[], None), true).await, (RC_AUTH_FAILED, None));
+        }
+
+        #[tokio::test]
+        async fn trusted_local_peer_should_not_need_login() {
+            let parser = test_parser("[[auth.local_peers]]\nuid = 1000\nuser = \"alice\"");
+
+            let mut client = connect_as(parser.clone(), Peer::Unix { uid: 1000, gid: 1000 });
+            assert_eq!(send(&mut client, &request(1, &[], None), true).await.0, RC_OK);
+
+            let mut client = connect_as(parser, Peer::Unix { uid: 1001, gid: 1000 });
+            assert_eq!(send(&mut client, &request(1, &[], None), true).await, (RC_AUTH_FAILED, None));
+        }
+
+        #[tokio::test]
+        async fn shutdown_should_close_the_idle_sessions() {
+            let parser = test_parser("");
+            let mut client = logged_client(Arc::clone(&parser)).await;
+
+            parser.shutdown.trigger();
+            assert!(parser.shutdown.drain().await);
+            let mut buf = [0u8; 1];
+            assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn silent_client_should_be_closed_after_the_header_timeout() {
+            let mut client = connect(test_parser("[timeouts]\nheader_seconds = 5"));
+
+            let mut header = [0u8; 6];
+            client.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[5], RC_TIMEOUT);
+            assert_eq!(client.read(&mut header).await.unwrap(), 0);
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn idle_session_should_be_closed_after_the_idle_timeout() {
+            let mut client = logged_client(test_parser("[timeouts]\nheader_seconds = 1\nidle_seconds = 60")).await;
+
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            assert_eq!(send(&mut client, &request(1, &[], None), true).await.0, RC_OK);
+
+            let start = tokio::time::Instant::now();
+            let mut header = [0u8; 6];
+            client.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[5], RC_TIMEOUT);
+            assert!(start.elapsed() >= std::time::Duration::from_secs(60));
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn get_should_respect_the_session_rate() {
+            let mut client = logged_client(test_parser("[throttle]\nsession_bytes_per_second = 10")).await;
+
+            // the login takes the burst of the first second, the 31 bytes of the
+            // request and the 4 of the file need 3.5 seconds more
+            let start = tokio::time::Instant::now();
+            let (response_code, file) = send(&mut client, &request(0, &["dir_1/dir_5/file_4.txt"], None), true).await;
+            assert_eq!(response_code, RC_OK);
+            assert_eq!(file.unwrap().len(), 4);
+            assert!(start.elapsed() >= std::time::Duration::from_millis(3500));
+        }
+
+        #[tokio::test]
+        async fn requests_should_be_audited() {
+            let path = std::env::temp_dir().join(format!("nftp-audit-serve-{}.log", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let parser = test_parser(&format!("[audit]\npath = \"{}\"", path.display()));
+            let mut client = logged_client(Arc::clone(&parser)).await;
+            send(&mut client, &request(0, &["dir_1/dir_5/file_4.txt"], None), true).await;
+            // the GET is recorded before the next request is served, and written by the flush
+            send(&mut client, &request(1, &[], None), true).await;
+            parser.audit.flush();
+
+            let content = std::fs::read_to_string(&path).unwrap();
+            let records: Vec<&str> = content.lines().collect();
+            assert!(records.len() >= 2);
+            assert!(records[0].contains(r#""user":"alice","istruction":3,"paths":[],"response_code":1"#));
+            assert!(records[1].contains(r#""istruction":0,"paths":["dir_1/dir_5/file_4.txt"],"response_code":1,"bytes":"#));
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn session_should_serve_many_requests() {
+            let mut client = logged_client(test_parser("")).await;
+
+            let (response_code, list) = send(&mut client, &request(1, &[], None), true).await;
+            assert_eq!(response_code, RC_OK);
+            assert!(String::from_utf8(list.unwrap()).unwrap().starts_with("root{"));
+
+            let (response_code, file) = send(&mut client, &request(0, &["/file.txt"], None), true).await;
+            assert_eq!(response_code, RC_OK);
+            assert_eq!(file.unwrap(), std::fs::read("./tests/tree_serialization/root/file.txt").unwrap());
+        }
+
+        #[tokio::test]
+        async fn bad_request_should_return_error_and_keep_the_session() {
+            let mut client = logged_client(test_parser("")).await;
+
+            assert_eq!(send(&mut client, b"FTP!\x10\x01\x00", true).await, (RC_ERROR, None));
+            assert_eq!(send(&mut client, &request(1, &[], None), true).await.0, RC_OK);
+        }
+    }
 }
\ No newline at end of file
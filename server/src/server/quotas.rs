@@ -0,0 +1,413 @@
+use std::{collections::HashMap, io::{self, ErrorKind, Write}, path::{Path, PathBuf}, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll}};
+use tokio::io::AsyncWrite;
+use tracing::warn;
+use protocol::du::Usage;
+use super::{
+    config::QuotaConfig,
+    user_space::UserSpace,
+    session::Session,
+    du::disk_usage,
+    response::{RC_ERROR, RC_AUTH_FAILED, RC_QUOTA_EXCEEDED}
+};
+
+/// Byte and file quotas on the space of some users and on some directories.
+///
+/// The usage of a quota is measured walking its directories each time an upload asks
+/// for space, so it's always up to date, also with the changes made outside the server,
+/// at a cost proportional to the size of the tree. The uploads in progress are reserved
+/// until they end, so that concurrent uploads can't go over the quotas together: their
+/// temporary files aren't measured, they are already counted in the reservations.
+/// The uploads that end while a tree is being measured may be missing from it, so
+/// they are added to the measured usage.
+pub struct Quotas {
+    rules: Vec<Rule>,
+    ledger: Arc<Mutex<Ledger>>
+}
+
+/// The uploads of each rule, indexed as the rules.
+struct Ledger {
+    /// Bytes and files of the uploads in progress
+    reserved: Vec<Usage>,
+    /// Bytes and files of all the uploads ended, only ever growing
+    released: Vec<Usage>
+}
+
+/// The usage of some rules, measured walking their directories.
+struct Measurement {
+    rules: Vec<usize>,
+    usages: Vec<Usage>,
+    /// The usage of the path measured with the rules, if any
+    path: Usage,
+    /// `Ledger::released` of the rules when the measurement started
+    released: Vec<Usage>
+}
+impl Measurement {
+    /// Return the usage of each rule now, with the uploads in progress and those
+    /// ended since the measurement started, which it may have missed.
+    fn current(&self, ledger: &Ledger) -> Vec<Usage> {
+        self.rules.iter().zip(&self.usages).zip(&self.released).map(|((&i, usage), released)| {
+            let mut usage = *usage;
+            usage += ledger.reserved[i];
+            usage += Usage {
+                size: ledger.released[i].size - released.size,
+                files: ledger.released[i].files - released.files
+            };
+            usage
+        }).collect()
+    }
+}
+
+struct Rule {
+    scope: Scope,
+    max_bytes: Option<u64>,
+    max_files: Option<u64>
+}
+
+enum Scope {
+    /// The whole space of the user, all its roots
+    User { name: String, roots: Vec<PathBuf> },
+    /// A directory with everything under it, whoever writes there
+    Directory(PathBuf)
+}
+
+impl Rule {
+    fn applies_to(&self, user: &str, path: &Path) -> bool {
+        match &self.scope {
+            Scope::User { name, .. } => name == user,
+            Scope::Directory(directory) => path.starts_with(directory)
+        }
+    }
+
+    fn roots(&self) -> Vec<PathBuf> {
+        match &self.scope {
+            Scope::User { roots, .. } => roots.clone(),
+            Scope::Directory(directory) => vec![directory.clone()]
+        }
+    }
+}
+
+impl Quotas {
+    /// Build the quotas of the configuration: each one limits either a user or a
+    /// directory, relative to `main_path`.
+    pub fn new(configs: Vec<QuotaConfig>, main_path: &Path, user_spaces: &HashMap<String, UserSpace>) -> Result<Self, String> {
+        let mut rules = Vec::with_capacity(configs.len());
+        for config in configs {
+            if config.max_bytes.is_none() && config.max_files.is_none() {
+                return Err("a quota needs `max_bytes` and/or `max_files`".to_string());
+            }
+            let scope = match (config.user, config.path) {
+                (Some(name), None) => {
+                    let space = user_spaces.get(&name).ok_or(format!("the user {} of a quota does not exists", name))?;
+                    Scope::User { roots: space.roots().into_iter().cloned().collect(), name }
+                },
+                (None, Some(path)) => Scope::Directory(main_path.join(path)),
+                _ => return Err("a quota needs either `user` or `path`".to_string())
+            };
+            rules.push(Rule { scope, max_bytes: config.max_bytes, max_files: config.max_files });
+        }
+        let ledger = Ledger { reserved: vec![Usage::default(); rules.len()], released: vec![Usage::default(); rules.len()] };
+        Ok(Quotas { rules, ledger: Arc::new(Mutex::new(ledger)) })
+    }
+
+    /// Reserve the space for a file of `size` bytes written by the user of the session
+    /// to the real path, replacing the file already there, if any.
+    /// Return QUOTA EXCEEDED if it doesn't fit in a quota of the user or of a directory
+    /// containing the path. The space is released when the reservation is dropped.
+    pub async fn reserve(&self, session: &Session, path: &Path, size: u64) -> Result<Reservation, u8> {
+        let user = session.user.as_deref().ok_or(RC_AUTH_FAILED)?;
+        let rules = self.matching(|rule| rule.applies_to(user, path));
+        if rules.is_empty() {
+            return Ok(self.unlimited());
+        }
+        let measurement = self.measure(rules, Some(path.to_path_buf())).await?;
+        let replaced = measurement.path;
+        self.admit(measurement, Usage { size, files: u64::from(replaced.files == 0) }, Usage { size: replaced.size, files: 0 })
+    }
+
+    /// Reserve the space for moving `source` to `destination`, real paths: everything
+    /// under the source counts for the directory quotas of the destination that don't
+    /// already include the source. The quotas of the user don't change.
+    pub async fn reserve_move(&self, source: &Path, destination: &Path) -> Result<Reservation, u8> {
+        let rules = self.matching(|rule| matches!(&rule.scope,
+            Scope::Directory(directory) if destination.starts_with(directory) && !source.starts_with(directory)));
+        if rules.is_empty() {
+            return Ok(self.unlimited());
+        }
+        let measurement = self.measure(rules, Some(source.to_path_buf())).await?;
+        let moved = measurement.path;
+        self.admit(measurement, moved, Usage::default())
+    }
+
+    /// Return the bytes and the files the user of the session can still add, `None`
+    /// for a limit that none of its quotas has.
+    pub async fn remaining(&self, session: &Session) -> Result<(Option<u64>, Option<u64>), u8> {
+        let user = session.user.as_deref().ok_or(RC_AUTH_FAILED)?;
+        let rules = self.matching(|rule| matches!(&rule.scope, Scope::User { name, .. } if name == user));
+        if rules.is_empty() {
+            return Ok((None, None));
+        }
+        let measurement = self.measure(rules, None).await?;
+        let usages = measurement.current(&self.ledger.lock().unwrap());
+        let (mut bytes, mut files): (Option<u64>, Option<u64>) = (None, None);
+        for (&i, usage) in measurement.rules.iter().zip(&usages) {
+            let rule = &self.rules[i];
+            if let Some(max) = rule.max_bytes {
+                let remaining = max.saturating_sub(usage.size);
+                bytes = Some(bytes.map_or(remaining, |bytes| bytes.min(remaining)));
+            }
+            if let Some(max) = rule.max_files {
+                let remaining = max.saturating_sub(usage.files);
+                files = Some(files.map_or(remaining, |files| files.min(remaining)));
+            }
+        }
+        Ok((bytes, files))
+    }
+
+    fn matching(&self, applies: impl Fn(&Rule) -> bool) -> Vec<usize> {
+        (0..self.rules.len()).filter(|&i| applies(&self.rules[i])).collect()
+    }
+
+    fn unlimited(&self) -> Reservation {
+        Reservation { rules: Vec::new(), usage: Usage::default(), allowance: u64::MAX, ledger: Arc::clone(&self.ledger) }
+    }
+
+    /// Measure on a blocking thread the usage of each rule and of `path`, if any:
+    /// a path that doesn't exist uses nothing. The temporary files of the uploads
+    /// in progress are skipped by `disk_usage`.
+    async fn measure(&self, rules: Vec<usize>, path: Option<PathBuf>) -> Result<Measurement, u8> {
+        let released = {
+            let ledger = self.ledger.lock().unwrap();
+            rules.iter().map(|&i| ledger.released[i]).collect()
+        };
+        let roots: Vec<Vec<PathBuf>> = rules.iter().map(|&i| self.rules[i].roots()).collect();
+        let usage_of = |path: &Path| disk_usage(path, "/", false, &|_| true).map_or(Usage::default(), |usage| usage.total);
+        let (usages, path) = tokio::task::spawn_blocking(move || {
+            let usages = roots.iter().map(|roots| {
+                let mut usage = Usage::default();
+                for root in roots {
+                    usage += usage_of(root);
+                }
+                usage
+            }).collect();
+            (usages, path.as_deref().map(usage_of).unwrap_or_default())
+        }).await.map_err(|e| {
+            warn!(error = %e, "Blocking task failed");
+            RC_ERROR
+        })?;
+        Ok(Measurement { rules, usages, path, released })
+    }
+
+    /// Reserve `added` for the measured rules, that will lose `removed` (a replaced
+    /// file), if it fits in all of them.
+    fn admit(&self, measurement: Measurement, added: Usage, removed: Usage) -> Result<Reservation, u8> {
+        let mut ledger = self.ledger.lock().unwrap();
+        let usages = measurement.current(&ledger);
+        let mut allowance = u64::MAX;
+        for (&i, usage) in measurement.rules.iter().zip(&usages) {
+            let rule = &self.rules[i];
+            let size = usage.size.saturating_sub(removed.size);
+            let files = usage.files.saturating_sub(removed.files);
+            if rule.max_bytes.is_some_and(|max| size.saturating_add(added.size) > max)
+                || rule.max_files.is_some_and(|max| files.saturating_add(added.files) > max) {
+                warn!(bytes = added.size, files = added.files, max_bytes = rule.max_bytes, max_files = rule.max_files, "Quota exceeded");
+                return Err(RC_QUOTA_EXCEEDED);
+            }
+            if let Some(max) = rule.max_bytes {
+                allowance = allowance.min(max - size);
+            }
+        }
+        for &i in &measurement.rules {
+            ledger.reserved[i] += added;
+        }
+        Ok(Reservation { rules: measurement.rules, usage: added, allowance, ledger: Arc::clone(&self.ledger) })
+    }
+}
+
+
+/// Space reserved by `Quotas::reserve` or `Quotas::reserve_move`, released on drop.
+pub struct Reservation {
+    rules: Vec<usize>,
+    usage: Usage,
+    /// Bytes that can be written before going over a quota
+    allowance: u64,
+    ledger: Arc<Mutex<Ledger>>
+}
+impl Reservation {
+    /// Return the bytes that the file can have without going over a quota.
+    pub fn allowance(&self) -> u64 {
+        self.allowance
+    }
+}
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut ledger = self.ledger.lock().unwrap();
+        for &i in &self.rules {
+            ledger.reserved[i].size = ledger.reserved[i].size.saturating_sub(self.usage.size);
+            ledger.reserved[i].files = ledger.reserved[i].files.saturating_sub(self.usage.files);
+            ledger.released[i] += self.usage;
+        }
+    }
+}
+
+
+/// A writer that fails with `ErrorKind::QuotaExceeded`, instead of writing, as soon
+/// as a write would go over the bytes allowed by a reservation.
+pub struct QuotaWriter<W> {
+    inner: W,
+    remaining: u64
+}
+impl<W> QuotaWriter<W> {
+    /// Limit `inner` to `allowance` bytes, see `Reservation::allowance`.
+    pub fn new(inner: W, allowance: u64) -> Self {
+        QuotaWriter { inner, remaining: allowance }
+    }
+
+    fn check(&self, len: usize) -> io::Result<()> {
+        if len as u64 > self.remaining {
+            return Err(io::Error::new(ErrorKind::QuotaExceeded, "the file goes over a quota"));
+        }
+        Ok(())
+    }
+}
+impl<W: Write> Write for QuotaWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check(buf.len())?;
+        let n = self.inner.write(buf)?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+impl<W: AsyncWrite + Unpin> AsyncWrite for QuotaWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.check(buf.len())?;
+        let written = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = written {
+            self.remaining -= n as u64;
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::server::{session::Peer, temporary_path};
+
+    fn quota(user: Option<&str>, path: Option<&str>, max_bytes: Option<u64>, max_files: Option<u64>) -> QuotaConfig {
+        QuotaConfig { user: user.map(str::to_string), path: path.map(PathBuf::from), max_bytes, max_files }
+    }
+
+    fn quotas(configs: Vec<QuotaConfig>) -> Quotas {
+        let root = std::env::current_dir().unwrap().join("tests/tree_serialization/root");
+        let user_spaces = HashMap::from([("alice".to_string(), UserSpace::Home(root.clone()))]);
+        Quotas::new(configs, &root, &user_spaces).unwrap()
+    }
+
+    fn alice() -> Session {
+        let mut session = Session::new(Peer::Tcp("127.0.0.1:40000".parse().unwrap()));
+        session.user = Some("alice".to_string());
+        session
+    }
+
+    #[tokio::test]
+    async fn reserve_should_count_the_usage_and_the_uploads_in_progress() {
+        // alice uses 17 bytes in 4 files, 13 bytes in 3 files under dir_1
+        let quotas = quotas(vec![quota(Some("alice"), None, Some(30), None), quota(None, Some("dir_1"), None, Some(4))]);
+        let root = std::env::current_dir().unwrap().join("tests/tree_serialization/root");
+        let session = alice();
+
+        let first = quotas.reserve(&session, &root.join("dir_1/new.txt"), 10).await.unwrap();
+        assert_eq!(first.allowance(), 13);
+        assert_eq!(quotas.reserve(&session, &root.join("dir_2/new.txt"), 4).await.err(), Some(RC_QUOTA_EXCEEDED));
+        assert_eq!(quotas.reserve(&session, &root.join("dir_1/other.txt"), 1).await.err(), Some(RC_QUOTA_EXCEEDED));
+        // replacing a file frees its space and doesn't add a file
+        assert!(quotas.reserve(&session, &root.join("dir_1/file_1.txt"), 8).await.is_ok());
+        assert_eq!(quotas.remaining(&session).await, Ok((Some(3), None)));
+
+        drop(first);
+        assert_eq!(quotas.remaining(&session).await, Ok((Some(13), None)));
+        assert!(quotas.reserve(&session, &root.join("dir_1/new.txt"), 13).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reserve_should_not_count_the_partial_files_of_the_uploads_in_progress() {
+        let root = std::env::temp_dir().join(format!("nftp-quotas-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("file.txt"), b"hello").unwrap();
+        let user_spaces = HashMap::from([("alice".to_string(), UserSpace::Home(root.clone()))]);
+        let quotas = Quotas::new(vec![quota(Some("alice"), None, Some(25), None)], &root, &user_spaces).unwrap();
+        let session = alice();
+
+        let first = quotas.reserve(&session, &root.join("a.bin"), 10).await.unwrap();
+        // the first upload has written half of its file
+        std::fs::write(temporary_path(&root.join("a.bin"), "nftp-part"), [0u8; 5]).unwrap();
+        let second = quotas.reserve(&session, &root.join("b.bin"), 10).await.unwrap();
+        assert_eq!(second.allowance(), 10);
+        assert_eq!(quotas.remaining(&session).await, Ok((Some(0), None)));
+
+        drop((first, second));
+        assert_eq!(quotas.remaining(&session).await, Ok((Some(20), None)));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn uploads_ended_while_measuring_should_be_counted() {
+        let root = std::env::temp_dir().join(format!("nftp-quotas-race-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("file.txt"), b"hello").unwrap();
+        let user_spaces = HashMap::from([("alice".to_string(), UserSpace::Home(root.clone()))]);
+        let quotas = Quotas::new(vec![quota(Some("alice"), None, Some(25), None)], &root, &user_spaces).unwrap();
+        let first = quotas.reserve(&alice(), &root.join("a.bin"), 10).await.unwrap();
+
+        let measurement = quotas.measure(vec![0], None).await.unwrap();
+        // the first upload ends after the tree has been measured
+        std::fs::write(root.join("a.bin"), [0u8; 10]).unwrap();
+        drop(first);
+        assert_eq!(quotas.admit(measurement, Usage { size: 15, files: 1 }, Usage::default()).err(), Some(RC_QUOTA_EXCEEDED));
+        assert_eq!(quotas.reserve(&alice(), &root.join("b.bin"), 10).await.unwrap().allowance(), 10);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reserve_move_should_count_only_the_directories_entered() {
+        let quotas = quotas(vec![quota(None, Some("dir_1"), Some(15), None)]);
+        let root = std::env::current_dir().unwrap().join("tests/tree_serialization/root");
+
+        assert_eq!(quotas.reserve_move(&root.join("file.txt"), &root.join("dir_1/file.txt")).await.err(), Some(RC_QUOTA_EXCEEDED));
+        assert!(quotas.reserve_move(&root.join("dir_1/file_1.txt"), &root.join("dir_1/dir_5/file_1.txt")).await.is_ok());
+        assert!(quotas.reserve_move(&root.join("dir_1/file_1.txt"), &root.join("file_1.txt")).await.is_ok());
+    }
+
+    #[test]
+    fn quota_writer_should_fail_over_the_allowance() {
+        let mut writer = QuotaWriter::new(Vec::new(), 5);
+
+        writer.write_all(b"hel").unwrap();
+        assert_eq!(writer.write_all(b"lo!").unwrap_err().kind(), ErrorKind::QuotaExceeded);
+        writer.write_all(b"lo").unwrap();
+        assert_eq!(writer.inner, b"hello");
+    }
+
+    #[test]
+    fn new_should_refuse_incomplete_quotas() {
+        let user_spaces = HashMap::new();
+
+        assert!(Quotas::new(vec![quota(None, Some("docs"), None, None)], Path::new("/srv"), &user_spaces).is_err());
+        assert!(Quotas::new(vec![quota(Some("bob"), None, Some(1), None)], Path::new("/srv"), &user_spaces).is_err());
+        assert!(Quotas::new(vec![quota(Some("bob"), Some("docs"), Some(1), None)], Path::new("/srv"), &user_spaces).is_err());
+        assert!(Quotas::new(vec![quota(None, Some("docs"), Some(1), None)], Path::new("/srv"), &user_spaces).is_ok());
+    }
+}
@@ -1,191 +1,167 @@
-use std::time::Duration;
-use tokio::{net::TcpStream, io::AsyncWriteExt, time::{Instant, sleep_until}};
-
-/// Response code OK
-pub const RC_OK: u8 = 1;
-
-/// Response code GENERIC ERROR
-pub const RC_ERROR: u8 = 100;
-
-/// Represents a response header for the nFTP protocol.
-pub struct ResponseHeader {
-    header_bytes: Vec<u8>
+use std::{io::ErrorKind, time::Duration};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use super::{config::ErrorResponseConfig, shutdown::Shutdown};
+pub use protocol::response::*;
+
+/// How `send_error_response` retries a failed write.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub retries: u32,
+    /// Wait before the first retry.
+    pub backoff: Duration,
+    /// Factor by which the wait grows at each retry.
+    pub backoff_factor: u32,
+    /// Time limit of each attempt.
+    pub write_timeout: Duration
 }
-impl ResponseHeader {
-    pub fn new(version_major: u8, version_minor: u8, response_code: u8, payload_dim: Option<u64>) -> Self {
-        let mut output_bytes = Vec::with_capacity(
-            if payload_dim.is_some() {14} else {6}
-        );
-
-        output_bytes.extend_from_slice(b"nFTP");
-        output_bytes.push((version_major << 4) | version_minor);
-        output_bytes.push(response_code);
-        if payload_dim.is_some() {
-            output_bytes.extend_from_slice(&payload_dim.unwrap().to_be_bytes());
-        }
-
-        ResponseHeader { header_bytes: output_bytes }
-    }
-
-    #[inline]
-    pub fn set_new_version(&mut self, version_major: u8, version_minor: u8) {
-        self.header_bytes[4] = (version_major << 4) | version_minor;
-    }
-
-    #[inline]
-    pub fn set_new_response_code(&mut self, response_code: u8) {
-        self.header_bytes[5] = response_code;
-    }
-
-    #[inline]
-    pub fn set_new_payload_dim(&mut self, payload_dim: Option<u64>) {
-        if self.header_bytes.len() > 6 {
-            match payload_dim {
-                Some(payload_dim) => {
-                    let bytes = payload_dim.to_be_bytes();
-                    for i in 6..14 {
-                        self.header_bytes[i] = bytes[i-6];
-                    }
-                },
-                None => for _ in 0..8 { 
-                    self.header_bytes.pop(); 
-                }
-            };
-        } else if payload_dim.is_some() {
-            let bytes = payload_dim.unwrap().to_be_bytes();
-            for i in 0..8 {
-                self.header_bytes.push(bytes[i]);
-            }
+impl RetryPolicy {
+    pub fn new(config: &ErrorResponseConfig, write_timeout: Duration) -> Self {
+        RetryPolicy {
+            retries: config.retries,
+            backoff: Duration::from_millis(config.backoff_ms),
+            backoff_factor: config.backoff_factor,
+            write_timeout
         }
     }
-
-    #[inline]
-    pub fn get_header(&self) -> &Vec<u8> {
-        &self.header_bytes
-    }
 }
 
 
 /// Try sending an error response. 
 /// In case of failure, try again using a procedure defined as follows:
 /// 
-/// 1. Wait 4 seconds and try again.
+/// 1. Wait the backoff of the policy and try again.
 /// 
 ///    - In case of failure:
-/// 2. Wait 4 times the previous time and try again.
+/// 2. Wait `backoff_factor` times the previous time and try again.
 /// 
 ///    - In case of failure, go back to step 2.
 /// 
-/// 3. After 5 attempts (6 in total), you fail.
+/// 3. After `retries` retries, you fail.
 /// 
-/// The growth of the waiting time is exponential, for 5 attempts 
-/// it will be (expressed in seconds):
-/// * 4 - 16 - 64 - 256 - 1024
+/// With the default policy the waits are (expressed in milliseconds):
+/// * 500 - 2000
+/// 
+/// It gives up at once if the connection is broken (e.g. broken pipe or reset),
+/// if an attempt times out or if the server is shutting down.
+/// Return true if the response was sent.
 /// 
 /// # Arguments
 /// * `socket` - the socket to write to.
 /// * `error_response_code` - the error code to write.
+/// * `policy` - how to retry.
+/// * `shutdown` - the shutdown of the server, that stops the retries.
 /// 
-#[inline]
-pub async fn send_error_response(socket: &mut TcpStream, error_response_code: u8) {
+pub async fn send_error_response<S: AsyncWrite + Unpin + ?Sized>(
+    socket: &mut S, 
+    error_response_code: u8, 
+    policy: &RetryPolicy, 
+    shutdown: &Shutdown
+) -> bool
+{
     let response_header = ResponseHeader::new(1, 0, error_response_code, None);
-    let max_number_of_attempts: u8 = 5;
-    let mut milliseconds: u64 = 1000; // 1 sec
+    let mut backoff = policy.backoff;
 
-    if socket.write_all(&response_header.get_header()).await.is_ok() {
-        return;
-    }
-    for _ in 0..max_number_of_attempts {
-        milliseconds *= 4;
-        sleep_until(Instant::now() + Duration::from_millis(milliseconds)).await;
-        if socket.write_all(&response_header.get_header()).await.is_ok() {
-            return;
+    for attempt in 0..=policy.retries {
+        if attempt > 0 {
+            tokio::select! {
+                _ = shutdown.triggered() => return false,
+                _ = tokio::time::sleep(backoff) => ()
+            }
+            backoff = backoff.saturating_mul(policy.backoff_factor);
+        }
+        match tokio::time::timeout(policy.write_timeout, socket.write_all(response_header.get_header())).await {
+            Ok(Ok(())) => return true,
+            Ok(Err(e)) if is_fatal(e.kind()) => {
+                tracing::warn!(error = %e, "Error response not sent, the connection is broken");
+                return false;
+            },
+            Ok(Err(e)) => tracing::warn!(error = %e, attempt, "Error response not sent"),
+            Err(_) => {
+                tracing::warn!("Error response not sent, the client is not reading");
+                return false;
+            }
         }
     }
+    false
 }
 
 
-/// Reassembles a vector of 8 bytes into a 64-bit unsigned integer.
-/// Utility function.
-pub fn reassemble_u64_from_bytes(bytes: &[u8]) -> u64 {
-    let mut res: u64 = 0;
-    let mut b: u8 = 64;
-    for i in 0..8 {
-        b -= 8;
-        res |= (bytes[i] as u64) << b;
-    }
-    res
+/// Return true for the errors after which the connection can't be used anymore.
+fn is_fatal(kind: ErrorKind) -> bool {
+    matches!(kind,
+        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted |
+        ErrorKind::NotConnected | ErrorKind::UnexpectedEof | ErrorKind::WriteZero |
+        ErrorKind::InvalidData
+    )
 }
 
 
 #[cfg(test)]
 pub mod test {
+    use std::{io, pin::Pin, task::{Context, Poll}};
     use super::*;
 
-    #[test]
-    fn reassemble_u64_from_bytes_should_return_the_correct_u64_number() {
-        let number = 18_446_744_073_709_551_615u64;
-        let bytes = number.to_be_bytes();
-        assert_eq!(number, reassemble_u64_from_bytes(&bytes));
+    /// A socket whose writes always fail with the given error, counting the attempts.
+    struct FailingSocket {
+        kind: ErrorKind,
+        attempts: u32
+    }
+    impl AsyncWrite for FailingSocket {
+        fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+            self.attempts += 1;
+            Poll::Ready(Err(self.kind.into()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
     }
 
-    #[test]
-    fn response_header_should_return_the_correct_array_of_bytes() {
-        let res = ResponseHeader::new(1, 2, 200, Some(18_446_744_073_709_551_615u64));
-        let output_bytes = res.get_header();
-
-        assert_eq!(output_bytes.len(), 14);
-        assert_eq!(&output_bytes[0..4], b"nFTP");
-        assert_eq!((output_bytes[4] & 0b1111_0000) >> 4, 1u8);
-        assert_eq!(output_bytes[4] & 0b0000_1111, 2u8);
-        assert_eq!(output_bytes[5], 200u8);
-        let payload_dim = reassemble_u64_from_bytes(&output_bytes[6..]);
-        assert_eq!(payload_dim, 18_446_744_073_709_551_615u64);
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(&ErrorResponseConfig::default(), Duration::from_secs(30))
     }
 
-    #[test]
-    fn response_header_with_none_payload_dim_should_return_the_correct_array_of_bytes() {
-        let res = ResponseHeader::new(1, 2, 200, None);
-        let output_bytes = res.get_header();
+    #[tokio::test(start_paused = true)]
+    async fn send_error_response_should_retry_the_transient_errors() {
+        let mut socket = FailingSocket { kind: ErrorKind::Interrupted, attempts: 0 };
+        let shutdown = Shutdown::new(Duration::from_secs(1));
 
-        assert_eq!(output_bytes.len(), 6);
-        assert_eq!(&output_bytes[0..4], b"nFTP");
-        assert_eq!((output_bytes[4] & 0b1111_0000) >> 4, 1u8);
-        assert_eq!(output_bytes[4] & 0b0000_1111, 2u8);
-        assert_eq!(output_bytes[5], 200u8);
+        let start = tokio::time::Instant::now();
+        assert!(!send_error_response(&mut socket, RC_ERROR, &policy(), &shutdown).await);
+        assert_eq!(socket.attempts, 3);
+        assert_eq!(start.elapsed(), Duration::from_millis(2500));
     }
 
-    #[test]
-    fn response_header_after_setting_new_payload_dim_should_contains_the_new_payload_dim() {
-        let mut h = ResponseHeader::new(1, 0, 200, Some(18_446_744_073_709_551_615u64));
-        
-        let new_payload_dimension = 17_000_744_111_709_555_001u64;
-        h.set_new_payload_dim(Some(new_payload_dimension));
-        assert_eq!(reassemble_u64_from_bytes(&h.get_header()[6..]), new_payload_dimension);
-    }
+    #[tokio::test(start_paused = true)]
+    async fn send_error_response_should_give_up_at_once_on_fatal_errors() {
+        let mut socket = FailingSocket { kind: ErrorKind::BrokenPipe, attempts: 0 };
+        let shutdown = Shutdown::new(Duration::from_secs(1));
 
-    #[test]
-    fn response_header_after_delete_payload_dim_should_not_contains_payload_dim() {
-        let mut h = ResponseHeader::new(1, 0, 200, Some(18_446_744_073_709_551_615u64));
-        
-        h.set_new_payload_dim(None);
-        assert_eq!(h.get_header().len(), 6);
+        assert!(!send_error_response(&mut socket, RC_ERROR, &policy(), &shutdown).await);
+        assert_eq!(socket.attempts, 1);
     }
 
-    #[test]
-    fn response_header_without_payload_dim_should_have_len_6_after_setting_none() {
-        let mut h = ResponseHeader::new(1, 0, 200, None);
-        
-        h.set_new_payload_dim(None);
-        assert_eq!(h.get_header().len(), 6);
+    #[tokio::test(start_paused = true)]
+    async fn send_error_response_should_stop_retrying_on_shutdown() {
+        let mut socket = FailingSocket { kind: ErrorKind::Interrupted, attempts: 0 };
+        let shutdown = Shutdown::new(Duration::from_secs(1));
+        shutdown.trigger();
+
+        assert!(!send_error_response(&mut socket, RC_ERROR, &policy(), &shutdown).await);
+        assert_eq!(socket.attempts, 1);
     }
 
-    #[test]
-    fn response_header_without_payload() {
-        let mut h = ResponseHeader::new(1, 0, 200, None);
-        
-        let new_payload_dimension = 17_000_744_111_709_555_001u64;
-        h.set_new_payload_dim(Some(new_payload_dimension));
-        assert_eq!(reassemble_u64_from_bytes(&h.get_header()[6..]), new_payload_dimension);
+    #[tokio::test]
+    async fn send_error_response_should_write_the_header() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let shutdown = Shutdown::new(Duration::from_secs(1));
+
+        assert!(send_error_response(&mut server, RC_PERMISSION_DENIED, &policy(), &shutdown).await);
+        let mut header = [0u8; 6];
+        tokio::io::AsyncReadExt::read_exact(&mut client, &mut header).await.unwrap();
+        assert_eq!(&header, ResponseHeader::new(1, 0, RC_PERMISSION_DENIED, None).get_header().as_slice());
     }
 }
\ No newline at end of file
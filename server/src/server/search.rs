@@ -0,0 +1,128 @@
+use std::{path::{Path, PathBuf}, time::UNIX_EPOCH};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use tokio::sync::mpsc;
+use tracing::debug;
+use protocol::{search::{Match, Pattern, Query}, stat::Stat};
+use super::is_temporary;
+
+/// Max dimension of a compiled regular expression
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+
+/// The compiled pattern of a query.
+pub enum Matcher {
+    Glob {
+        glob: GlobMatcher,
+        /// The glob contains a `/`: it's matched against the relative path, not the name
+        whole_path: bool
+    },
+    Regex(Regex)
+}
+impl Matcher {
+    /// Compile the pattern, returning the error for an invalid one.
+    pub fn new(pattern: &Pattern) -> Result<Self, String> {
+        match pattern {
+            Pattern::Glob(glob) => Ok(Matcher::Glob {
+                glob: GlobBuilder::new(glob).literal_separator(true).build().map_err(|e| e.to_string())?.compile_matcher(),
+                whole_path: glob.contains('/')
+            }),
+            Pattern::Regex(regex) => RegexBuilder::new(regex)
+                .size_limit(REGEX_SIZE_LIMIT)
+                .build()
+                .map(Matcher::Regex)
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    /// Return true if the entry matches: `relative` is its path relative to the
+    /// starting directory, `user_path` its path in the user space.
+    pub fn is_match(&self, relative: &str, user_path: &str) -> bool {
+        match self {
+            Matcher::Glob { glob, whole_path: true } => glob.is_match(relative),
+            Matcher::Glob { glob, whole_path: false } => glob.is_match(relative.rsplit('/').next().unwrap_or(relative)),
+            Matcher::Regex(regex) => regex.is_match(user_path)
+        }
+    }
+}
+
+
+/// Walk the tree under `start`, whose path in the user space is `user_start`, and send
+/// the entries matching the query, without following the symbolic links. Meant to run
+/// on a blocking thread: it stops early when the receiver is dropped.
+/// The entries that can't be read and the temporary files of the uploads are skipped.
+pub fn search(start: &Path, user_start: &str, query: &Query, matcher: &Matcher, found: &mpsc::Sender<Match>) {
+    let mut directories: Vec<(PathBuf, String)> = vec![(start.to_path_buf(), String::new())];
+    while let Some((directory, prefix)) = directories.pop() {
+        let entries = match std::fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!(error = %e, directory = %directory.display(), "Directory not readable for SEARCH request");
+                continue;
+            }
+        };
+        for entry in entries.flatten().filter(|entry| !is_temporary(&entry.file_name())) {
+            let Ok(metadata) = entry.metadata() else { continue };
+            let relative = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if metadata.is_dir() {
+                directories.push((entry.path(), format!("{}/", relative)));
+            }
+
+            let user_path = format!("{}/{}", user_start.trim_end_matches('/'), relative);
+            let modified = metadata.modified().ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_secs());
+            let stat = Stat { is_dir: metadata.is_dir(), size: metadata.len(), modified };
+            if matcher.is_match(&relative, &user_path) && query.filters(&stat)
+                && found.blocking_send(Match { path: user_path, stat }).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn found(query: Query) -> Vec<String> {
+        let matcher = Matcher::new(&query.pattern).unwrap();
+        let (sender, mut receiver) = mpsc::channel(100);
+        search(Path::new("./tests/tree_serialization/root/dir_1"), "/dir_1", &query, &matcher, &sender);
+        drop(sender);
+        let mut paths = Vec::new();
+        while let Ok(found) = receiver.try_recv() {
+            paths.push(found.path);
+        }
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn globs_should_match_the_name_or_the_relative_path() {
+        let glob = |glob: &str| Matcher::new(&Pattern::Glob(glob.to_string())).unwrap();
+
+        assert!(glob("*.txt").is_match("a/b/c.txt", "/x/a/b/c.txt"));
+        assert!(!glob("a/*.txt").is_match("a/b/c.txt", "/x/a/b/c.txt"));
+        assert!(glob("a/**/*.txt").is_match("a/b/c.txt", "/x/a/b/c.txt"));
+        assert!(Matcher::new(&Pattern::Glob("[".to_string())).is_err());
+    }
+
+    #[test]
+    fn regex_should_be_searched_in_the_user_path() {
+        let regex = Matcher::new(&Pattern::Regex(r"^/x/a/.*\.txt$".to_string())).unwrap();
+
+        assert!(regex.is_match("b/c.txt", "/x/a/b/c.txt"));
+        assert!(!regex.is_match("c.txt", "/y/c.txt"));
+        assert!(Matcher::new(&Pattern::Regex("(".to_string())).is_err());
+    }
+
+    #[test]
+    fn search_should_find_the_entries_under_the_start() {
+        assert_eq!(found(Query::new(Pattern::Glob("file_4.txt".to_string()))), vec!["/dir_1/dir_5/file_4.txt"]);
+        assert_eq!(found(Query::new(Pattern::Glob("dir_5".to_string()))), vec!["/dir_1/dir_5"]);
+        let only_files = Query { min_size: Some(0), max_size: Some(u64::MAX - 1), ..Query::new(Pattern::Regex("dir_5".to_string())) };
+        assert_eq!(found(only_files), vec!["/dir_1/dir_5/file_4.txt"]);
+    }
+}
@@ -0,0 +1,40 @@
+use std::{fmt, net::SocketAddr};
+use super::throttle::TokenBucket;
+
+/// The client at the other end of a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Peer {
+    /// A client connected through TCP, with its address.
+    Tcp(SocketAddr),
+    /// A local client connected through a Unix domain socket, with its credentials.
+    Unix { uid: u32, gid: u32 }
+}
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(address) => write!(f, "{}", address),
+            Peer::Unix { uid, gid } => write!(f, "unix(uid={}, gid={})", uid, gid)
+        }
+    }
+}
+
+
+/// State of a client connection, kept across its requests.
+pub struct Session {
+    /// The client.
+    pub peer: Peer,
+    /// Name of the authenticated user, `None` until a successful LOGIN.
+    pub user: Option<String>,
+    /// Bandwidth of the session.
+    pub bucket: TokenBucket,
+    /// Bytes received and sent for the current request.
+    pub transferred: u64,
+    /// Close the connection after the response to the current request, e.g. when
+    /// the rest of its payload can't be consumed.
+    pub closing: bool
+}
+impl Session {
+    pub fn new(peer: Peer) -> Self {
+        Session { peer, user: None, bucket: TokenBucket::default(), transferred: 0, closing: false }
+    }
+}
@@ -0,0 +1,80 @@
+use std::{future::Future, time::Duration};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Coordinates the graceful shutdown of the server: once triggered, the listeners
+/// stop accepting, idle sessions are closed and the running requests are given
+/// a grace period to finish.
+pub struct Shutdown {
+    token: CancellationToken,
+    sessions: TaskTracker,
+    grace_period: Duration
+}
+impl Shutdown {
+    pub fn new(grace_period: Duration) -> Self {
+        Shutdown { token: CancellationToken::new(), sessions: TaskTracker::new(), grace_period }
+    }
+
+    /// Start the shutdown.
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    /// Wait until the shutdown is triggered.
+    pub async fn triggered(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Run a session in its own task, keeping track of it for the shutdown.
+    pub fn spawn_session<F>(&self, session: F)
+    where F: Future<Output = ()> + Send + 'static {
+        self.sessions.spawn(session);
+    }
+
+    /// Wait for the running sessions to finish, at most for the grace period.
+    /// Return false if some sessions are still running after it.
+    pub async fn drain(&self) -> bool {
+        self.sessions.close();
+        tokio::time::timeout(self.grace_period, self.sessions.wait()).await.is_ok()
+    }
+}
+
+
+/// Wait for SIGTERM or SIGINT.
+pub async fn wait_for_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        _ = sigint.recv() => Ok("SIGINT")
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_should_wait_for_the_running_sessions() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        shutdown.spawn_session(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sender.send(()).unwrap();
+        });
+
+        shutdown.trigger();
+        assert!(shutdown.drain().await);
+        assert!(receiver.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn drain_should_give_up_after_the_grace_period() {
+        let shutdown = Shutdown::new(Duration::from_millis(50));
+        shutdown.spawn_session(std::future::pending());
+
+        shutdown.trigger();
+        assert!(!shutdown.drain().await);
+    }
+}
@@ -0,0 +1,95 @@
+//! Utilities to test the server on in-memory streams, without binding real ports.
+
+use std::{sync::Arc, path::{Path, PathBuf}};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use protocol::request::Request;
+use super::{parser::Parser, config::Config, session::Peer, response::RC_OK};
+
+/// Token of the user `alice` in the test config.
+pub const TOKEN: &[u8] = b"alice-token";
+
+/// Build a parser serving `./tests/tree_serialization/root` to the user `alice`,
+/// with the given extra TOML appended to the config.
+pub fn test_parser(extra_config: &str) -> Arc<Parser> {
+    parser_on(&std::env::current_dir().unwrap().join("tests/tree_serialization"), extra_config)
+}
+
+/// Build a parser serving to the user `alice` a new temporary directory, that
+/// the tests can modify, with a file `file.txt` and an empty directory `dir`.
+/// Return the parser and the home of `alice`.
+pub fn writable_test_parser(name: &str, extra_config: &str) -> (Arc<Parser>, PathBuf) {
+    let main_path = std::env::temp_dir().join(format!("nftp-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&main_path);
+    let home = main_path.join("root");
+    std::fs::create_dir_all(home.join("dir")).unwrap();
+    std::fs::write(home.join("file.txt"), b"hello").unwrap();
+    (parser_on(&main_path, extra_config), home)
+}
+
+fn parser_on(main_path: &Path, extra_config: &str) -> Arc<Parser> {
+    let config = Config::parse(&format!(r#"
+        main_path = "{}"
+
+        [[users]]
+        name = "alice"
+        tokens = ["{}"]
+        home = "root"
+
+        {}
+    "#, main_path.display(), std::str::from_utf8(TOKEN).unwrap(), extra_config)).unwrap();
+    Arc::new(Parser::new(config))
+}
+
+/// Serve a TCP client on an in-memory stream, returning the client side of the stream.
+pub fn connect(parser: Arc<Parser>) -> DuplexStream {
+    connect_as(parser, Peer::Tcp("127.0.0.1:40000".parse().unwrap()))
+}
+
+/// Serve a client on an in-memory stream, returning the client side of the stream.
+pub fn connect_as(parser: Arc<Parser>, peer: Peer) -> DuplexStream {
+    let (client, mut server) = tokio::io::duplex(64 * 1024);
+    let session_parser = Arc::clone(&parser);
+    parser.shutdown.spawn_session(async move {
+        session_parser.serve(&mut server, peer).await;
+    });
+    client
+}
+
+/// Build a version 1.0 request.
+pub fn request(istruction: u8, paths: &[&str], payload: Option<&[u8]>) -> Vec<u8> {
+    let mut request = Request::new(istruction);
+    for path in paths {
+        request = request.path(*path);
+    }
+    if let Some(payload) = payload {
+        request = request.payload(payload);
+    }
+    request.encode().unwrap()
+}
+
+/// Send a request and return the response code and, if any, the payload of the response.
+/// The payload is expected only with the OK response code and `with_payload`.
+pub async fn send<S: AsyncRead + AsyncWrite + Unpin>(client: &mut S, request: &[u8], with_payload: bool) -> (u8, Option<Vec<u8>>) {
+    client.write_all(request).await.unwrap();
+
+    let mut header = [0u8; 6];
+    client.read_exact(&mut header).await.unwrap();
+    assert_eq!(&header[0..4], b"nFTP");
+    assert_eq!(header[4], 0b0001_0000u8);
+    if header[5] != RC_OK || !with_payload {
+        return (header[5], None);
+    }
+
+    let mut payload_dim = [0u8; 8];
+    client.read_exact(&mut payload_dim).await.unwrap();
+    let mut payload = vec![0u8; u64::from_be_bytes(payload_dim) as usize];
+    client.read_exact(&mut payload).await.unwrap();
+    (header[5], Some(payload))
+}
+
+/// Connect and log in as `alice` with its token.
+pub async fn logged_client(parser: Arc<Parser>) -> DuplexStream {
+    let mut client = connect(parser);
+    assert_eq!(send(&mut client, &request(3, &[], Some(TOKEN)), false).await, (RC_OK, None));
+    client
+}
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, RwLock}, time::Duration};
+use tokio::time::Instant;
+use super::{config::ThrottleConfig, session::Session};
+
+/// A token bucket: it fills at `rate` tokens (bytes) per second, up to one second
+/// of burst. Taking more tokens than available leaves the bucket in debt, and the
+/// taker waits until the debt is paid back.
+#[derive(Default)]
+pub struct TokenBucket {
+    state: Mutex<BucketState>
+}
+
+#[derive(Default)]
+struct BucketState {
+    tokens: f64,
+    /// Last refill, `None` for a new bucket (that starts full).
+    last_refill: Option<Instant>
+}
+
+impl TokenBucket {
+    /// Take `n` tokens at the given rate, waiting if the bucket hasn't enough.
+    /// A rate of 0 means unlimited.
+    pub async fn take(&self, rate: u64, n: usize) {
+        if rate == 0 {
+            return;
+        }
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let rate = rate as f64;
+            state.tokens = match state.last_refill {
+                Some(last_refill) => (state.tokens + (now - last_refill).as_secs_f64() * rate).min(rate),
+                None => rate
+            };
+            state.last_refill = Some(now);
+            state.tokens -= n as f64;
+            if state.tokens < 0.0 { Duration::from_secs_f64(-state.tokens / rate) } else { Duration::ZERO }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+
+/// Bandwidth limits of the server: each transferred chunk takes its bytes from the
+/// bucket of the session, of the user and of the whole server.
+/// The rates can be changed while the server runs.
+pub struct Throttle {
+    rates: RwLock<ThrottleConfig>,
+    global: TokenBucket,
+    users: Mutex<HashMap<String, Arc<TokenBucket>>>
+}
+impl Throttle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Throttle { rates: RwLock::new(config), global: TokenBucket::default(), users: Mutex::new(HashMap::new()) }
+    }
+
+    /// Replace the rates, for the next chunks of every session.
+    pub fn set_rates(&self, config: ThrottleConfig) {
+        *self.rates.write().unwrap() = config;
+    }
+
+    /// Wait until the session can transfer `n` bytes.
+    pub async fn acquire(&self, session: &Session, n: usize) {
+        let (session_rate, user_rate, global_rate) = {
+            let rates = self.rates.read().unwrap();
+            (rates.session_bytes_per_second, rates.user_bytes_per_second, rates.global_bytes_per_second)
+        };
+
+        session.bucket.take(session_rate, n).await;
+        if let Some(user) = &session.user {
+            if user_rate != 0 {
+                let bucket = Arc::clone(self.users.lock().unwrap().entry(user.clone()).or_default());
+                bucket.take(user_rate, n).await;
+            }
+        }
+        self.global.take(global_rate, n).await;
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use crate::server::session::Peer;
+    use super::*;
+
+    fn session(user: &str) -> Session {
+        let mut session = Session::new(Peer::Tcp("127.0.0.1:40000".parse().unwrap()));
+        session.user = Some(user.to_string());
+        session
+    }
+
+    fn rates(session: u64, user: u64, global: u64) -> ThrottleConfig {
+        ThrottleConfig { session_bytes_per_second: session, user_bytes_per_second: user, global_bytes_per_second: global }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_should_allow_one_second_of_burst_then_the_rate() {
+        let bucket = TokenBucket::default();
+        let start = Instant::now();
+
+        bucket.take(1000, 1000).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        bucket.take(1000, 3000).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sessions_of_the_same_user_should_share_the_user_rate() {
+        let throttle = Throttle::new(rates(0, 1000, 0));
+        let (alice_1, alice_2, bob) = (session("alice"), session("alice"), session("bob"));
+        let start = Instant::now();
+
+        throttle.acquire(&alice_1, 1000).await;
+        throttle.acquire(&bob, 1000).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        throttle.acquire(&alice_2, 1000).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn new_rates_should_apply_to_the_next_chunks() {
+        let throttle = Throttle::new(rates(1000, 0, 0));
+        let session = session("alice");
+        throttle.acquire(&session, 1000).await;
+
+        throttle.set_rates(rates(0, 0, 0));
+        let start = Instant::now();
+        throttle.acquire(&session, 1_000_000).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt};
+use super::{config::TimeoutsConfig, response::{RC_ERROR, RC_TIMEOUT}};
+
+/// Dimension of the chunks in which the payloads are written
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Time limits of the socket operations.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Time given to a new connection to send its first request (or complete the TLS handshake).
+    pub header: Duration,
+    /// Time a session can stay without requests after the first one.
+    pub idle: Duration,
+    /// Time given to the client to receive (or send) each chunk of a transfer.
+    pub chunk: Duration
+}
+impl Timeouts {
+    pub fn new(config: &TimeoutsConfig) -> Self {
+        Timeouts {
+            header: Duration::from_secs(config.header_seconds),
+            idle: Duration::from_secs(config.idle_seconds),
+            chunk: Duration::from_secs(config.chunk_seconds)
+        }
+    }
+}
+
+
+/// Write all the bytes into the socket, one chunk at a time.
+/// Return `RC_TIMEOUT` if the client doesn't receive a chunk within the timeout,
+/// `RC_ERROR` if the writing fails.
+///
+/// # Arguments
+/// * `socket` - the socket to write to.
+/// * `bytes` - the bytes to write.
+/// * `timeout` - the time limit of each chunk.
+///
+pub async fn write_with_timeout<S: AsyncWrite + Unpin + ?Sized>(socket: &mut S, bytes: &[u8], timeout: Duration) -> Result<(), u8> {
+    for chunk in bytes.chunks(CHUNK_SIZE) {
+        match tokio::time::timeout(timeout, socket.write_all(chunk)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => {
+                tracing::warn!(error = %e, "Write failed");
+                return Err(RC_ERROR);
+            },
+            Err(_) => {
+                tracing::warn!(?timeout, "Write timed out");
+                return Err(RC_TIMEOUT);
+            }
+        }
+    }
+    Ok(())
+}
+
+
+/// Read some bytes from the socket.
+/// Return `RC_TIMEOUT` if the client doesn't send anything within the timeout,
+/// `RC_ERROR` if the reading fails or the client closes the connection.
+pub async fn read_with_timeout<S: AsyncRead + Unpin + ?Sized>(socket: &mut S, buf: &mut [u8], timeout: Duration) -> Result<usize, u8> {
+    match tokio::time::timeout(timeout, socket.read(buf)).await {
+        Ok(Ok(0)) => {
+            tracing::warn!("Connection closed during the transfer");
+            Err(RC_ERROR)
+        },
+        Ok(Ok(n)) => Ok(n),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Read failed");
+            Err(RC_ERROR)
+        },
+        Err(_) => {
+            tracing::warn!(?timeout, "Read timed out");
+            Err(RC_TIMEOUT)
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn write_to_a_client_that_does_not_read_should_time_out() {
+        let (_client, mut server) = tokio::io::duplex(1024);
+
+        let bytes = vec![0u8; 4096];
+        assert_eq!(write_with_timeout(&mut server, &bytes[..512], Duration::from_secs(5)).await, Ok(()));
+        assert_eq!(write_with_timeout(&mut server, &bytes, Duration::from_secs(5)).await, Err(RC_TIMEOUT));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_from_a_silent_client_should_time_out() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let mut buf = [0u8; 16];
+
+        client.write_all(b"nFTP").await.unwrap();
+        assert_eq!(read_with_timeout(&mut server, &mut buf, Duration::from_secs(5)).await, Ok(4));
+        assert_eq!(read_with_timeout(&mut server, &mut buf, Duration::from_secs(5)).await, Err(RC_TIMEOUT));
+        drop(client);
+        assert_eq!(read_with_timeout(&mut server, &mut buf, Duration::from_secs(5)).await, Err(RC_ERROR));
+    }
+}
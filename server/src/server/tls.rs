@@ -0,0 +1,156 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig, RootCertStore,
+        crypto::ring,
+        server::WebPkiClientVerifier,
+        pki_types::{CertificateDer, PrivateKeyDer}
+    }
+};
+use super::config::TlsConfig;
+
+/// Build the acceptor that performs the TLS handshake on the accepted connections,
+/// using the certificate, the key and the optional client CA of the config.
+pub fn build_acceptor(tls_config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let provider = Arc::new(ring::default_provider());
+    let certs = load_certs(&tls_config.cert)?;
+    let key = load_key(&tls_config.key)?;
+
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+
+    let builder = match &tls_config.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca)? {
+                roots.add(cert).map_err(|e| format!("{}: {}", client_ca.display(), e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if tls_config.client_auth_optional {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+        },
+        None => builder.with_no_client_auth()
+    };
+
+    let server_config = builder.with_single_cert(certs, key).map_err(|e| e.to_string())?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+
+/// Read all the certificates of a PEM file.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path.display()));
+    }
+    Ok(certs)
+}
+
+
+/// Read the first private key of a PEM file.
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .ok_or(format!("{}: no private key found", path.display()))
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{TlsConnector, rustls::{ClientConfig, pki_types::ServerName}};
+    use super::*;
+
+    fn tls_config(client_ca: bool, client_auth_optional: bool) -> TlsConfig {
+        TlsConfig {
+            cert: PathBuf::from("./tests/tls/server.pem"),
+            key: PathBuf::from("./tests/tls/server.key"),
+            client_ca: if client_ca { Some(PathBuf::from("./tests/tls/ca.pem")) } else { None },
+            client_auth_optional
+        }
+    }
+
+    fn connector(with_client_cert: bool) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots.add(load_certs(Path::new("./tests/tls/ca.pem")).unwrap().remove(0)).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let client_config = if with_client_cert {
+            builder.with_client_auth_cert(
+                load_certs(Path::new("./tests/tls/client.pem")).unwrap(),
+                load_key(Path::new("./tests/tls/client.key")).unwrap()
+            ).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        TlsConnector::from(Arc::new(client_config))
+    }
+
+    /// Perform a handshake on an in-memory stream and exchange a few bytes.
+    async fn handshake(acceptor: TlsAcceptor, connector: TlsConnector) -> bool {
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await.ok()?;
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.ok()?;
+            stream.write_all(&buf).await.ok()?;
+            stream.flush().await.ok()
+        });
+
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let client_ok = match connector.connect(server_name, client).await {
+            Ok(mut stream) => {
+                let mut buf = [0u8; 4];
+                stream.write_all(b"nFTP").await.is_ok()
+                    && stream.read_exact(&mut buf).await.is_ok()
+                    && &buf == b"nFTP"
+            },
+            Err(_) => false
+        };
+        server.await.unwrap().is_some() && client_ok
+    }
+
+    #[test]
+    fn build_acceptor_with_missing_files_should_return_err() {
+        let mut config = tls_config(false, false);
+        config.cert = PathBuf::from("./tests/tls/missing.pem");
+        assert!(build_acceptor(&config).is_err());
+
+        let mut config = tls_config(false, false);
+        config.key = PathBuf::from("./tests/tls/server.pem");
+        assert!(build_acceptor(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn handshake_without_client_auth_should_pass() {
+        let acceptor = build_acceptor(&tls_config(false, false)).unwrap();
+        assert!(handshake(acceptor, connector(false)).await);
+    }
+
+    #[tokio::test]
+    async fn handshake_with_required_client_auth_should_need_a_client_certificate() {
+        let acceptor = build_acceptor(&tls_config(true, false)).unwrap();
+        assert!(handshake(acceptor.clone(), connector(true)).await);
+        assert!(!handshake(acceptor, connector(false)).await);
+    }
+
+    #[tokio::test]
+    async fn handshake_with_optional_client_auth_should_accept_anonymous_clients() {
+        let acceptor = build_acceptor(&tls_config(true, true)).unwrap();
+        assert!(handshake(acceptor.clone(), connector(true)).await);
+        assert!(handshake(acceptor, connector(false)).await);
+    }
+}
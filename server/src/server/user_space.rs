@@ -0,0 +1,178 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf, Component}};
+use super::{config::UserConfig, tree_serialization, named_tree_serialization};
+
+/// The part of the filesystem visible to a user.
+/// The paths sent by the user are always relative to its own space.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserSpace {
+    /// A single directory, the root of the user.
+    Home(PathBuf),
+    /// A virtual root whose entries are named directories, anywhere on the filesystem.
+    Mounts(BTreeMap<String, PathBuf>)
+}
+impl UserSpace {
+    /// Build the space of a user from its configuration.
+    /// Relative paths are relative to `main_path`; without `home` and `mounts`
+    /// the user is confined to `main_path/<name>`.
+    pub fn from_config(main_path: &Path, user: &UserConfig) -> Self {
+        if !user.mounts.is_empty() {
+            return UserSpace::Mounts(user.mounts.iter()
+                .map(|(name, path)| (name.clone(), main_path.join(path)))
+                .collect());
+        }
+        match &user.home {
+            Some(home) => UserSpace::Home(main_path.join(home)),
+            None => UserSpace::Home(main_path.join(&user.name))
+        }
+    }
+
+    /// Return the directories of the filesystem that make up this space.
+    pub fn roots(&self) -> Vec<&PathBuf> {
+        match self {
+            UserSpace::Home(home) => vec![home],
+            UserSpace::Mounts(mounts) => mounts.values().collect()
+        }
+    }
+
+    /// Map a path sent by the user to the real path on the filesystem.
+    /// Return `None` if the path goes outside the space or, for a virtual root,
+    /// if it doesn't start with a mount name.
+    pub fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        let mut components = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(c) => components.push(c),
+                Component::RootDir | Component::CurDir => (),
+                Component::ParentDir | Component::Prefix(_) => return None
+            };
+        }
+
+        let mut components = components.into_iter();
+        let mut real_path = match self {
+            UserSpace::Home(home) => home.clone(),
+            UserSpace::Mounts(mounts) => mounts.get(components.next()?.to_str()?)?.clone()
+        };
+        real_path.extend(components);
+        Some(real_path)
+    }
+
+    /// Map a real path on the filesystem back to the path in the space, the inverse
+    /// of `resolve`. Return `None` if the path isn't under a root of the space.
+    pub fn user_path(&self, real_path: &Path) -> Option<String> {
+        let (name, relative) = match self {
+            UserSpace::Home(home) => (None, real_path.strip_prefix(home).ok()?),
+            UserSpace::Mounts(mounts) => mounts.iter()
+                .find_map(|(name, root)| Some((Some(name.as_str()), real_path.strip_prefix(root).ok()?)))?
+        };
+        let components = name.into_iter().map(str::to_string)
+            .chain(relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()));
+        Some(format!("/{}", components.collect::<Vec<_>>().join("/")))
+    }
+
+    /// Return true if the real path is one of the roots of the space, which
+    /// can't be removed or moved.
+    pub fn is_root(&self, real_path: &Path) -> bool {
+        self.roots().iter().any(|root| root.as_path() == real_path)
+    }
+
+    /// Light Weight Serialization of the whole space, see `tree_serialization`.
+    /// A virtual root is serialized as `/` and each mount with its name.
+    /// Only the entries whose path in the space is `visible` are serialized.
+    pub fn tree_serialization(&self, visible: &dyn Fn(&str) -> bool, result: &mut String) -> std::io::Result<()> {
+        match self {
+            UserSpace::Home(home) => tree_serialization(home, visible, result),
+            UserSpace::Mounts(mounts) => {
+                result.push_str("/{");
+                for (name, path) in mounts {
+                    let user_path = format!("/{}", name);
+                    if visible(&user_path) {
+                        named_tree_serialization(name, path, &user_path, visible, result)?;
+                    }
+                }
+                result.push('}');
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn user(home: Option<&str>, mounts: &[(&str, &str)]) -> UserConfig {
+        UserConfig {
+            name: "alice".to_string(),
+            home: home.map(PathBuf::from),
+            mounts: mounts.iter().map(|(n, p)| (n.to_string(), PathBuf::from(p))).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn from_config_without_home_should_use_the_user_name() {
+        let space = UserSpace::from_config(Path::new("/srv"), &user(None, &[]));
+        assert_eq!(space, UserSpace::Home(PathBuf::from("/srv/alice")));
+    }
+
+    #[test]
+    fn from_config_should_keep_absolute_paths() {
+        let space = UserSpace::from_config(Path::new("/srv"), &user(Some("/data/alice"), &[]));
+        assert_eq!(space, UserSpace::Home(PathBuf::from("/data/alice")));
+
+        let space = UserSpace::from_config(Path::new("/srv"), &user(Some("x"), &[("docs", "/data/docs"), ("pub", "pub")]));
+        assert_eq!(space.roots(), vec![&PathBuf::from("/data/docs"), &PathBuf::from("/srv/pub")]);
+    }
+
+    #[test]
+    fn resolve_should_be_relative_to_the_home() {
+        let space = UserSpace::Home(PathBuf::from("/srv/alice"));
+
+        assert_eq!(space.resolve(Path::new("/dir/file.txt")), Some(PathBuf::from("/srv/alice/dir/file.txt")));
+        assert_eq!(space.resolve(Path::new("dir/./file.txt")), Some(PathBuf::from("/srv/alice/dir/file.txt")));
+        assert_eq!(space.resolve(Path::new("/")), Some(PathBuf::from("/srv/alice")));
+        assert_eq!(space.resolve(Path::new("/dir/../../bob")), None);
+    }
+
+    #[test]
+    fn resolve_should_start_from_the_mount() {
+        let space = UserSpace::from_config(Path::new("/srv"), &user(None, &[("docs", "/data/docs")]));
+
+        assert_eq!(space.resolve(Path::new("/docs/file.txt")), Some(PathBuf::from("/data/docs/file.txt")));
+        assert_eq!(space.resolve(Path::new("/music/file.mp3")), None);
+        assert_eq!(space.resolve(Path::new("/")), None);
+    }
+
+    #[test]
+    fn user_path_should_invert_resolve() {
+        let home = UserSpace::Home(PathBuf::from("/srv/alice"));
+        let mounts = UserSpace::from_config(Path::new("/srv"), &user(None, &[("docs", "/data/docs")]));
+
+        assert_eq!(home.user_path(Path::new("/srv/alice/dir/file.txt")), Some("/dir/file.txt".to_string()));
+        assert_eq!(home.user_path(Path::new("/srv/alice")), Some("/".to_string()));
+        assert_eq!(mounts.user_path(Path::new("/data/docs/a/b.txt")), Some("/docs/a/b.txt".to_string()));
+        assert_eq!(mounts.user_path(Path::new("/srv/bob/file.txt")), None);
+    }
+
+    #[test]
+    fn is_root_should_match_the_home_and_the_mounts() {
+        let space = UserSpace::from_config(Path::new("/srv"), &user(None, &[("docs", "/data/docs")]));
+
+        assert!(space.is_root(&space.resolve(Path::new("/docs")).unwrap()));
+        assert!(!space.is_root(&space.resolve(Path::new("/docs/file.txt")).unwrap()));
+        assert!(UserSpace::Home(PathBuf::from("/srv/alice")).is_root(Path::new("/srv/alice")));
+    }
+
+    #[test]
+    fn tree_serialization_of_mounts_should_use_mount_names() {
+        let space = UserSpace::from_config(
+            Path::new("."),
+            &user(None, &[("a", "./tests/tree_serialization/root/dir_1/dir_5"), ("b", "./tests/tree_serialization/root/dir_1/dir_5")])
+        );
+        let mut result = String::new();
+
+        space.tree_serialization(&|path| path != "/b/file_4.txt", &mut result).unwrap();
+
+        assert_eq!(result, "/{a{file_4.txt,}b{}}");
+    }
+}
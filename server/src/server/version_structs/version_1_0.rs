@@ -1,52 +1,137 @@
-use std::path::PathBuf;
+use std::{path::{Path, PathBuf}, time::UNIX_EPOCH};
 use async_trait::async_trait;
-use tokio::{net::TcpStream, io::AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use protocol::{
+    istruction::{GET, LIST, PUT, LOGIN, RM, MKDIR, MV, STAT, CHECKSUM, GET_DELTA, SIGNATURES, PUT_DELTA, WATCH, SEARCH, DU, SPACE},
+    du,
+    search::{self, Query},
+    space,
+    stat,
+    watch::{Event, UNWATCH},
+    delta::{self, Delta, DeltaOp, Signatures, MAX_SIGNATURES_LEN}
+};
 use crate::server::{
     version_trait::{
         Version, 
         Istruction
     }, 
     parser::{
+        Parser,
         istruction_recognition, 
-        path_recognition
+        path_recognition,
+        payload_recognition
     }, 
     response::{
         ResponseHeader, 
-        RC_OK
-    }, tree_serialization
+        RC_OK,
+        RC_ERROR,
+        RC_AUTH_FAILED,
+        RC_QUOTA_EXCEEDED,
+        RC_SERVER_BUSY,
+        RC_PERMISSION_DENIED
+    }, 
+    session::Session,
+    acl::Permission,
+    timeouts::{write_with_timeout, CHUNK_SIZE},
+    watch::{self, EventMapper, Subscription, RENAME_WAIT},
+    search::{Matcher, search},
+    du::disk_usage,
+    quotas::QuotaWriter,
+    temporary_path,
+    is_temporary,
+    is_serializable,
+    Stream
 };
 
+/// Matches found by a SEARCH waiting to be written
+const SEARCH_QUEUE_LEN: usize = 256;
+
 pub struct Version1_0;
 
 impl Version for Version1_0 {
     fn parse(&self,
-        input_bytes: &Vec<u8>,
+        input_bytes: &[u8],
         total_len: &usize, 
         acc_len: &mut usize,
         index: &mut usize
     ) -> Option<Box<dyn Istruction>> 
     {
-        let istruction = istruction_recognition(input_bytes, total_len, acc_len, index);
-        let istruction = if istruction.is_none() { return None } else { istruction.unwrap() };
+        let istruction = istruction_recognition(input_bytes, total_len, acc_len, index)?;
 
-        let paths = path_recognition(input_bytes, total_len, acc_len, index);
-        let paths = if paths.is_none() { return None } else { paths.unwrap() };
+        let paths = path_recognition(input_bytes, total_len, acc_len, index)?;
 
         match istruction {
-            0 => return Some(Box::new(Get {paths})),
-            1 => return Some(Box::new(List)),
-            // 2 => return Some(Box::new(Insert)),
+            GET => Some(Box::new(Get {paths})),
+            LIST => Some(Box::new(List)),
+            PUT => {
+                let payload_dim = payload_recognition(input_bytes, total_len, acc_len, index)?;
+                Some(Box::new(Put { paths, payload_dim, payload_start: *index }))
+            },
+            LOGIN => {
+                payload_recognition(input_bytes, total_len, acc_len, index)?;
+                if total_len < acc_len {
+                    debug!("The LOGIN payload is incomplete");
+                    return None;
+                }
+                let username = match paths.first() {
+                    Some(path) => Some(path.to_str()?.to_string()),
+                    None => None
+                };
+                Some(Box::new(Login { username, secret: input_bytes[*index..*acc_len].to_vec() }))
+            },
+            RM => Some(Box::new(Rm {paths})),
+            MKDIR => Some(Box::new(Mkdir {paths})),
+            MV => Some(Box::new(Mv {paths})),
+            STAT => Some(Box::new(Stat {paths})),
+            CHECKSUM => Some(Box::new(Checksum {paths})),
+            GET_DELTA => {
+                let payload_dim = payload_recognition(input_bytes, total_len, acc_len, index)?;
+                Some(Box::new(GetDelta { paths, payload_dim, payload_start: *index }))
+            },
+            SIGNATURES => Some(Box::new(GetSignatures {paths})),
+            PUT_DELTA => {
+                let payload_dim = payload_recognition(input_bytes, total_len, acc_len, index)?;
+                Some(Box::new(PutDelta { paths, payload_dim, payload_start: *index }))
+            },
+            WATCH => Some(Box::new(Watch {paths})),
+            SEARCH => {
+                payload_recognition(input_bytes, total_len, acc_len, index)?;
+                if total_len < acc_len {
+                    debug!("The SEARCH payload is incomplete");
+                    return None;
+                }
+                let Some(query) = Query::decode(&input_bytes[*index..*acc_len]) else {
+                    debug!("Invalid query for SEARCH request");
+                    return None;
+                };
+                Some(Box::new(Search { paths, query }))
+            },
+            DU => {
+                payload_recognition(input_bytes, total_len, acc_len, index)?;
+                let children = match input_bytes.get(*index..*acc_len) {
+                    Some([du::TOTALS]) => false,
+                    Some([du::CHILDREN]) => true,
+                    _ => {
+                        debug!("Invalid option for DU request");
+                        return None;
+                    }
+                };
+                Some(Box::new(Du { paths, children }))
+            },
+            SPACE => Some(Box::new(Space)),
 
             _ => {
-                println!("Bad Istruction");
-                return None;
+                debug!(istruction, "Bad Istruction");
+                None
             }
-        };
+        }
     }
 
     #[inline]
     fn get_version(&self) -> u8 {
-        0b0001_0000u8
+        protocol::VERSION_1_0
     }
 }
 
@@ -58,87 +143,1043 @@ pub struct Get {
 #[async_trait]
 impl Istruction for Get {
 
-    /// For the GET request, execute() checks if the path exists and if it is a file,
-    /// then creates a response header, writes it and the file into the socket.
+    /// For the GET request, execute() checks the READ permission, if the path exists in the 
+    /// user space and if it is a file, then creates a response header, writes it and the 
+    /// file into the socket, one chunk at a time.
     #[inline]
-    async fn execute(&self, socket: &mut TcpStream, _: &Vec<u8>, main_path: &PathBuf) -> bool {
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
         if self.paths.len() != 1 {
-            println!("{}: Too many paths for GET request", socket.peer_addr().unwrap());
-            return false;
+            debug!("Too many paths for GET request");
+            return Err(RC_ERROR);
         }
 
-        let complete_path = main_path.join(&self.paths[0]);
+        let complete_path = parser.resolve(session, &self.paths[0], Permission::Read)?;
         // first syscall - check the existence of the path and if the path is a file
         if !complete_path.is_file() {
-            println!("{}: A path doesn't exists for GET request", socket.peer_addr().unwrap());
-            return false;
+            debug!("A path doesn't exists for GET request");
+            return Err(RC_ERROR);
         }
         
         // second syscall - get the dimension of the file (in bytes)
         let payload_dim = match complete_path.metadata() {
             Ok(n) => n.len(),
             Err(e) => {
-                println!("{}: {}", socket.peer_addr().unwrap(), e);
-                return false;
+                warn!(error = %e, "Metadata not readable");
+                return Err(RC_ERROR);
             }
         };
 
-        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(payload_dim));
-
-        match socket.write_all(&response_header.get_header()).await {
-            // third syscall - read the file in bytes
-            Ok(_) => if let Err(e) = socket.write_all(&std::fs::read(complete_path).unwrap()).await {
-                println!("{}: {}", socket.peer_addr().unwrap(), e);
-                return false;
-            },
+        // third syscall - open the file
+        let mut file = match tokio::fs::File::open(complete_path).await {
+            Ok(file) => file,
             Err(e) => {
-                println!("{}: Header writing problem occurs: {}", socket.peer_addr().unwrap(), e);
-                return false;
+                warn!(error = %e, "File not readable");
+                return Err(RC_ERROR);
             }
         };
-        true
+
+        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(payload_dim));
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+
+        // the payload dimension has been sent: after an error the client can't find
+        // the next response, so the session is closed
+        let result = write_file(socket, &mut file, payload_dim, parser, session).await;
+        session.closing |= result.is_err();
+        result
     }
 
     #[inline]
     fn get_istruction_code(&self) -> u8 {
-        0u8
+        GET
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// Write `payload_dim` bytes of the file one chunk at a time.
+async fn write_file(socket: &mut dyn Stream, file: &mut tokio::fs::File, payload_dim: u64, parser: &Parser, session: &mut Session) -> Result<(), u8> {
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let mut remaining = payload_dim;
+    while remaining > 0 {
+        let to_read = chunk.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        match file.read(&mut chunk[..to_read]).await {
+            Ok(0) => {
+                warn!("The file was truncated during the GET request");
+                return Err(RC_ERROR);
+            },
+            Ok(n) => {
+                parser.write_payload(socket, &chunk[..n], session).await?;
+                remaining -= n as u64;
+            },
+            Err(e) => {
+                warn!(error = %e, "File not readable");
+                return Err(RC_ERROR);
+            }
+        }
     }
+    Ok(())
 }
 
 
+/// The LIST istruction
 pub struct List;
 #[async_trait]
 impl Istruction for List {
+
+    /// For the LIST request, execute() checks the LIST permission on the root and 
+    /// writes the serialized tree of the user space, walked on a blocking thread, without
+    /// the entries the user hasn't the LIST permission on: a denied directory is left out
+    /// with its contents.
     #[inline]
-    async fn execute(&self, socket: &mut TcpStream, _: &Vec<u8>, main_path: &PathBuf) -> bool {
-        let mut list = String::with_capacity(1000);
-        tree_serialization(main_path, &mut list);
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        parser.check_permission(session, Path::new("/"), Permission::List)?;
+        let user = session.user.clone().ok_or(RC_AUTH_FAILED)?;
+        let user_space = parser.user_space(session).ok_or(RC_AUTH_FAILED)?.clone();
+        let access_control = parser.access_control.clone();
+        let list = blocking(move || {
+            let visible = |path: &str| access_control.is_allowed(&user, Path::new(path), Permission::List);
+            let mut list = String::with_capacity(1000);
+            user_space.tree_serialization(&visible, &mut list)?;
+            Ok(list)
+        }).await?;
 
         let response_header = ResponseHeader::new(1, 0, RC_OK, Some(list.len() as u64));
         
-        match socket.write_all(&response_header.get_header()).await {
-            Ok(_) => if let Err(e) = socket.write_all(list.as_bytes()).await {
-                println!("{}: {}", socket.peer_addr().unwrap(), e);
-                return false;
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+        parser.write_payload(socket, list.as_bytes(), session).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        LIST
+    }
+}
+
+
+/// The LOGIN istruction.
+/// With one path, the path is the username and the payload is the password;
+/// without paths, the payload is a pre-shared API token.
+pub struct Login {
+    pub username: Option<String>,
+    pub secret: Vec<u8>
+}
+#[async_trait]
+impl Istruction for Login {
+
+    /// For the LOGIN request, execute() checks the credentials and, if they are valid,
+    /// marks the session as authenticated and writes a response header without payload.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let user = match &self.username {
+            Some(username) => parser.authenticator.login_with_password(&session.peer, username, &self.secret).await,
+            None => parser.authenticator.login_with_token(&session.peer, &self.secret)
+        };
+        let user = match user {
+            Ok(user) => user,
+            Err(response_code) => {
+                warn!(username = self.username.as_deref(), "Failed login");
+                return Err(response_code);
+            }
+        };
+        info!(%user, "Logged in");
+        session.user = Some(user);
+
+        let response_header = ResponseHeader::new(1, 0, RC_OK, None);
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        LOGIN
+    }
+
+    #[inline]
+    fn get_username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    #[inline]
+    fn requires_authentication(&self) -> bool {
+        false
+    }
+}
+
+
+/// Write a response header without payload.
+#[inline]
+async fn write_ok(socket: &mut dyn Stream, parser: &Parser) -> Result<(), u8> {
+    let response_header = ResponseHeader::new(1, 0, RC_OK, None);
+    write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await
+}
+
+
+/// Return the part of the payload read together with the request.
+#[inline]
+fn received_payload(bytes: &[u8], payload_start: usize, payload_dim: u64) -> &[u8] {
+    let received_end = usize::try_from(payload_dim).ok()
+        .and_then(|dim| payload_start.checked_add(dim))
+        .map_or(bytes.len(), |end| end.min(bytes.len()));
+    &bytes[payload_start..received_end]
+}
+
+
+/// Run a file operation on a blocking thread.
+async fn blocking<T, F>(task: F) -> Result<T, u8>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static
+{
+    match tokio::task::spawn_blocking(task).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::QuotaExceeded => {
+            warn!(error = %e, "File operation over a quota");
+            Err(RC_QUOTA_EXCEEDED)
+        },
+        Ok(Err(e)) => {
+            warn!(error = %e, "File operation failed");
+            Err(RC_ERROR)
+        },
+        Err(e) => {
+            warn!(error = %e, "Blocking task failed");
+            Err(RC_ERROR)
+        }
+    }
+}
+
+
+/// Return the only path of the request.
+#[inline]
+fn single_path<'a>(paths: &'a [PathBuf], istruction: &str) -> Result<&'a Path, u8> {
+    match paths {
+        [path] => Ok(path),
+        _ => {
+            debug!(istruction, n_paths = paths.len(), "The request needs exactly one path");
+            Err(RC_ERROR)
+        }
+    }
+}
+
+/// Check that the path can be created: its name must be a valid entry of the
+/// serialized tree of LIST, see `is_serializable`.
+#[inline]
+fn new_name(path: &Path, istruction: &str) -> Result<(), u8> {
+    if !path.file_name().and_then(|name| name.to_str()).is_some_and(is_serializable) {
+        debug!(istruction, path = %path.display(), "The name of the path isn't allowed");
+        return Err(RC_ERROR);
+    }
+    Ok(())
+}
+
+
+/// The PUT istruction
+pub struct Put {
+    pub paths: Vec<PathBuf>,
+    pub payload_dim: u64,
+    /// Index of the first byte of the payload in the request
+    pub payload_start: usize
+}
+impl Put {
+    /// Check the WRITE permission and return the real path of the file to write,
+    /// whose parent must be an existing directory.
+    fn destination(&self, parser: &Parser, session: &Session) -> Result<PathBuf, u8> {
+        let path = single_path(&self.paths, "PUT")?;
+        let complete_path = parser.resolve(session, path, Permission::Write)?;
+        new_name(&complete_path, "PUT")?;
+        if complete_path.is_dir() || !complete_path.parent().is_some_and(Path::is_dir) {
+            debug!("The path is a directory or its parent doesn't exist for PUT request");
+            return Err(RC_ERROR);
+        }
+        Ok(complete_path)
+    }
+}
+#[async_trait]
+impl Istruction for Put {
+
+    /// For the PUT request, execute() checks the WRITE permission and reserves the declared
+    /// dimension in the quotas, then streams the payload into a temporary file next to the
+    /// destination, one chunk at a time and within the quotas, then renames it, so an
+    /// interrupted upload never leaves a partial file. An existing file is replaced.
+    /// If the request is refused before the payload has been received, the session is closed.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, bytes: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let received = received_payload(bytes, self.payload_start, self.payload_dim);
+        let admitted = match self.destination(parser, session) {
+            Ok(complete_path) => parser.quotas.reserve(session, &complete_path, self.payload_dim).await
+                .map(|reservation| (complete_path, reservation)),
+            Err(response_code) => Err(response_code)
+        };
+        let (complete_path, reservation) = match admitted {
+            Ok(admitted) => admitted,
+            Err(response_code) => {
+                session.closing = (received.len() as u64) < self.payload_dim;
+                return Err(response_code);
+            }
+        };
+
+        let temp_path = temporary_path(&complete_path, "nftp-part");
+        let mut file = match tokio::fs::File::options().write(true).create_new(true).open(&temp_path).await {
+            Ok(file) => QuotaWriter::new(file, reservation.allowance()),
+            Err(e) => {
+                warn!(error = %e, "File not creatable");
+                session.closing = (received.len() as u64) < self.payload_dim;
+                return Err(RC_ERROR);
+            }
+        };
+        let result = parser.read_payload(socket, received, self.payload_dim, &mut file, session).await;
+        drop(file);
+        let result = match result {
+            Ok(()) => tokio::fs::rename(&temp_path, &complete_path).await.map_err(|e| {
+                warn!(error = %e, "File not renamable");
+                RC_ERROR
+            }),
+            Err(response_code) => Err(response_code)
+        };
+        if let Err(response_code) = result {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(response_code);
+        }
+        write_ok(socket, parser).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        PUT
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The RM istruction
+pub struct Rm {
+    pub paths: Vec<PathBuf>
+}
+#[async_trait]
+impl Istruction for Rm {
+
+    /// For the RM request, execute() checks the DELETE permission and removes the file
+    /// or the empty directory. The roots of the user space can't be removed.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let path = single_path(&self.paths, "RM")?;
+        let complete_path = parser.resolve(session, path, Permission::Delete)?;
+        if parser.user_space(session).is_some_and(|space| space.is_root(&complete_path)) {
+            warn!("A root of the user space can't be removed");
+            return Err(RC_ERROR);
+        }
+
+        let removed = match tokio::fs::symlink_metadata(&complete_path).await {
+            Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir(&complete_path).await,
+            Ok(_) => tokio::fs::remove_file(&complete_path).await,
+            Err(e) => Err(e)
+        };
+        if let Err(e) = removed {
+            debug!(error = %e, "The path can't be removed for RM request");
+            return Err(RC_ERROR);
+        }
+        write_ok(socket, parser).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        RM
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The MKDIR istruction
+pub struct Mkdir {
+    pub paths: Vec<PathBuf>
+}
+#[async_trait]
+impl Istruction for Mkdir {
+
+    /// For the MKDIR request, execute() checks the WRITE permission and creates the
+    /// directory. Its parent must exist, the path must be free and its name allowed.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let path = single_path(&self.paths, "MKDIR")?;
+        let complete_path = parser.resolve(session, path, Permission::Write)?;
+        new_name(&complete_path, "MKDIR")?;
+        if let Err(e) = tokio::fs::create_dir(&complete_path).await {
+            debug!(error = %e, "The directory can't be created for MKDIR request");
+            return Err(RC_ERROR);
+        }
+        write_ok(socket, parser).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        MKDIR
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The MV istruction
+pub struct Mv {
+    pub paths: Vec<PathBuf>
+}
+#[async_trait]
+impl Istruction for Mv {
+
+    /// For the MV request, execute() checks the DELETE permission on the source and on
+    /// everything under it, the WRITE permission on the destination and the quotas of the
+    /// directories entered, then renames the source. The destination must not exist and
+    /// have an allowed name, and the roots of the user space can't be moved.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let [source, destination] = self.paths.as_slice() else {
+            debug!(n_paths = self.paths.len(), "The MV request needs exactly two paths");
+            return Err(RC_ERROR);
+        };
+        let complete_source = parser.resolve(session, source, Permission::Delete)?;
+        if !session.user.as_ref().is_some_and(|user| parser.access_control.is_allowed_under(user, source, Permission::Delete)) {
+            warn!(source = %source.display(), "Permission denied under the source of MV request");
+            return Err(RC_PERMISSION_DENIED);
+        }
+        let complete_destination = parser.resolve(session, destination, Permission::Write)?;
+        new_name(&complete_destination, "MV")?;
+        if parser.user_space(session).is_some_and(|space| space.is_root(&complete_source) || space.is_root(&complete_destination)) {
+            warn!("A root of the user space can't be moved");
+            return Err(RC_ERROR);
+        }
+        if tokio::fs::symlink_metadata(&complete_destination).await.is_ok() {
+            debug!("The destination already exists for MV request");
+            return Err(RC_ERROR);
+        }
+
+        let _reservation = parser.quotas.reserve_move(&complete_source, &complete_destination).await?;
+        if let Err(e) = tokio::fs::rename(&complete_source, &complete_destination).await {
+            debug!(error = %e, "The path can't be moved for MV request");
+            return Err(RC_ERROR);
+        }
+        write_ok(socket, parser).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        MV
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The STAT istruction
+pub struct Stat {
+    pub paths: Vec<PathBuf>
+}
+#[async_trait]
+impl Istruction for Stat {
+
+    /// For the STAT request, execute() checks the LIST permission and writes the kind,
+    /// the dimension and the last modification time of the path.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let path = single_path(&self.paths, "STAT")?;
+        let complete_path = parser.resolve(session, path, Permission::List)?;
+        let metadata = match tokio::fs::metadata(&complete_path).await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                debug!(error = %e, "Metadata not readable for STAT request");
+                return Err(RC_ERROR);
+            }
+        };
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_secs());
+        let payload = stat::Stat { is_dir: metadata.is_dir(), size: metadata.len(), modified }.encode();
+
+        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(payload.len() as u64));
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+        parser.write_payload(socket, &payload, session).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        STAT
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The CHECKSUM istruction
+pub struct Checksum {
+    pub paths: Vec<PathBuf>
+}
+#[async_trait]
+impl Istruction for Checksum {
+
+    /// For the CHECKSUM request, execute() checks the READ permission and writes the
+    /// SHA-256 of the file, computed on a blocking thread.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let path = single_path(&self.paths, "CHECKSUM")?;
+        let complete_path = parser.resolve(session, path, Permission::Read)?;
+        if !complete_path.is_file() {
+            debug!("The path is not a file for CHECKSUM request");
+            return Err(RC_ERROR);
+        }
+
+        let digest = blocking(move || sha256(&complete_path)).await?;
+
+        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(digest.len() as u64));
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+        parser.write_payload(socket, &digest, session).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        CHECKSUM
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+/// Return the SHA-256 of the file, reading it one chunk at a time.
+fn sha256(path: &Path) -> std::io::Result<[u8; 32]> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    loop {
+        match file.read(&mut chunk)? {
+            0 => return Ok(hasher.finalize().into()),
+            n => hasher.update(&chunk[..n])
+        }
+    }
+}
+
+
+/// The GET_DELTA istruction
+pub struct GetDelta {
+    pub paths: Vec<PathBuf>,
+    pub payload_dim: u64,
+    /// Index of the first byte of the payload in the request
+    pub payload_start: usize
+}
+impl GetDelta {
+    /// Check the READ permission and return the real path of the file.
+    fn source(&self, parser: &Parser, session: &Session) -> Result<PathBuf, u8> {
+        let path = single_path(&self.paths, "GET_DELTA")?;
+        if self.payload_dim > MAX_SIGNATURES_LEN {
+            debug!(payload_dim = self.payload_dim, "The signatures are too long for GET_DELTA request");
+            return Err(RC_ERROR);
+        }
+        let complete_path = parser.resolve(session, path, Permission::Read)?;
+        if !complete_path.is_file() {
+            debug!("The path is not a file for GET_DELTA request");
+            return Err(RC_ERROR);
+        }
+        Ok(complete_path)
+    }
+}
+#[async_trait]
+impl Istruction for GetDelta {
+
+    /// For the GET_DELTA request, execute() checks the READ permission, reads the signatures
+    /// of the old copy of the client and computes the delta of the file against them on a
+    /// blocking thread, then writes the delta, reading the changed data from the file one
+    /// chunk at a time. If the request is refused before the signatures have been received,
+    /// the session is closed.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, bytes: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let received = received_payload(bytes, self.payload_start, self.payload_dim);
+        let complete_path = match self.source(parser, session) {
+            Ok(complete_path) => complete_path,
+            Err(response_code) => {
+                session.closing = (received.len() as u64) < self.payload_dim;
+                return Err(response_code);
+            }
+        };
+
+        let mut encoded = Vec::new();
+        parser.read_payload(socket, received, self.payload_dim, &mut encoded, session).await?;
+        let Some(signatures) = Signatures::decode(&encoded) else {
+            debug!("Invalid signatures for GET_DELTA request");
+            return Err(RC_ERROR);
+        };
+        let file_path = complete_path.clone();
+        let delta = blocking(move || {
+            let file = std::io::BufReader::new(std::fs::File::open(file_path)?);
+            delta::compute_delta(file, &signatures)
+        }).await?;
+
+        let mut file = match tokio::fs::File::open(&complete_path).await {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(error = %e, "File not readable");
+                return Err(RC_ERROR);
+            }
+        };
+        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(delta.encoded_len()));
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+
+        // the delta dimension has been sent: after an error the session is closed
+        let result = write_delta(socket, &delta, &mut file, parser, session).await;
+        session.closing |= result.is_err();
+        result
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        GET_DELTA
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+/// Write the encoded delta, reading its data from the new file. If the file changed
+/// since the delta was computed, the client finds it through the checksum.
+async fn write_delta(socket: &mut dyn Stream, delta: &Delta, file: &mut tokio::fs::File, parser: &Parser, session: &mut Session) -> Result<(), u8> {
+    let file_error = |e: std::io::Error| {
+        warn!(error = %e, "File not readable");
+        RC_ERROR
+    };
+    parser.write_payload(socket, &delta.encode_header(), session).await?;
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    for op in &delta.ops {
+        parser.write_payload(socket, &op.encode_header(), session).await?;
+        let DeltaOp::Data { offset, len } = *op else { continue };
+        file.seek(std::io::SeekFrom::Start(offset)).await.map_err(file_error)?;
+        let mut remaining = len;
+        while remaining > 0 {
+            let to_read = chunk.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+            match file.read(&mut chunk[..to_read]).await.map_err(file_error)? {
+                0 => {
+                    warn!("The file was truncated during the GET_DELTA request");
+                    return Err(RC_ERROR);
+                },
+                n => {
+                    parser.write_payload(socket, &chunk[..n], session).await?;
+                    remaining -= n as u64;
+                }
+            }
+        }
+    }
+    parser.write_payload(socket, &delta.encode_trailer(), session).await
+}
+
+
+/// The SIGNATURES istruction
+pub struct GetSignatures {
+    pub paths: Vec<PathBuf>
+}
+#[async_trait]
+impl Istruction for GetSignatures {
+
+    /// For the SIGNATURES request, execute() checks the READ permission and writes the
+    /// signatures of the blocks of the file, computed on a blocking thread with a block
+    /// size chosen from its dimension.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let path = single_path(&self.paths, "SIGNATURES")?;
+        let complete_path = parser.resolve(session, path, Permission::Read)?;
+        if !complete_path.is_file() {
+            debug!("The path is not a file for SIGNATURES request");
+            return Err(RC_ERROR);
+        }
+
+        let signatures = blocking(move || {
+            let file = std::fs::File::open(complete_path)?;
+            let block_size = delta::block_size_for(file.metadata()?.len());
+            Signatures::compute(std::io::BufReader::new(file), block_size)
+        }).await?.encode();
+
+        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(signatures.len() as u64));
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+        parser.write_payload(socket, &signatures, session).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        SIGNATURES
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The PUT_DELTA istruction
+pub struct PutDelta {
+    pub paths: Vec<PathBuf>,
+    pub payload_dim: u64,
+    /// Index of the first byte of the payload in the request
+    pub payload_start: usize
+}
+impl PutDelta {
+    /// Check the WRITE permission and return the real path of the file to update,
+    /// that must exist: it's the old copy the delta refers to.
+    fn destination(&self, parser: &Parser, session: &Session) -> Result<PathBuf, u8> {
+        let path = single_path(&self.paths, "PUT_DELTA")?;
+        let complete_path = parser.resolve(session, path, Permission::Write)?;
+        if !complete_path.is_file() {
+            debug!("The path is not a file for PUT_DELTA request");
+            return Err(RC_ERROR);
+        }
+        Ok(complete_path)
+    }
+}
+#[async_trait]
+impl Istruction for PutDelta {
+
+    /// For the PUT_DELTA request, execute() checks the WRITE permission and the quotas, with
+    /// the dimension of the existing file, and streams the delta into a temporary file, then
+    /// rebuilds the new file from it and the existing one on a blocking thread, within the
+    /// quotas, checks its SHA-256 and renames it over the old one.
+    /// If the request is refused before the delta has been received, the session is closed.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, bytes: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let received = received_payload(bytes, self.payload_start, self.payload_dim);
+        let admitted = match self.destination(parser, session) {
+            Ok(complete_path) => {
+                let old_size = tokio::fs::metadata(&complete_path).await.map_or(0, |metadata| metadata.len());
+                parser.quotas.reserve(session, &complete_path, old_size).await.map(|reservation| (complete_path, reservation))
             },
+            Err(response_code) => Err(response_code)
+        };
+        let (complete_path, reservation) = match admitted {
+            Ok(admitted) => admitted,
+            Err(response_code) => {
+                session.closing = (received.len() as u64) < self.payload_dim;
+                return Err(response_code);
+            }
+        };
+
+        let delta_path = temporary_path(&complete_path, "nftp-delta");
+        let temp_path = temporary_path(&complete_path, "nftp-part");
+        let mut delta_file = match tokio::fs::File::options().write(true).create_new(true).open(&delta_path).await {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(error = %e, "File not creatable");
+                session.closing = (received.len() as u64) < self.payload_dim;
+                return Err(RC_ERROR);
+            }
+        };
+        let result = parser.read_payload(socket, received, self.payload_dim, &mut delta_file, session).await;
+        drop(delta_file);
+
+        let result = match result {
+            Ok(()) => {
+                let (delta_path, temp_path, complete_path) = (delta_path.clone(), temp_path.clone(), complete_path.clone());
+                let allowance = reservation.allowance();
+                blocking(move || {
+                    let delta = std::io::BufReader::new(std::fs::File::open(&delta_path)?);
+                    let old_copy = std::fs::File::open(&complete_path)?;
+                    let mut new_file = std::io::BufWriter::new(QuotaWriter::new(std::fs::File::create_new(&temp_path)?, allowance));
+                    delta::apply_delta(delta, old_copy, &mut new_file)?;
+                    new_file.into_inner().map_err(|e| e.into_error())?;
+                    std::fs::rename(&temp_path, &complete_path)
+                }).await
+            },
+            Err(response_code) => Err(response_code)
+        };
+        let _ = tokio::fs::remove_file(&delta_path).await;
+        if let Err(response_code) = result {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(response_code);
+        }
+        write_ok(socket, parser).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        PUT_DELTA
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The WATCH istruction
+pub struct Watch {
+    pub paths: Vec<PathBuf>
+}
+#[async_trait]
+impl Istruction for Watch {
+
+    /// For the WATCH request, execute() checks the LIST permission and subscribes to the
+    /// changes under the path, then writes an event for each one the user can see, until
+    /// the client unsubscribes or the server shuts down, and writes the END event.
+    /// If the client closes the connection instead, the session is closed. When too many
+    /// requests are already watching, the SERVER BUSY response code is returned.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let path = single_path(&self.paths, "WATCH")?;
+        let complete_path = parser.resolve(session, path, Permission::List)?;
+        let (Some(user), Some(user_space)) = (session.user.clone(), parser.user_space(session)) else {
+            return Err(RC_AUTH_FAILED);
+        };
+        if !complete_path.exists() {
+            debug!("The path doesn't exist for WATCH request");
+            return Err(RC_ERROR);
+        }
+        let Some(_watch_permit) = parser.limits.try_watch() else {
+            warn!("Too many WATCH requests, refusing");
+            return Err(RC_SERVER_BUSY);
+        };
+        let mut subscription = match Subscription::new(&complete_path) {
+            Ok(subscription) => subscription,
+            Err(e) => {
+                warn!(error = %e, "The path can't be watched");
+                return Err(RC_ERROR);
+            }
+        };
+        let mut mapper = EventMapper::new(user_space);
+        write_ok(socket, parser).await?;
+        info!("Watching the path");
+
+        let mut byte = [0u8; 1];
+        loop {
+            let (mut events, unwatched) = tokio::select! {
+                biased;
+                _ = parser.shutdown.triggered() => (mapper.flush(), true),
+                read = socket.read(&mut byte) => match read {
+                    Ok(1) if byte[0] == UNWATCH => (mapper.flush(), true),
+                    _ => {
+                        debug!("The client closed the connection or sent an unknown byte while watching");
+                        session.closing = true;
+                        return Ok(());
+                    }
+                },
+                change = subscription.recv() => match change {
+                    Ok(change) => (mapper.map(change), false),
+                    Err(e) => {
+                        warn!(error = %e, "Watch error");
+                        (vec![Event::Overflow], false)
+                    }
+                },
+                _ = tokio::time::sleep(RENAME_WAIT), if mapper.has_pending() => (mapper.flush(), false)
+            };
+            if subscription.overflowed() {
+                events.push(Event::Overflow);
+            }
+            if unwatched {
+                events.push(Event::End);
+            }
+            for event in events {
+                let allowed = |path: &str| parser.access_control.is_allowed(&user, Path::new(path), Permission::List)
+                    && !Path::new(path).file_name().is_some_and(is_temporary);
+                let Some(event) = watch::visible(event, allowed) else { continue };
+                if let Err(response_code) = parser.write_payload(socket, &event.encode(), session).await {
+                    session.closing = true;
+                    return Err(response_code);
+                }
+            }
+            if unwatched {
+                info!("Watch ended");
+                return Ok(());
+            }
+        }
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        WATCH
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The SEARCH istruction
+pub struct Search {
+    pub paths: Vec<PathBuf>,
+    pub query: Query
+}
+#[async_trait]
+impl Istruction for Search {
+
+    /// For the SEARCH request, execute() checks the LIST permission and compiles the pattern,
+    /// then walks the directory on a blocking thread and writes each match the user can see
+    /// as soon as it's found, and the end of the results.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let path = single_path(&self.paths, "SEARCH")?;
+        let complete_path = parser.resolve(session, path, Permission::List)?;
+        let Some(user) = session.user.clone() else { return Err(RC_AUTH_FAILED) };
+        let Some(user_start) = parser.user_space(session).and_then(|space| space.user_path(&complete_path)) else {
+            return Err(RC_ERROR);
+        };
+        if !complete_path.is_dir() {
+            debug!("The path is not a directory for SEARCH request");
+            return Err(RC_ERROR);
+        }
+        let matcher = match Matcher::new(&self.query.pattern) {
+            Ok(matcher) => matcher,
             Err(e) => {
-                println!("{}: Header writing problem occurs: {}", socket.peer_addr().unwrap(), e);
-                return false;
+                debug!(error = %e, "Invalid pattern for SEARCH request");
+                return Err(RC_ERROR);
             }
         };
-        true
+
+        let (sender, mut found) = tokio::sync::mpsc::channel(SEARCH_QUEUE_LEN);
+        let query = self.query.clone();
+        tokio::task::spawn_blocking(move || search(&complete_path, &user_start, &query, &matcher, &sender));
+        write_ok(socket, parser).await?;
+
+        while let Some(found) = found.recv().await {
+            if !parser.access_control.is_allowed(&user, Path::new(&found.path), Permission::List) {
+                continue;
+            }
+            let Some(bytes) = found.encode() else { continue };
+            if let Err(response_code) = parser.write_payload(socket, &bytes, session).await {
+                session.closing = true;
+                return Err(response_code);
+            }
+        }
+        let result = parser.write_payload(socket, &search::END, session).await;
+        session.closing |= result.is_err();
+        result
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        SEARCH
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The DU istruction
+pub struct Du {
+    pub paths: Vec<PathBuf>,
+    pub children: bool
+}
+#[async_trait]
+impl Istruction for Du {
+
+    /// For the DU request, execute() checks the LIST permission on every path, then walks
+    /// them on a blocking thread and writes their usage. Only the entries with the LIST
+    /// permission are counted: a denied directory is skipped with its contents.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        if self.paths.is_empty() {
+            debug!("No paths for DU request");
+            return Err(RC_ERROR);
+        }
+        let Some(user) = session.user.clone() else { return Err(RC_AUTH_FAILED) };
+        let mut complete_paths = Vec::with_capacity(self.paths.len());
+        for path in &self.paths {
+            complete_paths.push(parser.resolve(session, path, Permission::List)?);
+        }
+
+        let children = self.children;
+        let user_paths: Vec<String> = self.paths.iter().map(|path| path.display().to_string()).collect();
+        let access_control = parser.access_control.clone();
+        let usages = blocking(move || {
+            let visible = |path: &str| access_control.is_allowed(&user, Path::new(path), Permission::List);
+            complete_paths.iter().zip(&user_paths)
+                .map(|(path, user_path)| disk_usage(path, user_path, children, &visible))
+                .collect::<std::io::Result<Vec<_>>>()
+        }).await?;
+        let Some(payload) = du::encode_usages(&usages) else {
+            debug!("A name is too long for DU request");
+            return Err(RC_ERROR);
+        };
+
+        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(payload.len() as u64));
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+        parser.write_payload(socket, &payload, session).await
+    }
+
+    #[inline]
+    fn get_istruction_code(&self) -> u8 {
+        DU
+    }
+
+    #[inline]
+    fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+
+/// The SPACE istruction
+pub struct Space;
+#[async_trait]
+impl Istruction for Space {
+
+    /// For the SPACE request, execute() writes the total and available dimension of the
+    /// filesystem holding the main path, read on a blocking thread, and what's left of
+    /// the quotas of the user.
+    #[inline]
+    async fn execute(&self, socket: &mut dyn Stream, _: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8> {
+        let main_path = parser.main_path.clone();
+        let (total, available) = blocking(move || filesystem_space(&main_path)).await?;
+        let (remaining_bytes, remaining_files) = parser.quotas.remaining(session).await?;
+        let payload = space::Space { total, available, remaining_bytes, remaining_files }.encode();
+
+        let response_header = ResponseHeader::new(1, 0, RC_OK, Some(payload.len() as u64));
+        write_with_timeout(socket, response_header.get_header(), parser.timeouts.chunk).await?;
+        parser.write_payload(socket, &payload, session).await
     }
 
     #[inline]
     fn get_istruction_code(&self) -> u8 {
-        1u8
+        SPACE
     }
 }
 
+/// Return the total dimension of the filesystem holding the path and the bytes
+/// available to unprivileged users.
+fn filesystem_space(path: &Path) -> std::io::Result<(u64, u64)> {
+    let stat = nix::sys::statvfs::statvfs(path)?;
+    let fragment = stat.fragment_size() as u64;
+    Ok((
+        (stat.blocks() as u64).saturating_mul(fragment),
+        (stat.blocks_available() as u64).saturating_mul(fragment)
+    ))
+}
+
 
 #[cfg(test)]
 pub mod test {
-    use crate::server::version_trait::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::server::{version_trait::*, test_utils::*, response::RC_PERMISSION_DENIED};
     use super::*;
 
     #[test]
@@ -195,4 +1236,604 @@ pub mod test {
         let res = Version1_0.parse(&input_bytes, &input_bytes.len(), &mut acc_len, &mut index);
         assert!(res.is_none());
     }
+
+    #[test]
+    fn parse_should_return_login() {
+        let mut input_bytes: Vec<u8> = Vec::new();
+        let username = b"alice";
+        let password = b"secret";
+        let mut acc_len: usize = 0;
+        let mut index: usize = 0;
+        let login_code = 3u8;
+
+        input_bytes.push(login_code);
+        input_bytes.push(1u8); // 1 path, the username
+
+        input_bytes.extend_from_slice(&(username.len() as u16).to_be_bytes());
+        input_bytes.extend_from_slice(username);
+
+        input_bytes.extend_from_slice(&(password.len() as u64).to_be_bytes());
+        input_bytes.extend_from_slice(password);
+
+        let res = Version1_0.parse(&input_bytes, &input_bytes.len(), &mut acc_len, &mut index);
+        assert!(res.is_some());
+        let res = res.unwrap();
+        assert_eq!(login_code, res.get_istruction_code());
+        assert!(!res.requires_authentication());
+    }
+
+    #[test]
+    fn parse_login_with_incomplete_payload_should_return_err() {
+        let mut input_bytes: Vec<u8> = Vec::new();
+        let token = b"token";
+        let mut acc_len: usize = 0;
+        let mut index: usize = 0;
+
+        input_bytes.push(3u8);
+        input_bytes.push(0u8); // 0 paths, the payload is a token
+
+        input_bytes.extend_from_slice(&(token.len() as u64 + 1).to_be_bytes());
+        input_bytes.extend_from_slice(token);
+
+        let res = Version1_0.parse(&input_bytes, &input_bytes.len(), &mut acc_len, &mut index);
+        assert!(res.is_none());
+    }
+
+    #[tokio::test]
+    async fn get_should_return_the_file_of_the_user_space() {
+        let mut client = logged_client(test_parser("")).await;
+
+        let (response_code, file) = send(&mut client, &request(0, &["dir_1/dir_5/file_4.txt"], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        assert_eq!(file.unwrap(), std::fs::read("./tests/tree_serialization/root/dir_1/dir_5/file_4.txt").unwrap());
+    }
+
+    /// Read from the client until the server closes the connection, failing after a few seconds.
+    async fn read_until_closed(client: &mut tokio::io::DuplexStream) {
+        let mut buf = vec![0u8; 64 * 1024];
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while client.read(&mut buf).await.unwrap() > 0 {}
+        }).await.expect("the connection should be closed");
+    }
+
+    #[tokio::test]
+    async fn get_of_a_file_truncated_during_the_transfer_should_close_the_session() {
+        let (parser, home) = writable_test_parser("get-truncated", "");
+        let mut client = logged_client(parser).await;
+        std::fs::write(home.join("big.bin"), vec![7u8; 1 << 20]).unwrap();
+
+        client.write_all(&request(GET, &["big.bin"], None)).await.unwrap();
+        let mut header = [0u8; 14];
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!((header[5], u64::from_be_bytes(header[6..].try_into().unwrap())), (RC_OK, 1 << 20));
+        std::fs::File::options().write(true).open(home.join("big.bin")).unwrap().set_len(0).unwrap();
+
+        read_until_closed(&mut client).await;
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_with_a_directory_or_outside_path_should_return_err() {
+        let mut client = logged_client(test_parser("")).await;
+
+        assert_eq!(send(&mut client, &request(0, &["/dir_1"], None), true).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(0, &["/../root/file.txt"], None), true).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(0, &["/file.txt", "/dir_1/file_1.txt"], None), true).await, (RC_ERROR, None));
+    }
+
+    #[tokio::test]
+    async fn get_and_list_without_permission_should_return_permission_denied() {
+        let mut client = logged_client(test_parser(r#"
+            [[acl]]
+            path = "/dir_1"
+            users = ["alice"]
+            allow = ["read"]
+        "#)).await;
+
+        assert_eq!(send(&mut client, &request(0, &["/dir_1/file_1.txt"], None), true).await.0, RC_OK);
+        assert_eq!(send(&mut client, &request(0, &["/file.txt"], None), true).await, (RC_PERMISSION_DENIED, None));
+        assert_eq!(send(&mut client, &request(1, &[], None), true).await, (RC_PERMISSION_DENIED, None));
+    }
+
+    #[tokio::test]
+    async fn list_should_leave_out_the_denied_paths() {
+        let mut client = logged_client(test_parser(r#"
+            [[acl]]
+            path = "/"
+            users = ["alice"]
+            allow = ["list"]
+
+            [[acl]]
+            path = "/dir_1"
+            users = ["alice"]
+            allow = []
+
+            [[acl]]
+            path = "/file.txt"
+            users = ["alice"]
+            allow = ["read"]
+        "#)).await;
+
+        let (response_code, list) = send(&mut client, &request(LIST, &[], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        let list = String::from_utf8(list.unwrap()).unwrap();
+        assert!(list.starts_with("root{") && list.contains("dir_2{"));
+        assert!(!list.contains("dir_1") && !list.contains("file"));
+    }
+
+    #[test]
+    fn parse_put_with_partial_payload_should_return_put() {
+        let request = protocol::request::Request::new(PUT).path("/file.txt").encode_with_payload_dim(1 << 20).unwrap();
+        let mut input_bytes = request[5..].to_vec();
+        input_bytes.extend_from_slice(b"hello");
+        let mut acc_len: usize = 0;
+        let mut index: usize = 0;
+
+        let res = Version1_0.parse(&input_bytes, &input_bytes.len(), &mut acc_len, &mut index);
+        assert_eq!(res.unwrap().get_istruction_code(), PUT);
+        assert_eq!(&input_bytes[index..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn put_should_write_the_file_and_get_should_return_it() {
+        let (parser, home) = writable_test_parser("put", "");
+        let mut client = logged_client(parser).await;
+        let file: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+
+        let mut put = protocol::request::Request::new(PUT).path("dir/big.bin").encode_with_payload_dim(file.len() as u64).unwrap();
+        put.extend_from_slice(&file);
+
+        assert_eq!(send(&mut client, &put, false).await, (RC_OK, None));
+        assert_eq!(std::fs::read(home.join("dir/big.bin")).unwrap(), file);
+        assert_eq!(send(&mut client, &request(PUT, &["file.txt"], Some(b"replaced")), false).await, (RC_OK, None));
+        assert_eq!(send(&mut client, &request(GET, &["file.txt"], None), true).await, (RC_OK, Some(b"replaced".to_vec())));
+        assert_eq!(std::fs::read_dir(home.join("dir")).unwrap().count(), 1);
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_puts_to_the_same_path_should_not_collide() {
+        let (parser, home) = writable_test_parser("put-concurrent", "");
+        let mut first = logged_client(parser.clone()).await;
+        let mut second = logged_client(parser).await;
+        let file = vec![1u8; 200_000];
+
+        let put = with_payload(PUT, "dir/shared.bin", &file);
+        first.write_all(&put[..put.len() - 100_000]).await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while std::fs::read_dir(home.join("dir")).unwrap().count() == 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }).await.expect("the upload should be in progress");
+
+        let (response_code, list) = send(&mut second, &request(LIST, &[], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        assert!(String::from_utf8(list.unwrap()).unwrap().contains("dir{}"));
+        assert_eq!(send(&mut second, &with_payload(PUT, "dir/shared.bin", b"second"), false).await, (RC_OK, None));
+        assert_eq!(send(&mut first, &put[put.len() - 100_000..], false).await, (RC_OK, None));
+        assert_eq!(std::fs::read(home.join("dir/shared.bin")).unwrap(), file);
+        assert_eq!(std::fs::read_dir(home.join("dir")).unwrap().count(), 1);
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn names_breaking_the_list_should_be_refused() {
+        let (parser, home) = writable_test_parser("names", "");
+        let mut client = logged_client(parser).await;
+
+        assert_eq!(send(&mut client, &request(PUT, &["a,b.txt"], Some(b"new")), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(PUT, &[".a.1-1.nftp-part"], Some(b"new")), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(MKDIR, &["dir/{sub}"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(MV, &["file.txt", "file}.txt"], None), false).await, (RC_ERROR, None));
+        let (response_code, list) = send(&mut client, &request(LIST, &[], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        assert!([&b"root{dir{}file.txt,}"[..], b"root{file.txt,dir{}}"].contains(&list.unwrap().as_slice()));
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_refused_before_the_payload_should_close_the_session() {
+        let (parser, home) = writable_test_parser("put-refused", r#"
+            [[acl]]
+            path = "/"
+            users = ["alice"]
+            allow = ["read"]
+        "#);
+        let mut client = logged_client(parser).await;
+        let file = vec![0u8; 100_000];
+
+        assert_eq!(send(&mut client, &request(PUT, &["new.bin"], Some(&file[..10])), false).await, (RC_PERMISSION_DENIED, None));
+        let request = protocol::request::Request::new(PUT).path("new.bin").encode_with_payload_dim(file.len() as u64).unwrap();
+        assert_eq!(send(&mut client, &request, false).await, (RC_PERMISSION_DENIED, None));
+        assert_eq!(client.read(&mut [0u8; 16]).await.unwrap(), 0);
+        assert!(!home.join("new.bin").exists());
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn uploads_over_a_quota_should_be_refused() {
+        let (parser, home) = writable_test_parser("quotas", r#"
+            [[quotas]]
+            user = "alice"
+            max_bytes = 20
+
+            [[quotas]]
+            path = "root/dir"
+            max_files = 1
+        "#);
+        let mut client = logged_client(parser).await;
+
+        assert_eq!(send(&mut client, &request(PUT, &["dir/a.txt"], Some(&[0u8; 10])), false).await, (RC_OK, None));
+        assert_eq!(send(&mut client, &request(PUT, &["dir/b.txt"], Some(b"b")), false).await, (RC_QUOTA_EXCEEDED, None));
+        assert_eq!(send(&mut client, &request(PUT, &["file.txt"], Some(&[0u8; 11])), false).await, (RC_QUOTA_EXCEEDED, None));
+        assert_eq!(send(&mut client, &request(PUT, &["file.txt"], Some(&[0u8; 10])), false).await, (RC_OK, None));
+        assert_eq!(send(&mut client, &request(MV, &["file.txt", "dir/file.txt"], None), false).await, (RC_QUOTA_EXCEEDED, None));
+
+        let (response_code, payload) = send(&mut client, &request(SPACE, &[], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        let space = space::Space::decode(&payload.unwrap()).unwrap();
+        assert_eq!((space.remaining_bytes, space.remaining_files), (Some(0), None));
+
+        let put = protocol::request::Request::new(PUT).path("new.bin").encode_with_payload_dim(100_000).unwrap();
+        assert_eq!(send(&mut client, &put, false).await, (RC_QUOTA_EXCEEDED, None));
+        assert_eq!(client.read(&mut [0u8; 16]).await.unwrap(), 0);
+        assert!(!home.join("dir/b.txt").exists() && !home.join("new.bin").exists());
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rm_mkdir_and_mv_should_change_the_user_space() {
+        let (parser, home) = writable_test_parser("rm-mkdir-mv", "");
+        let mut client = logged_client(parser).await;
+
+        assert_eq!(send(&mut client, &request(MKDIR, &["dir/sub"], None), false).await, (RC_OK, None));
+        assert!(home.join("dir/sub").is_dir());
+        assert_eq!(send(&mut client, &request(MV, &["file.txt", "dir/sub/moved.txt"], None), false).await, (RC_OK, None));
+        assert_eq!(std::fs::read(home.join("dir/sub/moved.txt")).unwrap(), b"hello");
+        assert_eq!(send(&mut client, &request(RM, &["dir/sub"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(RM, &["dir/sub/moved.txt"], None), false).await, (RC_OK, None));
+        assert_eq!(send(&mut client, &request(RM, &["dir/sub"], None), false).await, (RC_OK, None));
+        assert!(!home.join("dir/sub").exists());
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rm_mkdir_and_mv_with_invalid_paths_should_return_err() {
+        let (parser, home) = writable_test_parser("invalid-paths", "");
+        let mut client = logged_client(parser).await;
+
+        assert_eq!(send(&mut client, &request(RM, &["/"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(RM, &["missing.txt"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(MKDIR, &["dir"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(MKDIR, &["missing/dir"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(MV, &["file.txt", "dir"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(MV, &["/", "dir/root"], None), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(MV, &["file.txt"], None), false).await, (RC_ERROR, None));
+        assert!(home.join("file.txt").is_file());
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rm_and_mv_without_permission_should_return_permission_denied() {
+        let (parser, home) = writable_test_parser("rm-mv-denied", r#"
+            [[acl]]
+            path = "/"
+            users = ["alice"]
+            allow = ["read", "write"]
+        "#);
+        let mut client = logged_client(parser).await;
+
+        assert_eq!(send(&mut client, &request(RM, &["file.txt"], None), false).await, (RC_PERMISSION_DENIED, None));
+        assert_eq!(send(&mut client, &request(MV, &["file.txt", "dir/file.txt"], None), false).await, (RC_PERMISSION_DENIED, None));
+        assert_eq!(send(&mut client, &request(MKDIR, &["new"], None), false).await, (RC_OK, None));
+        assert!(home.join("file.txt").is_file());
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn mv_of_a_directory_with_a_protected_path_should_return_permission_denied() {
+        let (parser, home) = writable_test_parser("mv-protected", r#"
+            [[acl]]
+            path = "/"
+            users = ["alice"]
+            allow = ["read", "write", "delete"]
+
+            [[acl]]
+            path = "/dir/protected.txt"
+            users = ["alice"]
+            allow = ["read"]
+        "#);
+        std::fs::write(home.join("dir/protected.txt"), b"keep").unwrap();
+        let mut client = logged_client(parser).await;
+
+        assert_eq!(send(&mut client, &request(MV, &["dir", "moved"], None), false).await, (RC_PERMISSION_DENIED, None));
+        assert!(home.join("dir/protected.txt").is_file());
+        assert_eq!(send(&mut client, &request(MV, &["file.txt", "dir/file.txt"], None), false).await, (RC_OK, None));
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stat_should_return_the_metadata_of_the_path() {
+        let mut client = logged_client(test_parser("")).await;
+
+        let (response_code, payload) = send(&mut client, &request(STAT, &["dir_1/dir_5/file_4.txt"], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        let stat = stat::Stat::decode(&payload.unwrap()).unwrap();
+        assert!(!stat.is_dir);
+        assert_eq!(stat.size, std::fs::metadata("./tests/tree_serialization/root/dir_1/dir_5/file_4.txt").unwrap().len());
+        assert!(stat.modified > 0);
+
+        let (_, payload) = send(&mut client, &request(STAT, &["/dir_1"], None), true).await;
+        assert!(stat::Stat::decode(&payload.unwrap()).unwrap().is_dir);
+        assert_eq!(send(&mut client, &request(STAT, &["/missing"], None), true).await, (RC_ERROR, None));
+    }
+
+    #[tokio::test]
+    async fn checksum_should_return_the_sha256_of_the_file() {
+        let (parser, home) = writable_test_parser("checksum", "");
+        let mut client = logged_client(parser).await;
+
+        let (response_code, digest) = send(&mut client, &request(CHECKSUM, &["file.txt"], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        assert_eq!(digest.unwrap(), Sha256::digest(b"hello").to_vec());
+        assert_eq!(send(&mut client, &request(CHECKSUM, &["dir"], None), true).await, (RC_ERROR, None));
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    fn with_payload(istruction: u8, path: &str, payload: &[u8]) -> Vec<u8> {
+        let mut request = protocol::request::Request::new(istruction).path(path).encode_with_payload_dim(payload.len() as u64).unwrap();
+        request.extend_from_slice(payload);
+        request
+    }
+
+    #[tokio::test]
+    async fn get_delta_of_a_file_truncated_during_the_transfer_should_close_the_session() {
+        let (parser, home) = writable_test_parser("get-delta-truncated", "");
+        let mut client = logged_client(parser).await;
+        std::fs::write(home.join("big.bin"), vec![7u8; 1 << 20]).unwrap();
+        let signatures = Signatures::compute(std::io::empty(), 2048).unwrap().encode();
+
+        client.write_all(&with_payload(GET_DELTA, "big.bin", &signatures)).await.unwrap();
+        let mut header = [0u8; 14];
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[5], RC_OK);
+        std::fs::File::options().write(true).open(home.join("big.bin")).unwrap().set_len(0).unwrap();
+
+        read_until_closed(&mut client).await;
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_delta_should_return_the_changes_against_the_old_copy() {
+        let (parser, home) = writable_test_parser("get-delta", "");
+        let mut client = logged_client(parser).await;
+        let new: Vec<u8> = (0..50_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        std::fs::write(home.join("big.bin"), &new).unwrap();
+        let mut old = new.clone();
+        old[10_000..10_100].fill(0);
+        let signatures = Signatures::compute(old.as_slice(), 2048).unwrap().encode();
+
+        let (response_code, encoded) = send(&mut client, &with_payload(GET_DELTA, "big.bin", &signatures), true).await;
+        assert_eq!(response_code, RC_OK);
+        let encoded = encoded.unwrap();
+        assert!(encoded.len() < 5000);
+        let mut rebuilt = Vec::new();
+        delta::apply_delta(encoded.as_slice(), std::io::Cursor::new(&old), &mut rebuilt).unwrap();
+        assert_eq!(rebuilt, new);
+
+        assert_eq!(send(&mut client, &with_payload(GET_DELTA, "big.bin", b"invalid"), true).await, (RC_ERROR, None));
+        // one operation for each byte of the file would be kept in memory
+        let tiny_blocks = Signatures::compute(&old[..256], 1).unwrap().encode();
+        assert_eq!(send(&mut client, &with_payload(GET_DELTA, "big.bin", &tiny_blocks), true).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &with_payload(GET_DELTA, "dir", &signatures), true).await, (RC_ERROR, None));
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_delta_should_rebuild_the_file_from_its_signatures() {
+        let (parser, home) = writable_test_parser("put-delta", "");
+        let mut client = logged_client(parser).await;
+        let old: Vec<u8> = (0..50_000u32).map(|i| (i * 13 % 241) as u8).collect();
+        std::fs::write(home.join("dir/big.bin"), &old).unwrap();
+        let mut new = b"prepended".to_vec();
+        new.extend_from_slice(&old);
+
+        let (response_code, signatures) = send(&mut client, &request(SIGNATURES, &["dir/big.bin"], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        let signatures = Signatures::decode(&signatures.unwrap()).unwrap();
+        assert_eq!(signatures.file_len, old.len() as u64);
+        let delta = delta::compute_delta(new.as_slice(), &signatures).unwrap();
+        let mut encoded = Vec::new();
+        delta.write(std::io::Cursor::new(&new), &mut encoded).unwrap();
+
+        let mut corrupted = encoded.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(send(&mut client, &with_payload(PUT_DELTA, "dir/big.bin", &corrupted), false).await, (RC_ERROR, None));
+        assert_eq!(std::fs::read(home.join("dir/big.bin")).unwrap(), old);
+
+        assert_eq!(send(&mut client, &with_payload(PUT_DELTA, "dir/big.bin", &encoded), false).await, (RC_OK, None));
+        assert_eq!(std::fs::read(home.join("dir/big.bin")).unwrap(), new);
+        assert_eq!(std::fs::read_dir(home.join("dir")).unwrap().count(), 1);
+        assert_eq!(send(&mut client, &with_payload(PUT_DELTA, "missing.bin", &encoded), false).await, (RC_ERROR, None));
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_delta_growing_over_a_quota_should_be_refused_while_rebuilding() {
+        let (parser, home) = writable_test_parser("put-delta-quota", r#"
+            [[quotas]]
+            user = "alice"
+            max_bytes = 50_010
+        "#);
+        let mut client = logged_client(parser).await;
+        let old: Vec<u8> = (0..50_000u32).map(|i| (i * 13 % 241) as u8).collect();
+        std::fs::write(home.join("dir/big.bin"), &old).unwrap();
+        let mut new = b"prepended".to_vec();
+        new.extend_from_slice(&old);
+
+        let (_, signatures) = send(&mut client, &request(SIGNATURES, &["dir/big.bin"], None), true).await;
+        let delta = delta::compute_delta(new.as_slice(), &Signatures::decode(&signatures.unwrap()).unwrap()).unwrap();
+        let mut encoded = Vec::new();
+        delta.write(std::io::Cursor::new(&new), &mut encoded).unwrap();
+
+        assert_eq!(send(&mut client, &with_payload(PUT_DELTA, "dir/big.bin", &encoded), false).await, (RC_QUOTA_EXCEEDED, None));
+        assert_eq!(std::fs::read(home.join("dir/big.bin")).unwrap(), old);
+        assert_eq!(std::fs::read_dir(home.join("dir")).unwrap().count(), 1);
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    /// Read the next WATCH event, failing after a few seconds.
+    async fn next_event(client: &mut tokio::io::DuplexStream) -> Event {
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            let kind = client.read_u8().await.unwrap();
+            let mut paths = Vec::new();
+            for _ in 0..Event::n_paths(kind).unwrap() {
+                let mut path = vec![0u8; client.read_u16().await.unwrap() as usize];
+                client.read_exact(&mut path).await.unwrap();
+                paths.push(String::from_utf8(path).unwrap());
+            }
+            Event::from_parts(kind, paths).unwrap()
+        }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn watch_should_stream_the_changes_until_unwatch() {
+        let (parser, home) = writable_test_parser("watch", r#"
+            [[acl]]
+            path = "/"
+            users = ["alice"]
+            allow = ["list", "read"]
+
+            [[acl]]
+            path = "/dir/hidden"
+            users = ["alice"]
+            allow = []
+        "#);
+        let mut client = logged_client(parser).await;
+
+        assert_eq!(send(&mut client, &request(WATCH, &["dir"], None), false).await, (RC_OK, None));
+        std::fs::write(home.join("dir/hidden"), b"secret").unwrap();
+        std::fs::create_dir(home.join("dir/sub")).unwrap();
+        assert_eq!(next_event(&mut client).await, Event::Create("/dir/sub".to_string()));
+        // the new directory is watched right after its creation is reported
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        std::fs::write(home.join("dir/sub/new.txt"), b"new").unwrap();
+        assert_eq!(next_event(&mut client).await, Event::Create("/dir/sub/new.txt".to_string()));
+        assert_eq!(next_event(&mut client).await, Event::Modify("/dir/sub/new.txt".to_string()));
+        std::fs::rename(home.join("dir/sub/new.txt"), home.join("dir/renamed.txt")).unwrap();
+        let rename = Event::Rename { from: "/dir/sub/new.txt".to_string(), to: "/dir/renamed.txt".to_string() };
+        while next_event(&mut client).await != rename {}
+        std::fs::rename(home.join("dir/renamed.txt"), home.join("outside.txt")).unwrap();
+        while next_event(&mut client).await != Event::Delete("/dir/renamed.txt".to_string()) {}
+
+        client.write_all(&[UNWATCH]).await.unwrap();
+        while next_event(&mut client).await != Event::End {}
+        assert_eq!(send(&mut client, &request(STAT, &["outside.txt"], None), true).await.0, RC_OK);
+        assert_eq!(send(&mut client, &request(WATCH, &["missing"], None), false).await, (RC_ERROR, None));
+        std::fs::remove_dir_all(home.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn watch_over_the_cap_should_be_refused() {
+        let parser = test_parser("[limits]\nmax_watches = 1");
+        let mut first = logged_client(std::sync::Arc::clone(&parser)).await;
+        let mut second = logged_client(parser).await;
+
+        assert_eq!(send(&mut first, &request(WATCH, &["/dir_1"], None), false).await, (RC_OK, None));
+        assert_eq!(send(&mut second, &request(WATCH, &["/dir_2"], None), false).await, (RC_SERVER_BUSY, None));
+        first.write_all(&[UNWATCH]).await.unwrap();
+        while next_event(&mut first).await != Event::End {}
+        assert_eq!(send(&mut second, &request(WATCH, &["/dir_2"], None), false).await, (RC_OK, None));
+    }
+
+    /// Read the results of a SEARCH until their end.
+    async fn search_results(client: &mut tokio::io::DuplexStream) -> Vec<(String, bool)> {
+        let mut results = Vec::new();
+        loop {
+            let len = client.read_u16().await.unwrap() as usize;
+            if len == 0 {
+                return results;
+            }
+            let mut path = vec![0u8; len];
+            client.read_exact(&mut path).await.unwrap();
+            let mut stat = [0u8; stat::STAT_LEN];
+            client.read_exact(&mut stat).await.unwrap();
+            results.push((String::from_utf8(path).unwrap(), stat::Stat::decode(&stat).unwrap().is_dir));
+        }
+    }
+
+    #[tokio::test]
+    async fn search_should_return_the_visible_matches() {
+        let mut client = logged_client(test_parser(r#"
+            [[acl]]
+            path = "/"
+            users = ["alice"]
+            allow = ["list"]
+
+            [[acl]]
+            path = "/dir_1/dir_5"
+            users = ["alice"]
+            allow = []
+        "#)).await;
+        let glob = |glob: &str| search::Query::new(search::Pattern::Glob(glob.to_string())).encode();
+
+        assert_eq!(send(&mut client, &request(SEARCH, &["/dir_1"], Some(&glob("file_*.txt"))), false).await, (RC_OK, None));
+        let mut results = search_results(&mut client).await;
+        results.sort();
+        assert_eq!(results, vec![("/dir_1/file_1.txt".to_string(), false), ("/dir_1/file_3.txt".to_string(), false)]);
+
+        let regex = search::Query::new(search::Pattern::Regex("^/dir_1/dir_5$".to_string())).encode();
+        assert_eq!(send(&mut client, &request(SEARCH, &["/"], Some(&regex)), false).await, (RC_OK, None));
+        assert!(search_results(&mut client).await.is_empty());
+
+        assert_eq!(send(&mut client, &request(SEARCH, &["/dir_1"], Some(&glob("["))), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(SEARCH, &["/dir_1/file_1.txt"], Some(&glob("*"))), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(SEARCH, &["/dir_1"], Some(b"bad")), false).await, (RC_ERROR, None));
+    }
+
+    #[tokio::test]
+    async fn du_should_return_the_usage_of_each_path() {
+        let mut client = logged_client(test_parser(r#"
+            [[acl]]
+            path = "/"
+            users = ["alice"]
+            allow = ["list"]
+
+            [[acl]]
+            path = "/dir_1/dir_5"
+            users = ["alice"]
+            allow = []
+
+            [[acl]]
+            path = "/dir_2"
+            users = ["alice"]
+            allow = []
+        "#)).await;
+
+        let (response_code, payload) = send(&mut client, &request(DU, &["/dir_1", "/file.txt"], Some(&[du::CHILDREN])), true).await;
+        assert_eq!(response_code, RC_OK);
+        let usages = du::decode_usages(&payload.unwrap()).unwrap();
+        assert_eq!(usages[0].total, du::Usage { size: 9, files: 2 });
+        assert_eq!(usages[0].children.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["file_1.txt", "file_3.txt"]);
+        assert_eq!(usages[1], du::PathUsage { total: du::Usage { size: 4, files: 1 }, children: vec![] });
+
+        let (response_code, payload) = send(&mut client, &request(DU, &["/"], Some(&[du::TOTALS])), true).await;
+        assert_eq!(response_code, RC_OK);
+        assert_eq!(du::decode_usages(&payload.unwrap()).unwrap(), vec![du::PathUsage { total: du::Usage { size: 13, files: 3 }, children: vec![] }]);
+
+        assert_eq!(send(&mut client, &request(DU, &["/dir_1", "/dir_2"], Some(&[du::TOTALS])), false).await, (RC_PERMISSION_DENIED, None));
+        assert_eq!(send(&mut client, &request(DU, &["/missing"], Some(&[du::TOTALS])), false).await, (RC_ERROR, None));
+        assert_eq!(send(&mut client, &request(DU, &["/dir_1"], Some(&[2])), false).await, (RC_ERROR, None));
+    }
+
+    #[tokio::test]
+    async fn space_should_return_the_capacity_of_the_main_path() {
+        let mut client = logged_client(test_parser("")).await;
+
+        let (response_code, payload) = send(&mut client, &request(SPACE, &[], None), true).await;
+        assert_eq!(response_code, RC_OK);
+        let space = space::Space::decode(&payload.unwrap()).unwrap();
+        assert!(space.total > 0 && space.available <= space.total);
+        assert_eq!((space.remaining_bytes, space.remaining_files), (None, None));
+    }
 }
\ No newline at end of file
@@ -1,14 +1,13 @@
 use std::path::PathBuf;
-
 use async_trait::async_trait;
-use tokio::net::TcpStream;
+use super::{parser::Parser, session::Session, Stream};
 
 /// Trait to represent all the versions of the nFTP protocol. 
 pub trait Version: Sync + Send  {
 
     /// Continue the parsing of a specific version of the protocol.
     fn parse(&self,
-        input_bytes: &Vec<u8>,
+        input_bytes: &[u8],
         total_len: &usize, 
         acc_len: &mut usize, 
         index: &mut usize) -> Option<Box<dyn Istruction>>;
@@ -26,9 +25,25 @@ pub trait Version: Sync + Send  {
 /// Trait to represent all the istructions and their actions.
 #[async_trait]
 pub trait Istruction: Sync + Send  {
-    /// Execute the istruction
-    async fn execute(&self, socket: &mut TcpStream, bytes: &Vec<u8>, main_path: &PathBuf) -> bool;
+    /// Execute the istruction.
+    /// Return the response code to send back to the client in case of error.
+    async fn execute(&self, socket: &mut dyn Stream, bytes: &[u8], parser: &Parser, session: &mut Session) -> Result<(), u8>;
 
     /// Return the istruction code
     fn get_istruction_code(&self) -> u8;
-}
\ No newline at end of file
+
+    /// Return the paths of the user space the istruction works on, for the logs.
+    fn get_paths(&self) -> &[PathBuf] {
+        &[]
+    }
+
+    /// Return the username given by the istruction, for the logs.
+    fn get_username(&self) -> Option<&str> {
+        None
+    }
+
+    /// Return true if the istruction can be executed only by an authenticated session.
+    fn requires_authentication(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,190 @@
+use std::{path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+use notify::{
+    event::{EventKind, ModifyKind, RenameMode},
+    RecommendedWatcher, RecursiveMode, Watcher
+};
+use tokio::sync::mpsc;
+use protocol::watch::Event;
+use super::user_space::UserSpace;
+
+/// Changes queued for a client before they are reported as an overflow
+const QUEUE_LEN: usize = 1024;
+
+/// Time to wait for the second half of a rename before reporting a deletion:
+/// the file was moved outside the watched path.
+pub const RENAME_WAIT: Duration = Duration::from_millis(50);
+
+
+/// The changes of the filesystem under a path, watched through inotify while
+/// the subscription is alive.
+pub struct Subscription {
+    changes: mpsc::Receiver<notify::Result<notify::Event>>,
+    overflowed: Arc<AtomicBool>,
+    _watcher: RecommendedWatcher
+}
+impl Subscription {
+    /// Start watching the path and, for a directory, everything under it.
+    pub fn new(path: &Path) -> notify::Result<Self> {
+        let (sender, changes) = mpsc::channel(QUEUE_LEN);
+        let overflowed = Arc::new(AtomicBool::new(false));
+        let full = Arc::clone(&overflowed);
+        let mut watcher = notify::recommended_watcher(move |change| {
+            if let Err(mpsc::error::TrySendError::Full(_)) = sender.try_send(change) {
+                full.store(true, Ordering::Relaxed);
+            }
+        })?;
+        watcher.watch(path, RecursiveMode::Recursive)?;
+        Ok(Subscription { changes, overflowed, _watcher: watcher })
+    }
+
+    /// Wait for the next change.
+    pub async fn recv(&mut self) -> notify::Result<notify::Event> {
+        self.changes.recv().await.unwrap_or_else(|| Err(notify::Error::generic("the watcher stopped")))
+    }
+
+    /// Return true if some changes were lost since the last call, because the
+    /// client didn't keep up.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed.swap(false, Ordering::Relaxed)
+    }
+}
+
+
+/// Turns the changes of the filesystem into the events of a user: the real paths
+/// become paths of its space, and the two halves of a rename become one event.
+pub struct EventMapper<'a> {
+    user_space: &'a UserSpace,
+    /// First half of a rename, with its cookie
+    pending: Option<(usize, PathBuf)>
+}
+impl<'a> EventMapper<'a> {
+    pub fn new(user_space: &'a UserSpace) -> Self {
+        EventMapper { user_space, pending: None }
+    }
+
+    /// Return true if a rename is waiting for its second half.
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Report a rename still waiting for its second half as a deletion.
+    pub fn flush(&mut self) -> Vec<Event> {
+        self.pending.take().and_then(|(_, from)| self.user_path(&from)).map(Event::Delete).into_iter().collect()
+    }
+
+    /// Return the events of a change. A change of a rename can be held until the
+    /// next one, see `flush`.
+    pub fn map(&mut self, change: notify::Event) -> Vec<Event> {
+        let tracker = change.tracker();
+        let Some(path) = change.paths.into_iter().next() else { return Vec::new() };
+
+        if let EventKind::Modify(ModifyKind::Name(mode)) = change.kind {
+            match (mode, tracker) {
+                (RenameMode::From, Some(tracker)) => {
+                    let events = self.flush();
+                    self.pending = Some((tracker, path));
+                    return events;
+                },
+                (RenameMode::To, Some(tracker)) if self.pending.as_ref().is_some_and(|(cookie, _)| *cookie == tracker) => {
+                    let from = self.pending.take().and_then(|(_, from)| self.user_path(&from));
+                    return match (from, self.user_path(&path)) {
+                        (Some(from), Some(to)) => vec![Event::Rename { from, to }],
+                        (None, Some(to)) => vec![Event::Create(to)],
+                        (Some(from), None) => vec![Event::Delete(from)],
+                        (None, None) => Vec::new()
+                    };
+                },
+                // already reported through its two halves
+                (RenameMode::Both, _) => return Vec::new(),
+                _ => ()
+            };
+        }
+
+        let mut events = self.flush();
+        let Some(user_path) = self.user_path(&path) else { return events };
+        let event = match change.kind {
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => Event::Create(user_path),
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => Event::Delete(user_path),
+            EventKind::Modify(_) => Event::Modify(user_path),
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => return events
+        };
+        events.push(event);
+        events
+    }
+
+    fn user_path(&self, real_path: &Path) -> Option<String> {
+        self.user_space.user_path(real_path)
+    }
+}
+
+
+/// Return the event as seen by a user allowed to see only some paths: a rename
+/// from or to a hidden path becomes a creation or a deletion.
+pub fn visible<F: Fn(&str) -> bool>(event: Event, allowed: F) -> Option<Event> {
+    match event {
+        Event::Create(ref path) | Event::Modify(ref path) | Event::Delete(ref path) => allowed(path).then_some(event),
+        Event::Rename { from, to } => match (allowed(&from), allowed(&to)) {
+            (true, true) => Some(Event::Rename { from, to }),
+            (true, false) => Some(Event::Delete(from)),
+            (false, true) => Some(Event::Create(to)),
+            (false, false) => None
+        },
+        Event::Overflow | Event::End => Some(event)
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+    use super::*;
+
+    fn change(kind: EventKind, path: &str, tracker: Option<usize>) -> notify::Event {
+        let change = notify::Event::new(kind).add_path(PathBuf::from(path));
+        match tracker {
+            Some(tracker) => change.set_tracker(tracker),
+            None => change
+        }
+    }
+
+    #[test]
+    fn map_should_return_the_paths_of_the_user_space() {
+        let space = UserSpace::Home(PathBuf::from("/srv/alice"));
+        let mut mapper = EventMapper::new(&space);
+
+        assert_eq!(mapper.map(change(EventKind::Create(CreateKind::File), "/srv/alice/a.txt", None)), vec![Event::Create("/a.txt".to_string())]);
+        assert_eq!(mapper.map(change(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/srv/alice/a.txt", None)), vec![Event::Modify("/a.txt".to_string())]);
+        assert_eq!(mapper.map(change(EventKind::Remove(RemoveKind::File), "/srv/alice/a.txt", None)), vec![Event::Delete("/a.txt".to_string())]);
+        assert!(mapper.map(change(EventKind::Create(CreateKind::File), "/srv/bob/a.txt", None)).is_empty());
+    }
+
+    #[test]
+    fn map_should_pair_the_halves_of_a_rename() {
+        let space = UserSpace::Home(PathBuf::from("/srv/alice"));
+        let mut mapper = EventMapper::new(&space);
+        let rename = |mode| EventKind::Modify(ModifyKind::Name(mode));
+
+        assert!(mapper.map(change(rename(RenameMode::From), "/srv/alice/a.txt", Some(7))).is_empty());
+        assert!(mapper.has_pending());
+        assert_eq!(mapper.map(change(rename(RenameMode::To), "/srv/alice/b.txt", Some(7))), vec![Event::Rename { from: "/a.txt".to_string(), to: "/b.txt".to_string() }]);
+        assert!(mapper.map(change(rename(RenameMode::Both), "/srv/alice/a.txt", Some(7))).is_empty());
+
+        // moved outside, then inside from outside
+        assert!(mapper.map(change(rename(RenameMode::From), "/srv/alice/b.txt", Some(8))).is_empty());
+        assert_eq!(mapper.flush(), vec![Event::Delete("/b.txt".to_string())]);
+        assert_eq!(mapper.map(change(rename(RenameMode::To), "/srv/alice/c.txt", Some(9))), vec![Event::Create("/c.txt".to_string())]);
+        assert!(!mapper.has_pending());
+    }
+
+    #[test]
+    fn visible_should_hide_the_forbidden_paths() {
+        let allowed = |path: &str| path.starts_with("/public");
+        let rename = |from: &str, to: &str| Event::Rename { from: from.to_string(), to: to.to_string() };
+
+        assert_eq!(visible(Event::Create("/private/a".to_string()), allowed), None);
+        assert_eq!(visible(rename("/public/a", "/public/b"), allowed), Some(rename("/public/a", "/public/b")));
+        assert_eq!(visible(rename("/public/a", "/private/a"), allowed), Some(Event::Delete("/public/a".to_string())));
+        assert_eq!(visible(rename("/private/a", "/public/a"), allowed), Some(Event::Create("/public/a".to_string())));
+        assert_eq!(visible(Event::Overflow, allowed), Some(Event::Overflow));
+    }
+}